@@ -0,0 +1,59 @@
+-- Flights, bookings and their passengers
+CREATE TABLE flights (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    origin TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    departure_time TEXT NOT NULL,
+    arrival_time TEXT NOT NULL,
+    price REAL NOT NULL,
+    aircraft_code TEXT NOT NULL DEFAULT 'A320'
+);
+
+CREATE TABLE bookings (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    flight_id INTEGER NOT NULL,
+    cabin TEXT NOT NULL DEFAULT 'ECONOMY',
+    payment_token TEXT NOT NULL,
+    card_brand TEXT NOT NULL,
+    card_last4 TEXT NOT NULL,
+    payment_reference TEXT NOT NULL,
+    payment_status TEXT NOT NULL,
+    total_price REAL NOT NULL,
+    booking_time TEXT NOT NULL
+);
+
+CREATE TABLE booking_segments (
+    booking_id INTEGER NOT NULL,
+    segment_index INTEGER NOT NULL,
+    flight_id INTEGER NOT NULL,
+    PRIMARY KEY (booking_id, segment_index)
+);
+
+CREATE TABLE passengers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    booking_id INTEGER NOT NULL,
+    passenger_index INTEGER NOT NULL,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    date_of_birth TEXT,
+    passenger_type TEXT NOT NULL,
+    email TEXT,
+    phone TEXT,
+    loyalty_number TEXT,
+    document_type TEXT,
+    document_number TEXT,
+    document_issuing_country TEXT,
+    document_expiry TEXT,
+    UNIQUE (booking_id, passenger_index)
+);
+
+CREATE TABLE bot_intents (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    agent_type TEXT NOT NULL,
+    confidence REAL NOT NULL,
+    intent_type TEXT NOT NULL,
+    query_params TEXT,
+    reason TEXT,
+    additional_context TEXT,
+    recorded_time TEXT NOT NULL
+);
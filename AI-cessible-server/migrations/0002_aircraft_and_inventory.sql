@@ -0,0 +1,60 @@
+-- Aircraft configurations used to build seat maps and inventory
+CREATE TABLE aircraft_configs (
+    code TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    has_wifi INTEGER NOT NULL
+);
+
+CREATE TABLE aircraft_cabins (
+    aircraft_code TEXT NOT NULL,
+    cabin TEXT NOT NULL,
+    first_row INTEGER NOT NULL,
+    last_row INTEGER NOT NULL,
+    seat_letters TEXT NOT NULL,
+    pitch_inches REAL NOT NULL,
+    width_inches REAL NOT NULL,
+    recline_degrees REAL NOT NULL,
+    has_power INTEGER NOT NULL,
+    exit_rows TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (aircraft_code, cabin)
+);
+
+INSERT INTO aircraft_configs (code, name, has_wifi) VALUES
+    ('A320', 'Airbus A320', 1),
+    ('E175', 'Embraer 175', 0);
+
+INSERT INTO aircraft_cabins (aircraft_code, cabin, first_row, last_row, seat_letters, pitch_inches, width_inches, recline_degrees, has_power, exit_rows) VALUES
+    ('A320', 'BUSINESS', 1, 3, 'ACDF', 38.0, 21.0, 15.0, 1, ''),
+    ('A320', 'PREMIUM_ECONOMY', 4, 7, 'ABCDEF', 34.0, 18.5, 7.0, 1, ''),
+    ('A320', 'ECONOMY', 8, 27, 'ABCDEF', 31.0, 17.5, 4.0, 0, '12,13'),
+    ('E175', 'BUSINESS', 1, 3, 'ACD', 37.0, 20.5, 12.0, 1, ''),
+    ('E175', 'PREMIUM_ECONOMY', 4, 6, 'ABCD', 34.0, 18.3, 6.0, 1, ''),
+    ('E175', 'ECONOMY', 7, 19, 'ABCD', 31.0, 18.3, 4.0, 1, '10');
+
+-- Per-cabin seat inventory, created for every new flight by trigger
+CREATE TABLE seat_inventory (
+    flight_id INTEGER NOT NULL,
+    cabin TEXT NOT NULL,
+    capacity INTEGER NOT NULL,
+    seats_sold INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (flight_id, cabin),
+    CHECK (seats_sold <= capacity)
+);
+
+CREATE TRIGGER flights_default_inventory AFTER INSERT ON flights
+BEGIN
+    INSERT INTO seat_inventory (flight_id, cabin, capacity)
+    SELECT NEW.id, cabin, (last_row - first_row + 1) * length(seat_letters)
+    FROM aircraft_cabins WHERE aircraft_code = NEW.aircraft_code;
+END;
+
+-- Seat holds and assignments
+CREATE TABLE seat_assignments (
+    flight_id INTEGER NOT NULL,
+    seat TEXT NOT NULL,
+    status TEXT NOT NULL,
+    booking_id INTEGER,
+    hold_token TEXT,
+    held_until TEXT,
+    PRIMARY KEY (flight_id, seat)
+);
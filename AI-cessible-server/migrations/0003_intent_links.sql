@@ -0,0 +1,6 @@
+-- Let bots tie an intent to the search, offer or booking it concerns
+ALTER TABLE bot_intents ADD COLUMN search_id TEXT;
+ALTER TABLE bot_intents ADD COLUMN offer_id TEXT;
+ALTER TABLE bot_intents ADD COLUMN booking_id INTEGER REFERENCES bookings(id);
+
+CREATE INDEX bot_intents_booking ON bot_intents (booking_id);
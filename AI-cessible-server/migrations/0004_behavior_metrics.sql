@@ -0,0 +1,24 @@
+-- Client-side behavior reports (frontend botDetection.js), one row per report
+CREATE TABLE behavior_metrics (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id TEXT NOT NULL,
+    client_confidence REAL,
+    mouse_entropy REAL,
+    typing_pattern REAL,
+    navigation_pattern REAL,
+    form_filling REAL,
+    form_fill_time_ms REAL,
+    mouse_movements INTEGER NOT NULL DEFAULT 0,
+    key_presses INTEGER NOT NULL DEFAULT 0,
+    clicks INTEGER NOT NULL DEFAULT 0,
+    scrolls INTEGER NOT NULL DEFAULT 0,
+    form_interactions INTEGER NOT NULL DEFAULT 0,
+    user_agent TEXT,
+    client_timestamp TEXT,
+    server_confidence REAL NOT NULL,
+    -- Full report as sent, including fields without a column
+    payload TEXT NOT NULL,
+    recorded_time TEXT NOT NULL
+);
+
+CREATE INDEX behavior_metrics_session ON behavior_metrics (session_id, recorded_time);
@@ -0,0 +1,4 @@
+-- Session an intent was submitted from, tying it to the session's searches and bookings
+ALTER TABLE bot_intents ADD COLUMN session_id TEXT;
+
+CREATE INDEX bot_intents_session ON bot_intents (session_id);
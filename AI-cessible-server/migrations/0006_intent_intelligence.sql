@@ -0,0 +1,2 @@
+-- Intelligence level (L0/L1/L2) of the session an intent came from
+ALTER TABLE bot_intents ADD COLUMN intelligence_level TEXT;
@@ -0,0 +1,7 @@
+-- Registry of agents that sign their requests with an Ed25519 key
+CREATE TABLE agents (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    public_key TEXT NOT NULL UNIQUE,
+    created_time TEXT NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Scoped API keys issued to registered agents; only a hash of each key is stored
+CREATE TABLE api_keys (
+    id TEXT PRIMARY KEY,
+    agent_id TEXT NOT NULL REFERENCES agents (id),
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    label TEXT,
+    created_time TEXT NOT NULL,
+    revoked_time TEXT
+);
+
+CREATE INDEX api_keys_agent ON api_keys (agent_id);
+
+-- Agent an intent was submitted by, so agents only list their own intents
+ALTER TABLE bot_intents ADD COLUMN agent_id TEXT;
+
+CREATE INDEX bot_intents_agent ON bot_intents (agent_id);
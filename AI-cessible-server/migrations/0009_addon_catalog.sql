@@ -0,0 +1,49 @@
+-- Add-ons sold with an offer. `unit` is 'segment' (priced per flight) or
+-- 'booking' (once per offer); `cabins` lists the eligible cabins, empty for all.
+CREATE TABLE addon_catalog (
+    code TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    unit TEXT NOT NULL CHECK (unit IN ('segment', 'booking')),
+    max_per_passenger INTEGER NOT NULL,
+    cabins TEXT NOT NULL DEFAULT '',
+    requires_wifi INTEGER NOT NULL DEFAULT 0
+);
+
+-- Add-on prices; NULL origin, destination or cabin match any. A cabin price
+-- wins over a route price, and the most specific route wins after that
+CREATE TABLE addon_prices (
+    addon_code TEXT NOT NULL REFERENCES addon_catalog (code),
+    origin TEXT,
+    destination TEXT,
+    cabin TEXT,
+    price REAL NOT NULL CHECK (price >= 0)
+);
+
+CREATE INDEX addon_prices_code ON addon_prices (addon_code);
+
+INSERT INTO addon_catalog (code, name, description, unit, max_per_passenger, cabins, requires_wifi) VALUES
+    ('CHECKED_BAG', 'Checked bag', 'One checked bag up to 23 kg', 'segment', 3, '', 0),
+    ('EXTRA_LEGROOM_SEAT', 'Extra legroom seat', 'Seat with extra pitch in an exit or bulkhead row', 'segment', 1, 'ECONOMY', 0),
+    ('MEAL', 'Meal', 'Hot meal served on board', 'segment', 2, 'ECONOMY,PREMIUM_ECONOMY', 0),
+    ('TRAVEL_INSURANCE', 'Travel insurance', 'Cancellation and baggage cover for the whole trip', 'booking', 1, '', 0),
+    ('PRIORITY_BOARDING', 'Priority boarding', 'Board in the first group', 'segment', 1, 'ECONOMY,PREMIUM_ECONOMY', 0),
+    ('WIFI', 'Wi-Fi pass', 'Internet access for the whole flight', 'segment', 1, '', 1);
+
+INSERT INTO addon_prices (addon_code, origin, destination, cabin, price) VALUES
+    ('CHECKED_BAG', NULL, NULL, NULL, 35.0),
+    ('CHECKED_BAG', NULL, NULL, 'PREMIUM_ECONOMY', 25.0),
+    ('CHECKED_BAG', NULL, NULL, 'BUSINESS', 0.0),
+    ('CHECKED_BAG', 'NYC', 'LAX', NULL, 45.0),
+    ('CHECKED_BAG', 'LAX', 'NYC', NULL, 45.0),
+    ('EXTRA_LEGROOM_SEAT', NULL, NULL, NULL, 29.0),
+    ('EXTRA_LEGROOM_SEAT', 'NYC', 'LAX', NULL, 49.0),
+    ('EXTRA_LEGROOM_SEAT', 'LAX', 'NYC', NULL, 49.0),
+    ('MEAL', NULL, NULL, NULL, 12.0),
+    ('MEAL', NULL, NULL, 'PREMIUM_ECONOMY', 8.0),
+    ('TRAVEL_INSURANCE', NULL, NULL, NULL, 24.0),
+    ('TRAVEL_INSURANCE', NULL, NULL, 'BUSINESS', 39.0),
+    ('PRIORITY_BOARDING', NULL, NULL, NULL, 15.0),
+    ('PRIORITY_BOARDING', NULL, NULL, 'PREMIUM_ECONOMY', 9.0),
+    ('WIFI', NULL, NULL, NULL, 8.0),
+    ('WIFI', NULL, NULL, 'BUSINESS', 0.0);
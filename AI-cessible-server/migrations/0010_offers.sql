@@ -0,0 +1,17 @@
+-- Offers quoted by buildOffer, bookable until they expire. `flight_ids` lists
+-- the flights in travel order; `line_items` is the itemized quote as JSON.
+CREATE TABLE offers (
+    id TEXT PRIMARY KEY,
+    flight_ids TEXT NOT NULL,
+    cabin TEXT NOT NULL,
+    passengers INTEGER NOT NULL,
+    line_items TEXT NOT NULL,
+    total_price REAL NOT NULL,
+    created_time TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    booking_id INTEGER
+);
+
+-- What a booking was charged for, and the offer it was booked from
+ALTER TABLE bookings ADD COLUMN offer_id TEXT;
+ALTER TABLE bookings ADD COLUMN line_items TEXT;
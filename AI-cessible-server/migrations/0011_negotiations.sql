@@ -0,0 +1,32 @@
+-- Price negotiations with agents. Prices are per seated passenger; the floor
+-- is never revealed to the agent.
+CREATE TABLE negotiations (
+    id TEXT PRIMARY KEY,
+    agent_id TEXT,
+    flight_id INTEGER NOT NULL,
+    cabin TEXT NOT NULL,
+    passengers INTEGER NOT NULL,
+    rule TEXT NOT NULL,
+    list_price REAL NOT NULL,
+    floor_price REAL NOT NULL,
+    asking_price REAL NOT NULL,
+    agreed_price REAL,
+    status TEXT NOT NULL,
+    offer_id TEXT,
+    created_time TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
+-- Every counter-offer of a negotiation and the answer it got
+CREATE TABLE negotiation_rounds (
+    negotiation_id TEXT NOT NULL,
+    round INTEGER NOT NULL,
+    proposed_price REAL NOT NULL,
+    asking_price REAL NOT NULL,
+    accepted INTEGER NOT NULL,
+    recorded_time TEXT NOT NULL,
+    PRIMARY KEY (negotiation_id, round)
+);
+
+-- Offers created by an agreed negotiation keep the agreed fare when booked
+ALTER TABLE offers ADD COLUMN negotiation_id TEXT;
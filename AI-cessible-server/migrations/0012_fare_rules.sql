@@ -0,0 +1,82 @@
+-- Fare rules behind offer explanations: a fare family per cabin with its
+-- refund and change penalties, baggage allowances per cabin and the taxes
+-- included in every fare
+
+CREATE TABLE fare_families (
+    code TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    cabin TEXT NOT NULL UNIQUE,
+    meal_included INTEGER NOT NULL,
+    -- Loyalty points earned per unit of base fare
+    loyalty_rate REAL NOT NULL
+);
+
+-- Refund of the fare when cancelling at least `min_hours_before_departure`
+-- ahead; the row with the largest threshold that is met applies
+CREATE TABLE fare_refund_rules (
+    fare_family TEXT NOT NULL REFERENCES fare_families (code),
+    min_hours_before_departure INTEGER NOT NULL,
+    refund_percent REAL NOT NULL CHECK (refund_percent BETWEEN 0 AND 100),
+    PRIMARY KEY (fare_family, min_hours_before_departure)
+);
+
+-- Fee for changing the flight, chosen the same way; NULL means no changes
+CREATE TABLE fare_change_rules (
+    fare_family TEXT NOT NULL REFERENCES fare_families (code),
+    min_hours_before_departure INTEGER NOT NULL,
+    fee REAL CHECK (fee >= 0),
+    PRIMARY KEY (fare_family, min_hours_before_departure)
+);
+
+CREATE TABLE baggage_allowances (
+    cabin TEXT PRIMARY KEY,
+    carry_on INTEGER NOT NULL,
+    checked INTEGER NOT NULL,
+    weight_limit_kg INTEGER NOT NULL
+);
+
+-- Taxes and fees included in fares. Rows with an airport apply to flights
+-- departing from (`applies_at` 'origin') or arriving at it; rows without one
+-- apply to every flight. Each charges a fixed amount plus a percentage of the
+-- base fare.
+CREATE TABLE tax_components (
+    id TEXT PRIMARY KEY,
+    code TEXT NOT NULL,
+    name TEXT NOT NULL,
+    airport TEXT,
+    applies_at TEXT NOT NULL DEFAULT 'origin' CHECK (applies_at IN ('origin', 'destination')),
+    amount REAL NOT NULL DEFAULT 0,
+    percent REAL NOT NULL DEFAULT 0
+);
+
+INSERT INTO fare_families (code, name, cabin, meal_included, loyalty_rate) VALUES
+    ('ECONOMY_STANDARD', 'Economy Standard', 'ECONOMY', 0, 1.0),
+    ('PREMIUM_FLEX', 'Premium Flex', 'PREMIUM_ECONOMY', 1, 1.5),
+    ('BUSINESS_FLEX', 'Business Flex', 'BUSINESS', 1, 2.0);
+
+INSERT INTO fare_refund_rules (fare_family, min_hours_before_departure, refund_percent) VALUES
+    ('ECONOMY_STANDARD', 24, 70),
+    ('ECONOMY_STANDARD', 0, 0),
+    ('PREMIUM_FLEX', 24, 90),
+    ('PREMIUM_FLEX', 0, 50),
+    ('BUSINESS_FLEX', 0, 100);
+
+INSERT INTO fare_change_rules (fare_family, min_hours_before_departure, fee) VALUES
+    ('ECONOMY_STANDARD', 24, 75),
+    ('ECONOMY_STANDARD', 0, NULL),
+    ('PREMIUM_FLEX', 0, 25),
+    ('BUSINESS_FLEX', 0, 0);
+
+INSERT INTO baggage_allowances (cabin, carry_on, checked, weight_limit_kg) VALUES
+    ('ECONOMY', 1, 1, 23),
+    ('PREMIUM_ECONOMY', 1, 2, 23),
+    ('BUSINESS', 2, 2, 32);
+
+INSERT INTO tax_components (id, code, name, airport, applies_at, amount, percent) VALUES
+    ('US-TRANSPORTATION', 'US', 'US transportation tax', NULL, 'origin', 0, 7.5),
+    ('US-SEGMENT', 'ZP', 'US flight segment tax', NULL, 'origin', 5.0, 0),
+    ('US-SECURITY', 'AY', 'September 11th security fee', NULL, 'origin', 5.6, 0),
+    ('NYC-PFC', 'XF', 'Passenger facility charge, New York', 'NYC', 'origin', 4.5, 0),
+    ('LAX-PFC', 'XF', 'Passenger facility charge, Los Angeles', 'LAX', 'origin', 4.5, 0),
+    ('SFO-PFC', 'XF', 'Passenger facility charge, San Francisco', 'SFO', 'origin', 4.5, 0),
+    ('SEA-PFC', 'XF', 'Passenger facility charge, Seattle', 'SEA', 'origin', 4.5, 0);
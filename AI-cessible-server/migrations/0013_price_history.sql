@@ -0,0 +1,27 @@
+-- Prices seen for each flight: when it was listed, whenever its price changes
+-- and when it is returned by a search
+CREATE TABLE price_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    flight_id INTEGER NOT NULL,
+    price REAL NOT NULL,
+    source TEXT NOT NULL CHECK (source IN ('listed', 'change', 'search')),
+    recorded_time TEXT NOT NULL
+);
+
+CREATE INDEX price_history_flight ON price_history (flight_id, recorded_time);
+
+CREATE TRIGGER flights_price_listed AFTER INSERT ON flights
+BEGIN
+    INSERT INTO price_history (flight_id, price, source, recorded_time)
+    VALUES (NEW.id, NEW.price, 'listed', datetime('now'));
+END;
+
+CREATE TRIGGER flights_price_change AFTER UPDATE OF price ON flights
+WHEN NEW.price <> OLD.price
+BEGIN
+    INSERT INTO price_history (flight_id, price, source, recorded_time)
+    VALUES (NEW.id, NEW.price, 'change', datetime('now'));
+END;
+
+INSERT INTO price_history (flight_id, price, source, recorded_time)
+SELECT id, price, 'listed', datetime('now') FROM flights;
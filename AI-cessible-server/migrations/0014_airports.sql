@@ -0,0 +1,20 @@
+-- Airports flights are scheduled between. Flight times are stored as RFC 3339
+-- timestamps with the UTC offset of the airport's zone on that date.
+CREATE TABLE airports (
+    code TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    -- IANA time zone, e.g. America/New_York
+    time_zone TEXT NOT NULL
+);
+
+INSERT INTO airports (code, name, latitude, longitude, time_zone) VALUES
+    ('NYC', 'New York John F. Kennedy International', 40.6413, -73.7781, 'America/New_York'),
+    ('BOS', 'Boston Logan International', 42.3656, -71.0096, 'America/New_York'),
+    ('ATL', 'Atlanta Hartsfield-Jackson International', 33.6407, -84.4277, 'America/New_York'),
+    ('ORD', 'Chicago O''Hare International', 41.9742, -87.9073, 'America/Chicago'),
+    ('DEN', 'Denver International', 39.8561, -104.6737, 'America/Denver'),
+    ('LAX', 'Los Angeles International', 33.9416, -118.4085, 'America/Los_Angeles'),
+    ('SFO', 'San Francisco International', 37.6213, -122.3790, 'America/Los_Angeles'),
+    ('SEA', 'Seattle-Tacoma International', 47.4502, -122.3088, 'America/Los_Angeles');
@@ -0,0 +1,16 @@
+-- Payment methods and authorizations of the mock processor, so the tokens
+-- stored on bookings stay valid across restarts. Card numbers are never stored.
+CREATE TABLE payment_methods (
+    token TEXT PRIMARY KEY,
+    brand TEXT NOT NULL,
+    last4 TEXT NOT NULL,
+    created_time TEXT NOT NULL
+);
+
+CREATE TABLE payment_authorizations (
+    id TEXT PRIMARY KEY,
+    token TEXT NOT NULL REFERENCES payment_methods (token),
+    amount REAL NOT NULL,
+    state TEXT NOT NULL CHECK (state IN ('AUTHORIZED', 'CAPTURED', 'REFUNDED')),
+    created_time TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Scopes each agent may use when signing requests and issue keys for.
+-- read-behavior is left out and only granted by an operator.
+ALTER TABLE agents ADD COLUMN scopes TEXT NOT NULL DEFAULT 'search,explain,negotiate,book,read-intents';
@@ -0,0 +1,5 @@
+-- Session and agent a booking was made by, so getBooking only shows callers their own
+ALTER TABLE bookings ADD COLUMN session_id TEXT;
+ALTER TABLE bookings ADD COLUMN agent_id TEXT;
+
+CREATE INDEX bookings_agent ON bookings (agent_id);
@@ -0,0 +1,6 @@
+-- A flight's listed price is its economy fare; other cabins charge a
+-- multiple of it
+ALTER TABLE fare_families ADD COLUMN fare_multiplier REAL NOT NULL DEFAULT 1.0 CHECK (fare_multiplier > 0);
+
+UPDATE fare_families SET fare_multiplier = 1.5 WHERE cabin = 'PREMIUM_ECONOMY';
+UPDATE fare_families SET fare_multiplier = 2.5 WHERE cabin = 'BUSINESS';
@@ -0,0 +1,2 @@
+-- Unbooked offers are purged a day after they expire
+CREATE INDEX offers_unbooked_expiry ON offers (expires_at) WHERE booking_id IS NULL;
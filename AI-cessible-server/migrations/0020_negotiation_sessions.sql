@@ -0,0 +1,3 @@
+-- Session a negotiation was opened in, so negotiations without an agent are
+-- only visible to that session
+ALTER TABLE negotiations ADD COLUMN session_id TEXT;
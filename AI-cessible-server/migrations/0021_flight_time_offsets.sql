@@ -0,0 +1,23 @@
+-- Flight times must carry a UTC offset (or Z). Times stored before this are
+-- localized from their airports by db::migrate when it applies this migration.
+CREATE TRIGGER flights_time_offset_insert BEFORE INSERT ON flights
+WHEN NOT (
+    (NEW.departure_time GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9][+-][0-9][0-9]:[0-9][0-9]'
+        OR NEW.departure_time GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9]Z')
+    AND (NEW.arrival_time GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9][+-][0-9][0-9]:[0-9][0-9]'
+        OR NEW.arrival_time GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9]Z')
+)
+BEGIN
+    SELECT RAISE(ABORT, 'Flight times need a UTC offset, e.g. 2025-06-01T08:00:00-04:00');
+END;
+
+CREATE TRIGGER flights_time_offset_update BEFORE UPDATE OF departure_time, arrival_time ON flights
+WHEN NOT (
+    (NEW.departure_time GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9][+-][0-9][0-9]:[0-9][0-9]'
+        OR NEW.departure_time GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9]Z')
+    AND (NEW.arrival_time GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9][+-][0-9][0-9]:[0-9][0-9]'
+        OR NEW.arrival_time GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9]Z')
+)
+BEGIN
+    SELECT RAISE(ABORT, 'Flight times need a UTC offset, e.g. 2025-06-01T08:00:00-04:00');
+END;
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+
+use async_graphql::ErrorExtensions;
+use sqlx::SqlitePool;
+
+use crate::errors::ApiError;
+use crate::passengers::MAX_PASSENGERS;
+use crate::schema::{Addon, AddonSelection, AddonUnit, Cabin, FlightOffer, LineItemKind, OfferLineItem};
+use crate::{fare_rules, seatmap};
+
+#[derive(sqlx::FromRow)]
+struct CatalogRow {
+    code: String,
+    name: String,
+    description: String,
+    unit: String,
+    max_per_passenger: i64,
+    cabins: String,
+    requires_wifi: bool,
+}
+
+impl CatalogRow {
+    fn unit(&self) -> AddonUnit {
+        if self.unit == "booking" {
+            AddonUnit::Booking
+        } else {
+            AddonUnit::Segment
+        }
+    }
+
+    /// Eligible cabins; every cabin when none are listed
+    fn cabins(&self) -> Vec<Cabin> {
+        let listed: Vec<Cabin> = self.cabins.split(',').filter_map(|c| c.trim().parse().ok()).collect();
+        if listed.is_empty() {
+            vec![Cabin::Economy, Cabin::PremiumEconomy, Cabin::Business]
+        } else {
+            listed
+        }
+    }
+}
+
+impl From<CatalogRow> for Addon {
+    fn from(row: CatalogRow) -> Self {
+        Addon {
+            unit: row.unit(),
+            cabins: row.cabins(),
+            code: row.code,
+            name: row.name,
+            description: row.description,
+            max_per_passenger: row.max_per_passenger,
+            requires_wifi: row.requires_wifi,
+        }
+    }
+}
+
+const CATALOG_SELECT: &str =
+    "SELECT code, name, description, unit, max_per_passenger, cabins, requires_wifi FROM addon_catalog";
+
+/// Every add-on that can be sold
+pub async fn catalog(pool: &SqlitePool) -> async_graphql::Result<Vec<Addon>> {
+    let rows = sqlx::query_as::<_, CatalogRow>(&format!("{} ORDER BY code", CATALOG_SELECT))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(Addon::from).collect())
+}
+
+/// Price of an add-on for a route and cabin: cabin prices win over route prices, which win over the default
+async fn unit_price(
+    pool: &SqlitePool,
+    code: &str,
+    origin: &str,
+    destination: &str,
+    cabin: Cabin,
+) -> async_graphql::Result<f64> {
+    let price: Option<(f64,)> = sqlx::query_as(
+        "SELECT price FROM addon_prices WHERE addon_code = ?1 \
+         AND (origin IS NULL OR origin = ?2) AND (destination IS NULL OR destination = ?3) AND (cabin IS NULL OR cabin = ?4) \
+         ORDER BY (cabin IS NOT NULL) DESC, (origin IS NOT NULL) + (destination IS NOT NULL) DESC LIMIT 1",
+    )
+    .bind(code)
+    .bind(origin)
+    .bind(destination)
+    .bind(cabin.as_str())
+    .fetch_optional(pool)
+    .await?;
+    price
+        .map(|(price,)| price)
+        .ok_or_else(|| ApiError::AddonNotEligible {
+            code: code.to_string(),
+            flight_id: None,
+            reason: "it has no price".to_string(),
+        }
+        .extend())
+}
+
+pub fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+/// Line items for the cabin's fare on every segment and the selected add-ons,
+/// and their total. Segment add-ons apply to every flight unless a selection names one.
+pub async fn price_offer(
+    pool: &SqlitePool,
+    segments: &[FlightOffer],
+    cabin: Cabin,
+    passengers: i64,
+    selections: &[AddonSelection],
+) -> async_graphql::Result<(Vec<OfferLineItem>, f64)> {
+    if !(1..=MAX_PASSENGERS as i64).contains(&passengers) {
+        return Err(async_graphql::Error::new(format!("passengers must be between 1 and {}", MAX_PASSENGERS)));
+    }
+    let multiplier = fare_rules::cabin_fare_multiplier(pool, cabin).await?;
+    let mut items: Vec<OfferLineItem> = segments
+        .iter()
+        .map(|flight| {
+            let fare = round_cents(flight.price * multiplier);
+            OfferLineItem {
+                kind: LineItemKind::Fare,
+                code: None,
+                description: format!("Fare {} to {}", flight.origin, flight.destination),
+                flight_id: Some(flight.id),
+                quantity: passengers,
+                unit_price: fare,
+                amount: round_cents(fare * passengers as f64),
+            }
+        })
+        .collect();
+
+    // Quantities per add-on and segment position (None for whole-booking add-ons)
+    let mut quantities: BTreeMap<(usize, Option<usize>), i64> = BTreeMap::new();
+    let mut addons: Vec<CatalogRow> = Vec::new();
+    for selection in selections {
+        let code = selection.code.trim().to_ascii_uppercase();
+        if selection.quantity < 1 {
+            return Err(ApiError::InvalidAddonQuantity { code, quantity: selection.quantity, max: None }.extend());
+        }
+        let index = match addons.iter().position(|a| a.code == code) {
+            Some(index) => index,
+            None => {
+                let row = sqlx::query_as::<_, CatalogRow>(&format!("{} WHERE code = ?", CATALOG_SELECT))
+                    .bind(&code)
+                    .fetch_optional(pool)
+                    .await?
+                    .ok_or_else(|| ApiError::UnknownAddon(code.clone()).extend())?;
+                addons.push(row);
+                addons.len() - 1
+            }
+        };
+        let addon = &addons[index];
+        let positions: Vec<Option<usize>> = match (addon.unit(), selection.flight_id) {
+            (AddonUnit::Booking, Some(flight_id)) => {
+                return Err(ApiError::AddonNotEligible {
+                    code,
+                    flight_id: Some(flight_id),
+                    reason: "it covers the whole booking, not one flight".to_string(),
+                }
+                .extend())
+            }
+            (AddonUnit::Booking, None) => vec![None],
+            (AddonUnit::Segment, Some(flight_id)) => match segments.iter().position(|f| f.id == flight_id) {
+                Some(position) => vec![Some(position)],
+                None => {
+                    return Err(ApiError::AddonNotEligible {
+                        code,
+                        flight_id: Some(flight_id),
+                        reason: "the flight is not part of the offer".to_string(),
+                    }
+                    .extend())
+                }
+            },
+            (AddonUnit::Segment, None) => (0..segments.len()).map(Some).collect(),
+        };
+        for position in positions {
+            *quantities.entry((index, position)).or_default() += selection.quantity;
+        }
+    }
+
+    let (first, last) = match (segments.first(), segments.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err(async_graphql::Error::new("An offer needs at least one flight")),
+    };
+    for ((index, position), quantity) in quantities {
+        let addon = &addons[index];
+        let flight = position.map(|p| &segments[p]);
+        let flight_id = flight.map(|f| f.id);
+        let max = addon.max_per_passenger * passengers;
+        if quantity > max {
+            return Err(ApiError::InvalidAddonQuantity { code: addon.code.clone(), quantity, max: Some(max) }.extend());
+        }
+        let not_eligible = |reason: String| {
+            ApiError::AddonNotEligible { code: addon.code.clone(), flight_id, reason }.extend()
+        };
+        if !addon.cabins().contains(&cabin) {
+            return Err(not_eligible(format!("it is not sold in {}", cabin.as_str())));
+        }
+        let (origin, destination) = match flight {
+            Some(flight) => {
+                if addon.requires_wifi && !seatmap::aircraft_for_flight(pool, flight.id).await?.has_wifi {
+                    return Err(not_eligible("the aircraft has no Wi-Fi".to_string()));
+                }
+                (flight.origin.as_str(), flight.destination.as_str())
+            }
+            None => (first.origin.as_str(), last.destination.as_str()),
+        };
+        let unit_price = unit_price(pool, &addon.code, origin, destination, cabin).await?;
+        let description = match flight_id {
+            Some(_) => format!("{} {} to {}", addon.name, origin, destination),
+            None => addon.name.clone(),
+        };
+        items.push(OfferLineItem {
+            kind: LineItemKind::Addon,
+            code: Some(addon.code.clone()),
+            description,
+            flight_id,
+            quantity,
+            unit_price,
+            amount: round_cents(unit_price * quantity as f64),
+        });
+    }
+
+    let total = round_cents(items.iter().map(|item| item.amount).sum());
+    Ok((items, total))
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use async_graphql::ErrorExtensions;
+use axum::http::{HeaderMap, Method};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+use crate::api_keys::{self, ApiScope};
+use crate::bot_schema::AgentRegistration;
+use crate::errors::ApiError;
+
+/// Largest request body that is buffered to check its digest
+pub const MAX_SIGNED_BODY: usize = 2 * 1024 * 1024;
+
+/// Signatures whose `date` is further than this from the server clock are rejected
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Components every signature must cover
+const REQUIRED_COMPONENTS: &[&str] = &["@method", "@path", "date", "content-digest"];
+
+/// Longest accepted `nonce` signature parameter
+const MAX_NONCE_LEN: usize = 128;
+
+/// Nonces remembered at once; signed requests beyond this within the clock
+/// skew window are turned away rather than let in unchecked
+const MAX_TRACKED_NONCES: usize = 100_000;
+
+/// Why a signed request was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    Malformed(String),
+    UnknownAgent(String),
+    Stale,
+    DigestMismatch,
+    Invalid,
+    Replayed,
+    TooManyNonces,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Malformed(reason) => write!(f, "Malformed signature: {}", reason),
+            SignatureError::UnknownAgent(id) => write!(f, "Unknown agent '{}'", id),
+            SignatureError::Stale => write!(f, "Signature date is outside the accepted window"),
+            SignatureError::DigestMismatch => write!(f, "Content-Digest does not match the request body"),
+            SignatureError::Invalid => write!(f, "Signature verification failed"),
+            SignatureError::Replayed => write!(f, "Signature nonce was already used"),
+            SignatureError::TooManyNonces => write!(f, "Too many signed requests; retry later"),
+        }
+    }
+}
+
+/// Nonces of accepted signatures, per agent, until their `date` leaves the
+/// clock skew window and the date check alone rejects a replay
+#[derive(Clone, Default)]
+pub struct NonceCache {
+    seen: Arc<Mutex<HashMap<(String, String), i64>>>,
+}
+
+impl NonceCache {
+    /// Remember `nonce` for `agent_id` until `expires` (unix seconds), failing
+    /// if it is already remembered
+    fn record(&self, agent_id: &str, nonce: &str, expires: i64, now: i64) -> Result<(), SignatureError> {
+        let mut seen = self.seen.lock().unwrap();
+        let key = (agent_id.to_string(), nonce.to_string());
+        if seen.get(&key).is_some_and(|until| *until >= now) {
+            return Err(SignatureError::Replayed);
+        }
+        if seen.len() >= MAX_TRACKED_NONCES {
+            seen.retain(|_, until| *until >= now);
+            if seen.len() >= MAX_TRACKED_NONCES {
+                return Err(SignatureError::TooManyNonces);
+            }
+        }
+        seen.insert(key, expires);
+        Ok(())
+    }
+}
+
+/// Public keys may be sent as standard or URL-safe base64
+fn decode_key(public_key: &str) -> Option<VerifyingKey> {
+    let key = public_key.trim();
+    let bytes = STANDARD.decode(key).or_else(|_| URL_SAFE_NO_PAD.decode(key)).ok()?;
+    VerifyingKey::from_bytes(&bytes.try_into().ok()?).ok()
+}
+
+/// Register an agent's Ed25519 public key and return its new agent id.
+/// Registration is open, so new agents only get the default grants.
+pub async fn register(pool: &SqlitePool, name: &str, public_key: &str) -> async_graphql::Result<AgentRegistration> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(async_graphql::Error::new("name is required"));
+    }
+    let key = decode_key(public_key)
+        .ok_or_else(|| async_graphql::Error::new("publicKey must be a base64 Ed25519 public key"))?;
+    let encoded = STANDARD.encode(key.as_bytes());
+    let taken: Option<(String,)> = sqlx::query_as("SELECT id FROM agents WHERE public_key = ?")
+        .bind(&encoded)
+        .fetch_optional(pool)
+        .await
+        .map_err(|err| ApiError::from(err).extend())?;
+    if taken.is_some() {
+        return Err(async_graphql::Error::new("publicKey is already registered"));
+    }
+
+    let agent_id = format!("agent_{}", uuid::Uuid::new_v4().simple());
+    sqlx::query("INSERT INTO agents (id, name, public_key, scopes, created_time) VALUES (?, ?, ?, ?, datetime('now'))")
+        .bind(&agent_id)
+        .bind(name)
+        .bind(&encoded)
+        .bind(api_keys::format_scopes(api_keys::AGENT_SCOPES))
+        .execute(pool)
+        .await
+        .map_err(|err| ApiError::from(err).extend())?;
+    Ok(AgentRegistration { agent_id, name: name.to_string() })
+}
+
+/// Agent whose signature a request carried
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedAgent {
+    pub agent_id: String,
+    /// Scopes the agent is granted, see [`crate::api_keys::AGENT_SCOPES`]
+    pub scopes: Vec<ApiScope>,
+}
+
+/// Public key and granted scopes of an agent
+async fn agent_key(pool: &SqlitePool, agent_id: &str) -> Result<Option<(VerifyingKey, Vec<ApiScope>)>, sqlx::Error> {
+    let row: Option<(String, String)> = sqlx::query_as("SELECT public_key, scopes FROM agents WHERE id = ?")
+        .bind(agent_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.and_then(|(key, scopes)| Some((decode_key(&key)?, api_keys::parse_scopes(&scopes)))))
+}
+
+/// `Signature-Input` entry: label, covered components and the raw parameters
+/// string that becomes `@signature-params`
+struct SignatureInput<'a> {
+    label: &'a str,
+    components: Vec<&'a str>,
+    params: &'a str,
+    key_id: &'a str,
+    nonce: &'a str,
+}
+
+fn parse_signature_input(value: &str) -> Result<SignatureInput<'_>, SignatureError> {
+    let malformed = |reason: &str| SignatureError::Malformed(reason.to_string());
+    let (label, params) = value.trim().split_once('=').ok_or_else(|| malformed("Signature-Input has no label"))?;
+    let inner = params.strip_prefix('(').ok_or_else(|| malformed("Signature-Input has no component list"))?;
+    let (list, rest) = inner.split_once(')').ok_or_else(|| malformed("unterminated component list"))?;
+    let components: Vec<&str> = list.split_whitespace().map(|c| c.trim_matches('"')).collect();
+
+    let mut key_id = None;
+    let mut nonce = None;
+    for param in rest.split(';').filter(|p| !p.is_empty()) {
+        let (name, value) = param.split_once('=').ok_or_else(|| malformed("invalid parameter"))?;
+        let value = value.trim_matches('"');
+        match name {
+            "keyid" => key_id = Some(value),
+            "nonce" if value.is_empty() || value.len() > MAX_NONCE_LEN => {
+                return Err(malformed("nonce must be 1 to 128 characters"))
+            }
+            "nonce" => nonce = Some(value),
+            "alg" if value != "ed25519" => return Err(malformed("only alg=\"ed25519\" is supported")),
+            _ => {}
+        }
+    }
+    Ok(SignatureInput {
+        label,
+        components,
+        params,
+        key_id: key_id.ok_or_else(|| malformed("keyid is required"))?,
+        nonce: nonce.ok_or_else(|| malformed("nonce is required"))?,
+    })
+}
+
+/// Signature value for `label` from a `Signature: label=:base64:` header
+fn signature_for(headers: &HeaderMap, label: &str) -> Result<Signature, SignatureError> {
+    let value = headers
+        .get("signature")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| SignatureError::Malformed("Signature header is missing".to_string()))?;
+    let encoded = value
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .find(|(name, _)| *name == label)
+        .map(|(_, sig)| sig.trim_matches(':'))
+        .ok_or_else(|| SignatureError::Malformed(format!("no signature labelled '{}'", label)))?;
+    let bytes = STANDARD.decode(encoded).map_err(|_| SignatureError::Malformed("signature is not base64".to_string()))?;
+    Signature::from_slice(&bytes).map_err(|_| SignatureError::Malformed("signature has the wrong length".to_string()))
+}
+
+/// `Content-Digest` value for a body
+pub fn content_digest(body: &[u8]) -> String {
+    format!("sha-256=:{}:", STANDARD.encode(Sha256::digest(body)))
+}
+
+/// Verify an HTTP message signature over method, path, date and body digest.
+/// The signed `nonce` parameter may be used once per agent within the clock
+/// skew window. Returns the signing agent for signed requests and `None` for unsigned ones.
+pub async fn verify_request(
+    pool: &SqlitePool,
+    nonces: &NonceCache,
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Option<SignedAgent>, SignatureError> {
+    let Some(input) = headers.get("signature-input") else {
+        return Ok(None);
+    };
+    let input = input.to_str().map_err(|_| SignatureError::Malformed("Signature-Input is not ASCII".to_string()))?;
+    let input = parse_signature_input(input)?;
+    if let Some(missing) = REQUIRED_COMPONENTS.iter().find(|c| !input.components.contains(c)) {
+        return Err(SignatureError::Malformed(format!("signature must cover {}", missing)));
+    }
+
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .ok_or_else(|| SignatureError::Malformed(format!("{} header is missing", name)))
+    };
+    let date = DateTime::parse_from_rfc2822(header("date")?)
+        .map_err(|_| SignatureError::Malformed("date is not an HTTP date".to_string()))?;
+    let now = Utc::now();
+    if (now - date.with_timezone(&Utc)).num_seconds().abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(SignatureError::Stale);
+    }
+    if header("content-digest")? != content_digest(body) {
+        return Err(SignatureError::DigestMismatch);
+    }
+
+    let mut base = String::new();
+    for component in &input.components {
+        let value = match *component {
+            "@method" => method.as_str(),
+            "@path" => path,
+            name if !name.starts_with('@') => header(name)?,
+            other => return Err(SignatureError::Malformed(format!("unsupported component {}", other))),
+        };
+        base.push_str(&format!("\"{}\": {}\n", component, value));
+    }
+    base.push_str(&format!("\"@signature-params\": {}", input.params));
+
+    let signature = signature_for(headers, input.label)?;
+    let (key, scopes) = agent_key(pool, input.key_id)
+        .await
+        .map_err(|err| SignatureError::Malformed(err.to_string()))?
+        .ok_or_else(|| SignatureError::UnknownAgent(input.key_id.to_string()))?;
+    key.verify_strict(base.as_bytes(), &signature).map_err(|_| SignatureError::Invalid)?;
+    // Only nonces of valid signatures are remembered, so others cannot burn them
+    nonces.record(input.key_id, input.nonce, date.timestamp() + MAX_CLOCK_SKEW_SECS, now.timestamp())?;
+    Ok(Some(SignedAgent { agent_id: input.key_id.to_string(), scopes }))
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use chrono::{NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use sqlx::SqlitePool;
+use tracing::warn;
+
+use crate::itinerary::parse_time;
+use crate::schema::{Airport, FlightOffer};
+
+/// Mean radius of the Earth in statute miles
+const EARTH_RADIUS_MILES: f64 = 3958.8;
+
+/// Flight times written without a UTC offset, read as local time at the airport
+const LOCAL_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Zone assumed for times stored without an offset at an airport missing
+/// from the reference table, whose local time is unknown
+const FALLBACK_TIME_ZONE: &str = "UTC";
+
+/// Batches the airport lookups of every flight in a response into one query
+pub struct AirportLoader {
+    pool: SqlitePool,
+}
+
+/// Loader registered as schema data for the airport and distance fields of flights
+pub fn loader(pool: SqlitePool) -> DataLoader<AirportLoader> {
+    DataLoader::new(AirportLoader { pool }, tokio::spawn)
+}
+
+impl Loader<String> for AirportLoader {
+    type Value = Airport;
+    type Error = Arc<sqlx::Error>;
+
+    /// Codes missing from the reference table are left out, so they load as `None`
+    async fn load(&self, codes: &[String]) -> Result<HashMap<String, Self::Value>, Self::Error> {
+        let placeholders = vec!["?"; codes.len()].join(", ");
+        let sql = format!(
+            "SELECT code, name, latitude, longitude, time_zone FROM airports WHERE code IN ({})",
+            placeholders
+        );
+        let mut query = sqlx::query_as::<_, Airport>(&sql);
+        for code in codes {
+            query = query.bind(code);
+        }
+        let airports = query.fetch_all(&self.pool).await.map_err(Arc::new)?;
+        Ok(airports.into_iter().map(|airport| (airport.code.clone(), airport)).collect())
+    }
+}
+
+/// Origin and destination airports of a flight, `None` where the reference
+/// table has no entry for the code
+pub async fn for_flight(
+    loader: &DataLoader<AirportLoader>,
+    flight: &FlightOffer,
+) -> async_graphql::Result<(Option<Airport>, Option<Airport>)> {
+    let mut airports = loader.load_many([flight.origin.clone(), flight.destination.clone()]).await?;
+    Ok((airports.remove(&flight.origin), airports.remove(&flight.destination)))
+}
+
+/// Great-circle (haversine) distance between two airports, in whole statute miles
+pub fn distance_miles(from: &Airport, to: &Airport) -> i64 {
+    let (lat1, lat2) = (from.latitude.to_radians(), to.latitude.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (to.longitude - from.longitude).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    (2.0 * EARTH_RADIUS_MILES * a.sqrt().asin()).round() as i64
+}
+
+/// Block time from departure to arrival; both carry their airport's offset
+pub fn block_minutes(flight: &FlightOffer) -> async_graphql::Result<i64> {
+    Ok((parse_time(&flight.arrival_time)? - parse_time(&flight.departure_time)?).num_minutes())
+}
+
+/// Timestamp with the UTC offset for a local wall-clock time in an IANA zone.
+/// Times repeated when clocks go back resolve to the earlier one.
+pub fn localize(local: &str, time_zone: &str) -> Result<String, String> {
+    let zone: Tz = time_zone.parse().map_err(|_| format!("Unknown time zone '{}'", time_zone))?;
+    let naive = NaiveDateTime::parse_from_str(local, LOCAL_TIME_FORMAT)
+        .map_err(|_| format!("Invalid local time '{}'", local))?;
+    zone.from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.to_rfc3339())
+        .ok_or_else(|| format!("{} does not exist in {}", local, time_zone))
+}
+
+#[derive(sqlx::FromRow)]
+struct UnzonedFlight {
+    id: i64,
+    departure_time: String,
+    arrival_time: String,
+    origin_zone: Option<String>,
+    destination_zone: Option<String>,
+}
+
+/// Rewrite flight times stored without an offset as local times at their
+/// airports; returns how many flights were updated. Times at an airport
+/// missing from the reference table are read as [`FALLBACK_TIME_ZONE`]; a
+/// time that cannot be read, or does not exist in its zone, is an error and
+/// nothing is updated. Run by [`crate::db::migrate`] on every start, so rows
+/// written by an older build are localized as well.
+pub async fn localize_flight_times(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let flights = sqlx::query_as::<_, UnzonedFlight>(
+        "SELECT f.id, f.departure_time, f.arrival_time, o.time_zone AS origin_zone, d.time_zone AS destination_zone \
+         FROM flights f LEFT JOIN airports o ON o.code = f.origin LEFT JOIN airports d ON d.code = f.destination \
+         WHERE length(f.departure_time) = 19 OR length(f.arrival_time) = 19",
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let mut updated = 0;
+    for flight in flights {
+        if flight.origin_zone.is_none() || flight.destination_zone.is_none() {
+            warn!("Flight {} has an airport without a time zone; reading its times as {}", flight.id, FALLBACK_TIME_ZONE);
+        }
+        let zoned = |time: &str, zone: &Option<String>| {
+            if time.len() != 19 {
+                return Ok(time.to_string());
+            }
+            localize(time, zone.as_deref().unwrap_or(FALLBACK_TIME_ZONE))
+                .map_err(|err| sqlx::Error::Decode(format!("Flight {}: {}", flight.id, err).into()))
+        };
+        sqlx::query("UPDATE flights SET departure_time = ?, arrival_time = ? WHERE id = ?")
+            .bind(zoned(&flight.departure_time, &flight.origin_zone)?)
+            .bind(zoned(&flight.arrival_time, &flight.destination_zone)?)
+            .bind(flight.id)
+            .execute(&mut tx)
+            .await?;
+        updated += 1;
+    }
+    tx.commit().await?;
+    Ok(updated)
+}
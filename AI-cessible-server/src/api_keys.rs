@@ -0,0 +1,261 @@
+use async_graphql::{Context, ErrorExtensions, Guard};
+use axum::http::HeaderMap;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+use crate::bot_detection::BotInfo;
+use crate::bot_schema::{ApiKeyInfo, IssuedApiKey};
+use crate::errors::ApiError;
+
+/// Header carrying an API key for clients that cannot set `Authorization`
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Prefix of every issued key, so leaked keys are easy to recognise
+const KEY_PREFIX: &str = "bsk_";
+
+/// Operations an API key can be allowed to perform
+#[derive(async_graphql::Enum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ApiScope {
+    /// Flight, itinerary and trip search and seat maps
+    Search,
+    /// Offer explanations, insights and structured bookings
+    Explain,
+    /// Price and upgrade negotiation
+    Negotiate,
+    /// Offers, seat holds, payment tokens and bookings
+    Book,
+    /// The agent's own stored intents
+    ReadIntents,
+    /// Behavior aggregates of sessions other than the caller's
+    ReadBehavior,
+}
+
+/// Every scope
+pub const ALL_SCOPES: &[ApiScope] = &[
+    ApiScope::Search,
+    ApiScope::Explain,
+    ApiScope::Negotiate,
+    ApiScope::Book,
+    ApiScope::ReadIntents,
+    ApiScope::ReadBehavior,
+];
+
+/// Scopes of callers without a key or signature: what the public web app can do
+pub const PUBLIC_SCOPES: &[ApiScope] = &[ApiScope::Search, ApiScope::Book];
+
+/// Scopes a newly registered agent is granted, matching the `agents.scopes`
+/// default; `ReadBehavior` is only granted by an operator
+pub const AGENT_SCOPES: &[ApiScope] =
+    &[ApiScope::Search, ApiScope::Explain, ApiScope::Negotiate, ApiScope::Book, ApiScope::ReadIntents];
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Search => "search",
+            ApiScope::Explain => "explain",
+            ApiScope::Negotiate => "negotiate",
+            ApiScope::Book => "book",
+            ApiScope::ReadIntents => "read-intents",
+            ApiScope::ReadBehavior => "read-behavior",
+        }
+    }
+}
+
+impl std::str::FromStr for ApiScope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "search" => Ok(ApiScope::Search),
+            "explain" => Ok(ApiScope::Explain),
+            "negotiate" => Ok(ApiScope::Negotiate),
+            "book" => Ok(ApiScope::Book),
+            "read-intents" => Ok(ApiScope::ReadIntents),
+            "read-behavior" => Ok(ApiScope::ReadBehavior),
+            other => Err(format!("Unknown API scope '{}'", other)),
+        }
+    }
+}
+
+/// A validated API key presented with a request
+#[derive(Clone, Debug)]
+pub struct ApiKeyGrant {
+    pub key_id: String,
+    pub agent_id: String,
+    pub scopes: Vec<ApiScope>,
+}
+
+/// Why the API key of a request was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiKeyError {
+    Unknown,
+    Revoked,
+    /// The request is signed by a different agent than the key belongs to
+    AgentMismatch,
+}
+
+impl std::fmt::Display for ApiKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeyError::Unknown => write!(f, "Unknown API key"),
+            ApiKeyError::Revoked => write!(f, "API key has been revoked"),
+            ApiKeyError::AgentMismatch => write!(f, "API key belongs to a different agent than the signature"),
+        }
+    }
+}
+
+fn key_hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+pub(crate) fn format_scopes(scopes: &[ApiScope]) -> String {
+    scopes.iter().map(ApiScope::as_str).collect::<Vec<_>>().join(",")
+}
+
+/// Stored scopes; unknown names from older builds are skipped
+pub(crate) fn parse_scopes(value: &str) -> Vec<ApiScope> {
+    value.split(',').filter_map(|scope| scope.parse().ok()).collect()
+}
+
+/// Key sent with `Authorization: Bearer <key>` or `X-Api-Key`
+pub fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("Bearer "))
+        .map(str::trim);
+    bearer.or_else(|| headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()).map(str::trim))
+}
+
+/// Issue a key for an agent, limited to scopes the agent is granted.
+/// The key itself is only returned here; the server keeps its hash.
+pub async fn issue(
+    pool: &SqlitePool,
+    agent_id: &str,
+    scopes: &[ApiScope],
+    label: Option<&str>,
+) -> async_graphql::Result<IssuedApiKey> {
+    if scopes.is_empty() {
+        return Err(async_graphql::Error::new("At least one scope is required"));
+    }
+    let mut unique = Vec::new();
+    for scope in scopes {
+        if !unique.contains(scope) {
+            unique.push(*scope);
+        }
+    }
+    let scopes = unique;
+    let granted: Option<(String,)> = sqlx::query_as("SELECT scopes FROM agents WHERE id = ?")
+        .bind(agent_id)
+        .fetch_optional(pool)
+        .await?;
+    let granted = granted.map(|(scopes,)| parse_scopes(&scopes)).unwrap_or_default();
+    if let Some(scope) = scopes.iter().find(|scope| !granted.contains(scope)) {
+        return Err(async_graphql::Error::new(format!("Agent is not granted the '{}' scope", scope.as_str())));
+    }
+    let key_id = format!("key_{}", uuid::Uuid::new_v4().simple());
+    let api_key = format!("{}{}{}", KEY_PREFIX, uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let label = label.map(str::trim).filter(|l| !l.is_empty());
+
+    sqlx::query(
+        "INSERT INTO api_keys (id, agent_id, key_hash, scopes, label, created_time) VALUES (?, ?, ?, ?, ?, datetime('now'))",
+    )
+    .bind(&key_id)
+    .bind(agent_id)
+    .bind(key_hash(&api_key))
+    .bind(format_scopes(&scopes))
+    .bind(label)
+    .execute(pool)
+    .await?;
+    Ok(IssuedApiKey { key_id, api_key, agent_id: agent_id.to_string(), scopes })
+}
+
+/// Revoke one of the agent's keys; `false` if it has no such active key
+pub async fn revoke(pool: &SqlitePool, agent_id: &str, key_id: &str) -> async_graphql::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_time = datetime('now') WHERE id = ? AND agent_id = ? AND revoked_time IS NULL",
+    )
+    .bind(key_id)
+    .bind(agent_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Row shape of a key listing
+#[derive(sqlx::FromRow)]
+struct KeyRow {
+    id: String,
+    scopes: String,
+    label: Option<String>,
+    created_time: String,
+    revoked_time: Option<String>,
+}
+
+/// Keys issued to an agent, newest first
+pub async fn list(pool: &SqlitePool, agent_id: &str) -> async_graphql::Result<Vec<ApiKeyInfo>> {
+    let rows = sqlx::query_as::<_, KeyRow>(
+        "SELECT id, scopes, label, created_time, revoked_time FROM api_keys WHERE agent_id = ? ORDER BY created_time DESC, id",
+    )
+    .bind(agent_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| ApiKeyInfo {
+            key_id: row.id,
+            scopes: parse_scopes(&row.scopes),
+            label: row.label,
+            created_time: row.created_time,
+            revoked_time: row.revoked_time,
+        })
+        .collect())
+}
+
+/// Look up a presented key. Its scopes are cut to what the agent is still granted.
+pub async fn validate(pool: &SqlitePool, key: &str) -> Result<Result<ApiKeyGrant, ApiKeyError>, sqlx::Error> {
+    let row: Option<(String, String, String, Option<String>, String)> = sqlx::query_as(
+        "SELECT k.id, k.agent_id, k.scopes, k.revoked_time, a.scopes FROM api_keys k JOIN agents a ON a.id = k.agent_id WHERE k.key_hash = ?",
+    )
+    .bind(key_hash(key))
+    .fetch_optional(pool)
+    .await?;
+    Ok(match row {
+        None => Err(ApiKeyError::Unknown),
+        Some((_, _, _, Some(_), _)) => Err(ApiKeyError::Revoked),
+        Some((key_id, agent_id, scopes, None, granted)) => {
+            let granted = parse_scopes(&granted);
+            let scopes = parse_scopes(&scopes).into_iter().filter(|scope| granted.contains(scope)).collect();
+            Ok(ApiKeyGrant { key_id, agent_id, scopes })
+        }
+    })
+}
+
+/// Error for a caller that lacks `scope`, or `None` if it may proceed
+pub fn check_scope(bot_info: &BotInfo, scope: ApiScope, operation: &str) -> Option<ApiError> {
+    if bot_info.has_scope(scope) {
+        return None;
+    }
+    let operation = operation.to_string();
+    Some(match bot_info.api_key {
+        Some(_) => ApiError::MissingScope { operation, scope },
+        None => ApiError::Unauthorized { operation, scope },
+    })
+}
+
+/// Field guard requiring a scope. Requests executed without detection data
+/// are refused, so a route that skips the middleware cannot skip the check.
+pub struct ScopeGuard(pub ApiScope);
+
+impl Guard for ScopeGuard {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        let Some(bot_info) = ctx.data_opt::<BotInfo>() else {
+            let operation = ctx.field().name().to_string();
+            return Err(ApiError::Unauthorized { operation, scope: self.0 }.extend());
+        };
+        match check_scope(bot_info, self.0, ctx.field().name()) {
+            Some(err) => Err(err.extend()),
+            None => Ok(()),
+        }
+    }
+}
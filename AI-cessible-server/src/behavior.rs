@@ -0,0 +1,229 @@
+use async_graphql::ErrorExtensions;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::bot_detection::BotInfo;
+use crate::bot_schema::BehaviorAggregate;
+use crate::errors::ApiError;
+use crate::sessions::SessionStore;
+
+/// Weights the frontend detector gives its own signals, reused to turn
+/// stored reports into a single behavior score
+const SIGNAL_WEIGHTS: [f64; 4] = [0.45, 0.15, 0.25, 0.15];
+
+/// Most aggregates returned by one listing
+pub const MAX_AGGREGATES: i64 = 100;
+
+/// Signal scores computed in the browser, each 0 (human-like) to 1 (bot-like)
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BehaviorSignals {
+    pub mouse_entropy: Option<f64>,
+    pub typing_pattern: Option<f64>,
+    pub navigation_pattern: Option<f64>,
+    pub form_filling: Option<f64>,
+}
+
+/// Number of raw events behind the signals
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleCounts {
+    #[serde(default)]
+    pub mouse_movements: u32,
+    #[serde(default)]
+    pub key_presses: u32,
+    #[serde(default)]
+    pub clicks: u32,
+    #[serde(default)]
+    pub scrolls: u32,
+    #[serde(default)]
+    pub form_interactions: u32,
+}
+
+/// A behavior report as sent by `botDetection.js`. Unknown fields are accepted
+/// and kept in the stored payload.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BehaviorReport {
+    pub session_id: Option<String>,
+    pub confidence_score: Option<f64>,
+    #[serde(default)]
+    pub signals: BehaviorSignals,
+    #[serde(default)]
+    pub sample_counts: SampleCounts,
+    pub form_fill_time_ms: Option<f64>,
+    pub user_agent: Option<String>,
+    pub timestamp: Option<String>,
+}
+
+impl BehaviorReport {
+    fn validate(&self) -> Result<(), String> {
+        let scores = [
+            ("confidenceScore", self.confidence_score),
+            ("signals.mouseEntropy", self.signals.mouse_entropy),
+            ("signals.typingPattern", self.signals.typing_pattern),
+            ("signals.navigationPattern", self.signals.navigation_pattern),
+            ("signals.formFilling", self.signals.form_filling),
+        ];
+        for (field, value) in scores {
+            if let Some(value) = value {
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(format!("{} must be between 0 and 1", field));
+                }
+            }
+        }
+        if self.form_fill_time_ms.is_some_and(|ms| !(ms >= 0.0 && ms.is_finite())) {
+            return Err("formFillTimeMs must be a non-negative number".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Weighted average of the available signal averages
+fn behavior_score(signals: [Option<f64>; 4]) -> Option<f64> {
+    let (sum, weight) = signals
+        .iter()
+        .zip(SIGNAL_WEIGHTS)
+        .filter_map(|(value, weight)| value.map(|v| (v * weight, weight)))
+        .fold((0.0, 0.0), |(s, w), (v, weight)| (s + v, w + weight));
+    (weight > 0.0).then(|| sum / weight)
+}
+
+/// Store a behavior report and return its id. Reports are filed under the
+/// caller's session, which must be one the server issued, so nobody can
+/// report for a session id they picked or another client's session.
+pub async fn record(
+    pool: &SqlitePool,
+    sessions: &SessionStore,
+    bot_info: Option<&BotInfo>,
+    payload: &serde_json::Value,
+) -> async_graphql::Result<i64> {
+    let report: BehaviorReport = serde_json::from_value(payload.clone())
+        .map_err(|err| async_graphql::Error::new(format!("Invalid behavior report: {}", err)))?;
+    report.validate().map_err(async_graphql::Error::new)?;
+
+    let session_id = bot_info
+        .map(|info| info.session_id.as_str())
+        .ok_or_else(|| async_graphql::Error::new("A session id is required"))?;
+    if report.session_id.as_deref().map(str::trim).is_some_and(|id| !id.is_empty() && id != session_id) {
+        return Err(async_graphql::Error::new("sessionId must be the session of the request"));
+    }
+    if !sessions.issued_here(session_id) {
+        return Err(async_graphql::Error::new(
+            "Behavior reports are only accepted for sessions issued by the server; send back the session cookie or X-Session-Id of an earlier response",
+        ));
+    }
+    let counts = &report.sample_counts;
+    let signals = &report.signals;
+
+    let result = sqlx::query(
+        "INSERT INTO behavior_metrics (session_id, client_confidence, mouse_entropy, typing_pattern, navigation_pattern, form_filling, form_fill_time_ms, mouse_movements, key_presses, clicks, scrolls, form_interactions, user_agent, client_timestamp, server_confidence, payload, recorded_time) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'))",
+    )
+    .bind(session_id)
+    .bind(report.confidence_score)
+    .bind(signals.mouse_entropy)
+    .bind(signals.typing_pattern)
+    .bind(signals.navigation_pattern)
+    .bind(signals.form_filling)
+    .bind(report.form_fill_time_ms)
+    .bind(counts.mouse_movements)
+    .bind(counts.key_presses)
+    .bind(counts.clicks)
+    .bind(counts.scrolls)
+    .bind(counts.form_interactions)
+    .bind(&report.user_agent)
+    .bind(&report.timestamp)
+    .bind(bot_info.map_or(0.0, |info| info.confidence_score))
+    .bind(payload.to_string())
+    .execute(pool)
+    .await
+    .map_err(|err| ApiError::from(err).extend())?;
+
+    // Detection reads the session's score from memory, so it is computed here once per report
+    let aggregate = session_aggregate(pool, session_id).await.map_err(|err| ApiError::from(err).extend())?;
+    if let Some(aggregate) = aggregate {
+        sessions.record_behavior(&aggregate);
+    }
+    Ok(result.last_insert_rowid())
+}
+
+/// Row shape of the per-session aggregate query
+#[derive(sqlx::FromRow)]
+struct AggregateRow {
+    session_id: String,
+    reports: i64,
+    avg_client_confidence: Option<f64>,
+    avg_mouse_entropy: Option<f64>,
+    avg_typing_pattern: Option<f64>,
+    avg_navigation_pattern: Option<f64>,
+    avg_form_filling: Option<f64>,
+    avg_form_fill_time_ms: Option<f64>,
+    mouse_movements: i64,
+    key_presses: i64,
+    clicks: i64,
+    scrolls: i64,
+    form_interactions: i64,
+    first_seen: String,
+    last_seen: String,
+}
+
+const AGGREGATE_SELECT: &str = "SELECT session_id, COUNT(*) AS reports, AVG(client_confidence) AS avg_client_confidence, \
+     AVG(mouse_entropy) AS avg_mouse_entropy, AVG(typing_pattern) AS avg_typing_pattern, \
+     AVG(navigation_pattern) AS avg_navigation_pattern, AVG(form_filling) AS avg_form_filling, \
+     AVG(form_fill_time_ms) AS avg_form_fill_time_ms, SUM(mouse_movements) AS mouse_movements, \
+     SUM(key_presses) AS key_presses, SUM(clicks) AS clicks, SUM(scrolls) AS scrolls, \
+     SUM(form_interactions) AS form_interactions, MIN(recorded_time) AS first_seen, MAX(recorded_time) AS last_seen \
+     FROM behavior_metrics";
+
+impl From<AggregateRow> for BehaviorAggregate {
+    fn from(row: AggregateRow) -> Self {
+        BehaviorAggregate {
+            behavior_score: behavior_score([
+                row.avg_mouse_entropy,
+                row.avg_typing_pattern,
+                row.avg_navigation_pattern,
+                row.avg_form_filling,
+            ]),
+            session_id: row.session_id,
+            reports: row.reports,
+            avg_client_confidence: row.avg_client_confidence,
+            avg_mouse_entropy: row.avg_mouse_entropy,
+            avg_typing_pattern: row.avg_typing_pattern,
+            avg_navigation_pattern: row.avg_navigation_pattern,
+            avg_form_filling: row.avg_form_filling,
+            avg_form_fill_time_ms: row.avg_form_fill_time_ms,
+            mouse_movements: row.mouse_movements,
+            key_presses: row.key_presses,
+            clicks: row.clicks,
+            scrolls: row.scrolls,
+            form_interactions: row.form_interactions,
+            first_seen: row.first_seen,
+            last_seen: row.last_seen,
+        }
+    }
+}
+
+/// Aggregated behavior of one session, if it has sent any reports
+pub async fn session_aggregate(pool: &SqlitePool, session_id: &str) -> Result<Option<BehaviorAggregate>, sqlx::Error> {
+    let row = sqlx::query_as::<_, AggregateRow>(&format!(
+        "{} WHERE session_id = ? GROUP BY session_id",
+        AGGREGATE_SELECT
+    ))
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(BehaviorAggregate::from))
+}
+
+/// Aggregates of the most recently active sessions
+pub async fn recent_aggregates(pool: &SqlitePool, limit: i64) -> async_graphql::Result<Vec<BehaviorAggregate>> {
+    let rows = sqlx::query_as::<_, AggregateRow>(&format!(
+        "{} GROUP BY session_id ORDER BY last_seen DESC LIMIT ?",
+        AGGREGATE_SELECT
+    ))
+    .bind(limit.clamp(1, MAX_AGGREGATES))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(BehaviorAggregate::from).collect())
+}
@@ -0,0 +1,297 @@
+use async_graphql::ErrorExtensions;
+use sqlx::SqlitePool;
+use tracing::warn;
+
+use crate::bot_detection::BotInfo;
+use crate::errors::ApiError;
+use crate::itinerary::parse_time;
+use crate::payment::{self, Authorization, PaymentMethod, PaymentProcessor};
+use crate::schema::{
+    AssignedSeat, BookingConfirmation, Cabin, FlightOffer, OfferLineItem, PassengerInput, PaymentStatus,
+    PaymentSummary, SeatSelectionInput,
+};
+use crate::seatmap::SeatInfo;
+use crate::{addons, inventory, offers, passengers, seatmap, trips};
+
+/// What a booking charges: the itemized quote of an offer, or the fares alone
+pub struct Quote {
+    /// Offer being booked, if the booking was made from one
+    pub offer_id: Option<String>,
+    pub line_items: Vec<OfferLineItem>,
+    pub total_price: f64,
+}
+
+/// Session and agent a booking or negotiation belongs to; only they can look it up again
+#[derive(Clone, Debug, Default)]
+pub struct Owner {
+    pub session_id: Option<String>,
+    pub agent_id: Option<String>,
+}
+
+impl Owner {
+    /// Owner of a booking or negotiation made, or looked up, by the caller
+    pub fn of(bot_info: Option<&BotInfo>) -> Self {
+        Owner {
+            session_id: bot_info.map(|info| info.session_id.clone()).filter(|id| !id.is_empty()),
+            agent_id: bot_info.and_then(|info| info.agent_id.clone()),
+        }
+    }
+}
+
+/// Fail with `BOOKING_NOT_FOUND` unless the booking was made by the caller's
+/// agent or session, so ids cannot be walked to read other travellers' bookings
+pub async fn check_owner(pool: &SqlitePool, booking_id: i64, caller: &Owner) -> async_graphql::Result<()> {
+    let owner: Option<(Option<String>, Option<String>)> =
+        sqlx::query_as("SELECT session_id, agent_id FROM bookings WHERE id = ?")
+            .bind(booking_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|err| ApiError::from(err).extend())?;
+    let owns = |theirs: &Option<String>, ours: &Option<String>| theirs.is_some() && theirs == ours;
+    match owner {
+        Some((session_id, agent_id)) if owns(&agent_id, &caller.agent_id) || owns(&session_id, &caller.session_id) => {
+            Ok(())
+        }
+        _ => Err(ApiError::BookingNotFound(booking_id).extend()),
+    }
+}
+
+/// Fetch a single flight by id
+pub async fn fetch_flight(pool: &SqlitePool, flight_id: i64) -> async_graphql::Result<FlightOffer> {
+    sqlx::query_as::<_, FlightOffer>(
+        "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
+    )
+    .bind(flight_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::FlightNotFound(flight_id).extend())
+}
+
+/// Resolve the flights referenced by either a single flight id or a trip id
+pub async fn resolve_flights(
+    pool: &SqlitePool,
+    flight_id: Option<i64>,
+    trip_id: Option<&str>,
+) -> async_graphql::Result<Vec<FlightOffer>> {
+    let ids = match (flight_id, trip_id) {
+        (Some(id), None) => vec![id],
+        (None, Some(trip_id)) => trips::parse_trip_id(trip_id)?,
+        _ => {
+            return Err(async_graphql::Error::new(
+                "Provide exactly one of flightId or tripId",
+            ))
+        }
+    };
+    let mut flights = Vec::with_capacity(ids.len());
+    for id in ids {
+        flights.push(fetch_flight(pool, id).await?);
+    }
+    trips::validate_sequence(&flights)?;
+    Ok(flights)
+}
+
+/// Flights of a booking in travel order
+pub async fn booking_segments(
+    pool: &SqlitePool,
+    booking_id: i64,
+) -> async_graphql::Result<Vec<FlightOffer>> {
+    let segments = sqlx::query_as::<_, FlightOffer>(
+        "SELECT f.id, f.origin, f.destination, f.departure_time, f.arrival_time, f.price FROM booking_segments s JOIN flights f ON f.id = s.flight_id WHERE s.booking_id = ? ORDER BY s.segment_index",
+    )
+    .bind(booking_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(segments)
+}
+
+/// Masked payment details stored with a booking
+pub async fn payment_summary(pool: &SqlitePool, booking_id: i64) -> async_graphql::Result<PaymentSummary> {
+    let (brand, last4, status, amount): (String, String, String, f64) = sqlx::query_as(
+        "SELECT card_brand, card_last4, payment_status, total_price FROM bookings WHERE id = ?",
+    )
+    .bind(booking_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(PaymentSummary {
+        brand,
+        last4,
+        status: status.parse().unwrap_or(PaymentStatus::Authorized),
+        amount,
+    })
+}
+
+/// Line items stored with a booking; empty for bookings made before they were recorded
+pub async fn line_items(pool: &SqlitePool, booking_id: i64) -> async_graphql::Result<Vec<OfferLineItem>> {
+    let (items,): (Option<String>,) = sqlx::query_as("SELECT line_items FROM bookings WHERE id = ?")
+        .bind(booking_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(match items {
+        Some(items) => serde_json::from_str(&items)?,
+        None => Vec::new(),
+    })
+}
+
+/// Record a booking covering one or more flights in a single transaction,
+/// taking a seat per seated passenger in the requested cabin on every flight
+/// and assigning any selected seats.
+///
+/// The total of `quote` is charged, or the fares of the seated passengers
+/// without one. It is authorized before anything is written, released again
+/// if the booking cannot be recorded, and captured once the booking is committed.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_booking(
+    pool: &SqlitePool,
+    processor: &dyn PaymentProcessor,
+    flights: Vec<FlightOffer>,
+    cabin: Cabin,
+    quote: Option<Quote>,
+    seats: &[SeatSelectionInput],
+    passengers: &[PassengerInput],
+    payment: &str,
+    owner: &Owner,
+) -> async_graphql::Result<BookingConfirmation> {
+    let first = flights
+        .first()
+        .cloned()
+        .ok_or_else(|| async_graphql::Error::new("A booking needs at least one flight"))?;
+
+    let last = flights.last().unwrap_or(&first);
+    let departs = parse_time(&first.departure_time)?.date_naive();
+    let returns = parse_time(&last.arrival_time)?.date_naive();
+    passengers::validate(passengers, departs, returns)?;
+    let seated = passengers::seated_count(passengers);
+
+    let flight_ids: Vec<i64> = flights.iter().map(|f| f.id).collect();
+    let mut selected = Vec::with_capacity(seats.len());
+    for selection in seats {
+        let already = selected
+            .iter()
+            .filter(|(s, _): &&(&SeatSelectionInput, _)| s.flight_id == selection.flight_id)
+            .count() as i64;
+        if already >= seated {
+            return Err(async_graphql::Error::new(format!(
+                "More seats selected on flight {} than seated passengers",
+                selection.flight_id
+            )));
+        }
+        let info = seatmap::validate_selection(pool, &flight_ids, cabin, selection).await?;
+        selected.push((selection, info));
+    }
+
+    let quote = match quote {
+        Some(quote) => quote,
+        None => {
+            let (line_items, total_price) = addons::price_offer(pool, &flights, cabin, seated, &[]).await?;
+            Quote { offer_id: None, line_items, total_price }
+        }
+    };
+    let total_price = quote.total_price;
+    let method = payment::resolve_payment_method(processor, payment)
+        .await
+        .map_err(|err| ApiError::Payment(err).extend())?;
+    let authorization = processor
+        .authorize(&method.token, total_price)
+        .await
+        .map_err(|err| ApiError::Payment(err).extend())?;
+
+    let recorded =
+        record_booking(pool, &flights, cabin, &selected, passengers, seated, &quote, &method, &authorization, owner)
+            .await;
+    let booking_id = match recorded {
+        Ok(booking_id) => booking_id,
+        Err(err) => {
+            if let Err(refund_err) = processor.refund(&authorization.id).await {
+                warn!("Failed to release authorization {}: {}", authorization.id, refund_err);
+            }
+            return Err(err);
+        }
+    };
+
+    let mut status = PaymentStatus::Authorized;
+    match processor.capture(&authorization.id).await {
+        Ok(()) => {
+            status = PaymentStatus::Captured;
+            sqlx::query("UPDATE bookings SET payment_status = ? WHERE id = ?")
+                .bind(status.as_str())
+                .bind(booking_id)
+                .execute(pool)
+                .await?;
+        }
+        Err(err) => warn!("Capture of booking {} failed, left authorized: {}", booking_id, err),
+    }
+
+    Ok(BookingConfirmation {
+        booking_id,
+        total_price,
+        flight: first,
+        segments: flights,
+        cabin,
+        offer_id: quote.offer_id,
+        line_items: quote.line_items,
+        seats: selected
+            .into_iter()
+            .map(|(selection, info)| AssignedSeat { flight_id: selection.flight_id, seat: info.seat })
+            .collect(),
+        payment: PaymentSummary { brand: method.brand, last4: method.last4, status, amount: total_price },
+    })
+}
+
+/// Write the booking, its passengers, segments and seats in one transaction
+#[allow(clippy::too_many_arguments)]
+async fn record_booking(
+    pool: &SqlitePool,
+    flights: &[FlightOffer],
+    cabin: Cabin,
+    selected: &[(&SeatSelectionInput, SeatInfo)],
+    passengers: &[PassengerInput],
+    seated: i64,
+    quote: &Quote,
+    method: &PaymentMethod,
+    authorization: &Authorization,
+    owner: &Owner,
+) -> async_graphql::Result<i64> {
+    let mut tx = pool.begin().await?;
+    for flight in flights {
+        inventory::reserve(&mut tx, flight.id, cabin, seated).await?;
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO bookings (flight_id, cabin, payment_token, card_brand, card_last4, payment_reference, payment_status, total_price, offer_id, line_items, session_id, agent_id, booking_time) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'))",
+    )
+    .bind(flights[0].id)
+    .bind(cabin.as_str())
+    .bind(&method.token)
+    .bind(&method.brand)
+    .bind(&method.last4)
+    .bind(&authorization.id)
+    .bind(PaymentStatus::Authorized.as_str())
+    .bind(authorization.amount)
+    .bind(&quote.offer_id)
+    .bind(serde_json::to_string(&quote.line_items)?)
+    .bind(&owner.session_id)
+    .bind(&owner.agent_id)
+    .execute(&mut tx)
+    .await?;
+    let booking_id = result.last_insert_rowid();
+    if let Some(offer_id) = &quote.offer_id {
+        offers::mark_booked(&mut tx, offer_id, booking_id).await?;
+    }
+    passengers::insert(&mut tx, booking_id, passengers).await?;
+
+    for (index, flight) in flights.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO booking_segments (booking_id, segment_index, flight_id) VALUES (?, ?, ?)",
+        )
+        .bind(booking_id)
+        .bind(index as i64)
+        .bind(flight.id)
+        .execute(&mut tx)
+        .await?;
+    }
+    for (selection, info) in selected {
+        seatmap::assign_seat(&mut tx, booking_id, selection.flight_id, &info.seat, selection.hold_token.as_deref()).await?;
+    }
+    tx.commit().await?;
+    Ok(booking_id)
+}
@@ -1,77 +1,567 @@
 use axum::{
-    extract::Request,
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{header::SET_COOKIE, HeaderMap, HeaderValue, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Json, Response},
 };
-use std::time::Instant;
-use tracing::debug;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sqlx::SqlitePool;
+use tracing::{debug, warn};
 
-/// Bot detection middleware for HTTP requests
+use crate::api_keys::{self, ApiKeyError, ApiKeyGrant, ApiScope, PUBLIC_SCOPES};
+use crate::agents::{self, NonceCache, SignatureError, SignedAgent};
+use crate::config::DetectionConfig;
+use crate::query_tracking::QueryPattern;
+use crate::sessions::{self, OperationFields, SessionSource, SessionStore, SESSION_HEADER};
+
+/// Clients whose last request time is remembered for the timing signal
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// User-Agent fragments of HTTP libraries, crawlers and automation tools
+const AUTOMATION_AGENTS: &[&str] = &[
+    "bot", "crawler", "spider", "curl", "wget", "python", "httpx", "aiohttp", "go-http-client",
+    "java/", "okhttp", "node-fetch", "axios", "undici", "headless", "playwright", "puppeteer",
+    "selenium", "phantomjs", "postman",
+];
+
+/// Headers every mainstream browser sends with API requests
+const BROWSER_HEADERS: &[&str] = &["accept", "accept-language", "accept-encoding", "sec-fetch-mode"];
+
+/// Header pairs Chrome, Firefox and Safari all send in this order. HTTP
+/// libraries set their headers in their own order, e.g. `Accept-Encoding`
+/// before `Accept`.
+const BROWSER_HEADER_ORDER: &[(&str, &str)] =
+    &[("accept", "accept-language"), ("accept", "accept-encoding"), ("user-agent", "accept-encoding")];
+
+/// Shared state of the bot detection middleware
+#[derive(Clone)]
+pub struct Detector {
+    config: DetectionConfig,
+    /// Peers whose `X-Forwarded-For` header names the client
+    trusted_proxies: Vec<IpAddr>,
+    pool: SqlitePool,
+    sessions: SessionStore,
+    nonces: NonceCache,
+    last_seen: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Detector {
+    pub fn new(config: DetectionConfig, trusted_proxies: Vec<IpAddr>, pool: SqlitePool, sessions: SessionStore) -> Self {
+        Detector {
+            config,
+            trusted_proxies,
+            pool,
+            sessions,
+            nonces: NonceCache::default(),
+            last_seen: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Record a request from `client` and return the time since its previous one
+    fn observe(&self, client: String, now: Instant) -> Option<Duration> {
+        let mut last_seen = self.last_seen.lock().unwrap();
+        if last_seen.len() >= MAX_TRACKED_CLIENTS {
+            let horizon = Duration::from_millis(self.config.burst_interval_ms);
+            last_seen.retain(|_, seen| now.duration_since(*seen) < horizon);
+        }
+        last_seen.insert(client, now).map(|previous| now.duration_since(previous))
+    }
+}
+
+/// Bot detection middleware for HTTP requests.
+/// Also tracks the request in its session and hands the session id back to the client.
 pub async fn bot_detection_middleware(
+    State(detector): State<Detector>,
     request: Request,
     next: Next,
 ) -> Response {
-    // Extract bot detection headers
-    let bot_confidence = request
-        .headers()
-        .get("X-Bot-Confidence")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|v| v.parse::<f32>().ok())
-        .unwrap_or(0.5);
-
-    let agent_type = request
-        .headers()
-        .get("X-User-Agent-Type")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("unknown");
-
-    // Store in request extensions for use in the GraphQL resolvers
-    let bot_info = BotInfo {
-        confidence_score: bot_confidence,
-        agent_type: agent_type.to_string(),
-        request_start: Instant::now(),
+    let (request, signed) = match verify_signature(&detector.pool, &detector.nonces, request).await {
+        Ok(verified) => verified,
+        Err(response) => return response,
     };
+    let signer = signed.as_ref().map(|agent| agent.agent_id.as_str());
+    let api_key = match check_api_key(&detector.pool, request.headers(), signer).await {
+        Ok(api_key) => api_key,
+        Err(response) => return response,
+    };
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0);
+    let client_ip = client_ip(request.headers(), peer, &detector.trusted_proxies);
+    let client = client_key(request.headers(), &client_ip);
+    let (session_id, session_source) = detector.sessions.session_id(request.headers());
+    let since_last = detector.observe(client, Instant::now());
+    let behavior_score = detector.sessions.behavior_score(&session_id);
+    let query_pattern = detector.sessions.query_pattern(&session_id);
+    let observations = Observations { since_last, behavior_score, query_pattern };
+    let mut bot_info = score_request(request.headers(), &detector.config, &observations);
+    bot_info.session_id = session_id.clone();
+    bot_info.client_ip = client_ip;
+    match signed {
+        Some(agent) => {
+            bot_info.verified = true;
+            bot_info.agent_id = Some(agent.agent_id);
+            bot_info.agent_scopes = agent.scopes;
+        }
+        None => bot_info.agent_id = api_key.as_ref().map(|key| key.agent_id.clone()),
+    }
+    bot_info.api_key = api_key;
+    let path = request.uri().path().to_string();
+    // A session id issued just now only gets state once the client sends it back,
+    // so clients that drop the cookie cannot fill the store
+    let sequence = (session_source != SessionSource::Issued)
+        .then(|| detector.sessions.begin(&session_id, request.method().as_str(), &path, bot_info.confidence_score));
+    let session = detector.sessions.snapshot(&session_id);
+    if let Some(session) = &session {
+        bot_info.intelligence_level = session.intelligence_level;
+    }
 
     // Log for debugging
     debug!(
-        "Bot detection: path={}, confidence={}, agent_type={}, is_bot={}",
+        "Bot detection: path={}, confidence={:.2}, agent_type={}, is_bot={}, level={}, signals={:?}",
         request.uri().path(),
-        bot_confidence, 
-        agent_type,
-        bot_info.is_likely_bot()
+        bot_info.confidence_score,
+        bot_info.agent_type,
+        bot_info.is_likely_bot(),
+        bot_info.intelligence_level.as_str(),
+        bot_info.signals
     );
 
-    // Create a new request with bot info
+    // Store in request extensions for use in the GraphQL resolvers
     let mut modified_request = request;
     modified_request.extensions_mut().insert(bot_info);
+    if let Some(session) = session {
+        modified_request.extensions_mut().insert(session);
+    }
 
     // Process request
-    let response = next.run(modified_request).await;
-    
-    // Return response
+    let mut response = next.run(modified_request).await;
+
+    let fields = response.extensions_mut().remove::<OperationFields>().unwrap_or_default();
+    let bot_api = header(response.headers(), API_VARIANT_HEADER) == Some(ApiVariant::Bot.as_str())
+        || response.extensions().get::<BotEndpoint>().is_some();
+    if let Some(sequence) = sequence {
+        detector.sessions.finish(&session_id, sequence, fields.0, bot_api);
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&session_id) {
+        response.headers_mut().insert(SESSION_HEADER, value);
+    }
+    if session_source == SessionSource::Issued {
+        if let Ok(cookie) = HeaderValue::from_str(&sessions::set_cookie(&session_id)) {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+    }
+    response
+}
+
+/// Marks responses of the bot endpoints, whose use counts towards a session's
+/// bot API usage; routes opt in with [`mark_bot_endpoint`]
+#[derive(Clone, Copy, Debug)]
+pub struct BotEndpoint;
+
+/// Response mapper for the routes of bot endpoints
+pub async fn mark_bot_endpoint(mut response: Response) -> Response {
+    response.extensions_mut().insert(BotEndpoint);
     response
 }
 
+/// Check the HTTP message signature of a signed request, buffering its body
+/// for the digest. Unsigned requests pass through; bad or replayed signatures
+/// get a 401, and a full nonce cache a 503.
+async fn verify_signature(
+    pool: &SqlitePool,
+    nonces: &NonceCache,
+    request: Request,
+) -> Result<(Request, Option<SignedAgent>), Response> {
+    if !request.headers().contains_key("signature-input") {
+        return Ok((request, None));
+    }
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, agents::MAX_SIGNED_BODY)
+        .await
+        .map_err(|err| (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response())?;
+    match agents::verify_request(pool, nonces, &parts.method, parts.uri.path(), &parts.headers, &body).await {
+        Ok(signed) => Ok((Request::from_parts(parts, Body::from(body)), signed)),
+        Err(err) => {
+            debug!("Rejected signed request to {}: {}", parts.uri.path(), err);
+            let status = match err {
+                SignatureError::TooManyNonces => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::UNAUTHORIZED,
+            };
+            Err((status, Json(serde_json::json!({ "error": err.to_string() }))).into_response())
+        }
+    }
+}
+
+/// Validate the API key a request presents. Requests without one pass through;
+/// unknown or revoked keys, or keys of another agent than the signer, get a 401.
+async fn check_api_key(
+    pool: &SqlitePool,
+    headers: &HeaderMap,
+    signer: Option<&str>,
+) -> Result<Option<ApiKeyGrant>, Response> {
+    let Some(key) = api_keys::presented_key(headers) else {
+        return Ok(None);
+    };
+    let unauthorized = |err: ApiKeyError| {
+        (StatusCode::UNAUTHORIZED, Json(serde_json::json!({ "error": err.to_string() }))).into_response()
+    };
+    match api_keys::validate(pool, key).await {
+        Ok(Ok(grant)) if signer.is_some_and(|agent| agent != grant.agent_id) => {
+            Err(unauthorized(ApiKeyError::AgentMismatch))
+        }
+        Ok(Ok(grant)) => Ok(Some(grant)),
+        Ok(Err(err)) => Err(unauthorized(err)),
+        Err(err) => {
+            warn!("API key lookup failed: {}", err);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": "API key lookup failed" })))
+                .into_response())
+        }
+    }
+}
+
+/// Score of a single detection signal, from 0 (human-like) to 1 (bot-like)
+#[derive(Clone, Debug)]
+pub struct SignalScore {
+    pub name: &'static str,
+    pub score: f32,
+    pub weight: f32,
+    pub detail: String,
+}
+
 /// Information about bot detection for the current request
 #[derive(Clone, Debug)]
 pub struct BotInfo {
+    /// Weighted average of the signal scores
     pub confidence_score: f32,
+    /// Type the client declared in `X-User-Agent-Type`, or `unknown`
     pub agent_type: String,
-    pub request_start: Instant,
+    /// Session the request belongs to, see [`crate::sessions`]
+    pub session_id: String,
+    /// Address of the client, see [`client_ip`]
+    pub client_ip: String,
+    /// Level of the session, as classified before this request ran
+    pub intelligence_level: IntelligenceLevel,
+    /// Whether the request carried a valid signature from a registered agent
+    pub verified: bool,
+    /// Registered agent that signed the request or owns its API key
+    pub agent_id: Option<String>,
+    /// Scopes granted to the agent that signed the request
+    pub agent_scopes: Vec<ApiScope>,
+    /// Validated API key sent with the request
+    pub api_key: Option<ApiKeyGrant>,
+    /// Configured confidence at which the client counts as a bot
+    pub bot_threshold: f32,
+    /// Per-signal breakdown of `confidence_score`
+    pub signals: Vec<SignalScore>,
 }
 
 impl BotInfo {
+    /// A signature counts as the client declaring itself an agent, which only
+    /// picks the bot API; anyone may register a key, so it grants no trust.
+    /// What clients claim otherwise only counts through the weighted
+    /// client-hint signal
     pub fn is_likely_bot(&self) -> bool {
-        self.confidence_score >= 0.55 || self.agent_type == "bot"
+        self.confidence_score >= self.bot_threshold || self.verified
+    }
+
+    /// An API key limits the request to the key's scopes. Otherwise anonymous
+    /// requests may do what the web app can, and signed ones also what their
+    /// agent is granted.
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        match &self.api_key {
+            Some(key) => key.scopes.contains(&scope),
+            None => PUBLIC_SCOPES.contains(&scope) || self.agent_scopes.contains(&scope),
+        }
+    }
+}
+
+/// Bot intelligence level from the project brief
+#[derive(async_graphql::Enum, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum IntelligenceLevel {
+    /// Low score and no bot API usage
+    #[default]
+    L0,
+    /// Moderate score or minimal bot API usage
+    L1,
+    /// High score with repeated bot API usage
+    L2,
+}
+
+impl IntelligenceLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IntelligenceLevel::L0 => "L0",
+            IntelligenceLevel::L1 => "L1",
+            IntelligenceLevel::L2 => "L2",
+        }
+    }
+}
+
+impl std::str::FromStr for IntelligenceLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "L0" => Ok(IntelligenceLevel::L0),
+            "L1" => Ok(IntelligenceLevel::L1),
+            "L2" => Ok(IntelligenceLevel::L2),
+            other => Err(format!("Unknown intelligence level '{}'", other)),
+        }
+    }
+}
+
+/// Fields only the bot schema offers; using them counts toward the intelligence level
+pub const BOT_ONLY_FIELDS: &[&str] = &[
+    "requestExplanation",
+    "offerInsights",
+    "negotiateOffer",
+    "counterOffer",
+    "acceptNegotiation",
+    "submitIntent",
+    "getStructuredBooking",
+];
+
+/// Classify a client the way the frontend detector does: half bot score,
+/// 30% navigation pattern and 20% use of bot-only fields (saturating at three)
+pub fn classify_intelligence(confidence: f32, navigation: f64, bot_field_uses: u64) -> IntelligenceLevel {
+    let usage = (bot_field_uses as f64 / 3.0).min(1.0);
+    let combined = f64::from(confidence) * 0.5 + navigation.clamp(0.0, 1.0) * 0.3 + usage * 0.2;
+    if combined >= 0.7 {
+        IntelligenceLevel::L2
+    } else if combined >= 0.4 {
+        IntelligenceLevel::L1
+    } else {
+        IntelligenceLevel::L0
     }
 }
 
-/// Route selection based on bot detection
-pub fn should_use_bot_api(bot_info: &BotInfo) -> bool {
-    // Use bot-specific endpoints if:
-    // 1. Confidence score is high enough
-    // 2. Client explicitly identifies as a bot
-    
-    // For this implementation, we'll use the is_likely_bot method
-    bot_info.is_likely_bot()
-} 
\ No newline at end of file
+/// Header a client sends to pick a schema variant on `/graphql`, and that
+/// every GraphQL response carries to say which variant served it
+pub const API_VARIANT_HEADER: &str = "x-api-variant";
+
+/// Schema variant serving a GraphQL request
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ApiVariant {
+    Human,
+    Bot,
+}
+
+impl ApiVariant {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVariant::Human => "human",
+            ApiVariant::Bot => "bot",
+        }
+    }
+
+    /// Variant explicitly requested through the `X-Api-Variant` header
+    pub fn requested(headers: &HeaderMap) -> Option<ApiVariant> {
+        header(headers, API_VARIANT_HEADER).and_then(|v| v.parse().ok())
+    }
+}
+
+impl std::str::FromStr for ApiVariant {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "human" => Ok(ApiVariant::Human),
+            "bot" => Ok(ApiVariant::Bot),
+            other => Err(format!("Unknown API variant '{}'", other)),
+        }
+    }
+}
+
+/// Route selection based on bot detection.
+/// An explicitly requested variant wins, so bots can opt out of the bot schema.
+pub fn should_use_bot_api(bot_info: &BotInfo, requested: Option<ApiVariant>) -> bool {
+    match requested {
+        Some(variant) => variant == ApiVariant::Bot,
+        None => bot_info.is_likely_bot(),
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Client address: the peer address, unless the peer is a trusted proxy.
+/// `X-Forwarded-For` is then read from the right, each trusted proxy naming
+/// the hop before it, up to the first address that is not a trusted proxy.
+/// Entries further left are written by the client and ignored.
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trusted_proxies: &[IpAddr]) -> String {
+    let Some(mut client) = peer.map(|p| p.ip()) else {
+        return String::new();
+    };
+    if trusted_proxies.contains(&client) {
+        let forwarded = headers.get_all("x-forwarded-for").iter().filter_map(|v| v.to_str().ok());
+        let hops: Vec<&str> = forwarded.flat_map(|list| list.split(',')).collect();
+        for hop in hops.into_iter().rev() {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !trusted_proxies.contains(&ip) {
+                break;
+            }
+        }
+    }
+    client.to_string()
+}
+
+/// Key identifying a client for the timing signal: client address plus User-Agent
+fn client_key(headers: &HeaderMap, client_ip: &str) -> String {
+    format!("{}|{}", client_ip, header(headers, "user-agent").unwrap_or_default())
+}
+
+fn user_agent_signal(headers: &HeaderMap) -> (f32, String) {
+    let Some(agent) = header(headers, "user-agent").filter(|a| !a.trim().is_empty()) else {
+        return (1.0, "no User-Agent".to_string());
+    };
+    let lower = agent.to_ascii_lowercase();
+    if let Some(tool) = AUTOMATION_AGENTS.iter().find(|tool| lower.contains(*tool)) {
+        return (1.0, format!("automation agent '{}'", tool));
+    }
+    let browser = lower.starts_with("mozilla/5.0")
+        && ["chrome/", "firefox/", "safari/", "edg/"].iter().any(|b| lower.contains(b));
+    if browser {
+        (0.05, "browser User-Agent".to_string())
+    } else {
+        (0.7, "unrecognized User-Agent".to_string())
+    }
+}
+
+/// Share of the [`BROWSER_HEADER_ORDER`] pairs present in the request that
+/// arrive out of order; requests with none of the pairs get no evidence
+/// either way, their missing headers are scored separately
+fn header_order_signal(headers: &HeaderMap) -> (f32, String) {
+    let names: Vec<&str> = headers.keys().map(|k| k.as_str()).collect();
+    let position = |name: &str| names.iter().position(|n| *n == name);
+    let pairs: Vec<(&str, &str, bool)> = BROWSER_HEADER_ORDER
+        .iter()
+        .filter_map(|(first, second)| Some((*first, *second, position(first)? < position(second)?)))
+        .collect();
+    if pairs.is_empty() {
+        return (0.5, "no headers to order".to_string());
+    }
+    let reversed: Vec<String> = pairs
+        .iter()
+        .filter(|(_, _, ordered)| !ordered)
+        .map(|(first, second, _)| format!("{} after {}", first, second))
+        .collect();
+    let score = reversed.len() as f32 / pairs.len() as f32;
+    let detail = if reversed.is_empty() { "browser header order".to_string() } else { reversed.join(", ") };
+    (score, detail)
+}
+
+fn missing_headers_signal(headers: &HeaderMap) -> (f32, String) {
+    let missing: Vec<&str> = BROWSER_HEADERS.iter().copied().filter(|h| !headers.contains_key(*h)).collect();
+    let score = missing.len() as f32 / BROWSER_HEADERS.len() as f32;
+    let detail = if missing.is_empty() { "none missing".to_string() } else { format!("missing {}", missing.join(", ")) };
+    (score, detail)
+}
+
+fn timing_signal(since_last: Option<Duration>, burst_interval: Duration) -> (f32, String) {
+    match since_last {
+        None => (0.3, "first request".to_string()),
+        Some(gap) if gap < burst_interval / 4 => (1.0, format!("{}ms since previous request", gap.as_millis())),
+        Some(gap) if gap < burst_interval => (0.6, format!("{}ms since previous request", gap.as_millis())),
+        Some(gap) => (0.0, format!("{}ms since previous request", gap.as_millis())),
+    }
+}
+
+fn cookie_signal(headers: &HeaderMap) -> (f32, String) {
+    if headers.contains_key("cookie") {
+        (0.0, "cookies present".to_string())
+    } else {
+        (0.6, "no cookies".to_string())
+    }
+}
+
+/// Stored behavior reports of the session; without any the signal carries no weight
+fn behavior_signal(behavior_score: Option<f64>, weight: f32) -> (f32, (f32, String)) {
+    match behavior_score {
+        Some(score) => (weight, (score.clamp(0.0, 1.0) as f32, format!("session behavior score {:.2}", score))),
+        None => (0.0, (0.0, "no behavior reports".to_string())),
+    }
+}
+
+/// Shape and cadence of the session's GraphQL queries; no weight before its first query
+fn graphql_signal(pattern: Option<&QueryPattern>, weight: f32) -> (f32, (f32, String)) {
+    match pattern {
+        Some(pattern) => (weight, (pattern.score, pattern.detail.clone())),
+        None => (0.0, (0.0, "no GraphQL queries".to_string())),
+    }
+}
+
+/// The client's own `X-Bot-Confidence` estimate, else the type it declares
+/// in `X-User-Agent-Type`; advisory only
+fn client_hint_signal(headers: &HeaderMap, default_confidence: f32) -> (f32, String) {
+    if let Some(hint) = header(headers, "x-bot-confidence").and_then(|v| v.trim().parse::<f32>().ok()) {
+        if hint.is_finite() {
+            return (hint.clamp(0.0, 1.0), format!("client reported {}", hint));
+        }
+    }
+    match header(headers, "x-user-agent-type").map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+        Some("bot") => (1.0, "client declared a bot".to_string()),
+        Some("human") => (0.0, "client declared a human".to_string()),
+        _ => (default_confidence, "no client hint".to_string()),
+    }
+}
+
+/// What the server knows about a client beyond the headers of the current request
+#[derive(Clone, Debug, Default)]
+pub struct Observations {
+    /// Time since the same client's previous request
+    pub since_last: Option<Duration>,
+    /// Aggregated score of the session's stored behavior reports
+    pub behavior_score: Option<f64>,
+    /// Most bot-like pattern in the session's recent GraphQL queries
+    pub query_pattern: Option<QueryPattern>,
+}
+
+/// Score a request from its headers and what is known about the client
+pub fn score_request(headers: &HeaderMap, config: &DetectionConfig, observations: &Observations) -> BotInfo {
+    let weights = &config.weights;
+    let burst_interval = Duration::from_millis(config.burst_interval_ms);
+    let (behavior_weight, behavior) = behavior_signal(observations.behavior_score, weights.behavior);
+    let (graphql_weight, graphql) = graphql_signal(observations.query_pattern.as_ref(), weights.graphql);
+    let signals: Vec<SignalScore> = [
+        ("user_agent", weights.user_agent, user_agent_signal(headers)),
+        ("header_order", weights.header_order, header_order_signal(headers)),
+        ("missing_headers", weights.missing_headers, missing_headers_signal(headers)),
+        ("timing", weights.timing, timing_signal(observations.since_last, burst_interval)),
+        ("cookies", weights.cookies, cookie_signal(headers)),
+        ("client_hint", weights.client_hint, client_hint_signal(headers, config.default_confidence)),
+        ("behavior", behavior_weight, behavior),
+        ("graphql", graphql_weight, graphql),
+    ]
+    .into_iter()
+    .map(|(name, weight, (score, detail))| SignalScore { name, score, weight, detail })
+    .collect();
+
+    let total_weight: f32 = signals.iter().map(|s| s.weight).sum();
+    let confidence_score = if total_weight > 0.0 {
+        signals.iter().map(|s| s.score * s.weight).sum::<f32>() / total_weight
+    } else {
+        config.default_confidence
+    };
+
+    BotInfo {
+        confidence_score,
+        agent_type: header(headers, "x-user-agent-type").unwrap_or("unknown").to_string(),
+        session_id: String::new(),
+        client_ip: String::new(),
+        intelligence_level: IntelligenceLevel::default(),
+        verified: false,
+        agent_id: None,
+        agent_scopes: Vec::new(),
+        api_key: None,
+        bot_threshold: config.bot_threshold,
+        signals,
+    }
+}
@@ -1,17 +1,39 @@
-use async_graphql::{Context, InputObject, Object, SimpleObject};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{Context, Enum, ErrorExtensions, InputObject, MergedObject, Object, SimpleObject};
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use tracing::info;
 
-use crate::schema::FlightOffer;
+use crate::airports::AirportLoader;
+use crate::api_keys::{ApiScope, ScopeGuard, ALL_SCOPES};
+use crate::booking::Owner;
+use crate::bot_detection::{BotInfo, IntelligenceLevel};
+use crate::schema::{self, BookingConfirmation, Cabin, FlightOffer, MutationRoot, PassengerInput, QueryRoot, SeatSelectionInput};
+use crate::config::NegotiationConfig;
+use crate::errors::ApiError;
+use crate::addons::round_cents;
+use crate::sessions::SessionStore;
+use crate::{agents, airports, api_keys, behavior, booking, fare_rules, intents, negotiation, passengers, price_history, seatmap};
 
-/// Bot-specific intent data
+/// Bot-specific intent data. REST bodies use the same camelCase names as GraphQL;
+/// the snake_case names older clients sent are still accepted.
 #[derive(InputObject, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct BotIntent {
+    #[serde(alias = "intent_type")]
     pub intent_type: String,
+    #[serde(alias = "query_params")]
     pub query_params: Option<serde_json::Value>,
     pub reason: Option<String>,
+    #[serde(alias = "additional_context")]
     pub additional_context: Option<serde_json::Value>,
+    /// Search the intent relates to, e.g. a trip id
+    #[serde(alias = "search_id")]
+    pub search_id: Option<String>,
+    #[serde(alias = "offer_id")]
+    pub offer_id: Option<String>,
+    #[serde(alias = "booking_id")]
+    pub booking_id: Option<i64>,
 }
 
 /// Bot intent record stored in the database
@@ -24,24 +46,197 @@ pub struct BotIntentRecord {
     pub query_params: Option<String>,
     pub reason: Option<String>,
     pub additional_context: Option<String>,
+    pub search_id: Option<String>,
+    pub offer_id: Option<String>,
+    pub booking_id: Option<i64>,
+    /// Session the intent was submitted from
+    pub session_id: Option<String>,
+    /// Intelligence level of that session when the intent was submitted
+    pub intelligence_level: Option<String>,
+    /// Agent that submitted the intent, if it signed the request or sent an API key
+    pub agent_id: Option<String>,
     pub recorded_time: String,
 }
 
-/// Explanation for a flight offer
+/// One weighted signal behind the bot-detection score
+#[derive(SimpleObject)]
+pub struct DetectionSignal {
+    pub name: String,
+    /// 0 is human-like, 1 is bot-like
+    pub score: f32,
+    pub weight: f32,
+    pub detail: String,
+}
+
+/// Agent registered for signed requests; `agentId` is the signature `keyid`
+#[derive(SimpleObject, Clone, Debug)]
+pub struct AgentRegistration {
+    pub agent_id: String,
+    pub name: String,
+}
+
+/// Newly issued API key. `apiKey` is shown only once; send it as
+/// `Authorization: Bearer <apiKey>` or `X-Api-Key`.
+#[derive(SimpleObject, Clone, Debug)]
+pub struct IssuedApiKey {
+    pub key_id: String,
+    pub api_key: String,
+    pub agent_id: String,
+    pub scopes: Vec<ApiScope>,
+}
+
+/// API key of an agent, without the key itself
+#[derive(SimpleObject, Clone, Debug)]
+pub struct ApiKeyInfo {
+    pub key_id: String,
+    pub scopes: Vec<ApiScope>,
+    pub label: Option<String>,
+    pub created_time: String,
+    pub revoked_time: Option<String>,
+}
+
+/// How the server classified the current request
+#[derive(SimpleObject)]
+pub struct DetectionReport {
+    pub confidence: f32,
+    pub is_bot: bool,
+    pub declared_agent_type: String,
+    /// Session the request was attributed to
+    pub session_id: String,
+    pub intelligence_level: IntelligenceLevel,
+    /// Whether the request was signed by a registered agent
+    pub verified: bool,
+    pub agent_id: Option<String>,
+    /// API key sent with the request
+    pub api_key_id: Option<String>,
+    /// Scopes the request may use
+    pub scopes: Vec<ApiScope>,
+    pub signals: Vec<DetectionSignal>,
+}
+
+/// Behavior reports of one browser session, averaged or summed
+#[derive(SimpleObject, Clone, Debug)]
+pub struct BehaviorAggregate {
+    pub session_id: String,
+    pub reports: i64,
+    /// Score derived from the averaged signals, 0 (human-like) to 1 (bot-like)
+    pub behavior_score: Option<f64>,
+    pub avg_client_confidence: Option<f64>,
+    pub avg_mouse_entropy: Option<f64>,
+    pub avg_typing_pattern: Option<f64>,
+    pub avg_navigation_pattern: Option<f64>,
+    pub avg_form_filling: Option<f64>,
+    pub avg_form_fill_time_ms: Option<f64>,
+    pub mouse_movements: i64,
+    pub key_presses: i64,
+    pub clicks: i64,
+    pub scrolls: i64,
+    pub form_interactions: i64,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/// A request recorded in a session's history
+#[derive(SimpleObject, Clone, Debug)]
+pub struct SessionRequest {
+    pub at: String,
+    pub method: String,
+    pub path: String,
+    pub confidence: f32,
+    /// Root GraphQL fields the request ran, empty for other endpoints
+    pub fields: Vec<String>,
+    /// Whether a bot endpoint or the bot schema answered the request
+    pub bot_api: bool,
+}
+
+/// Number of requests a session sent to one bot endpoint
+#[derive(SimpleObject, Clone, Debug)]
+pub struct EndpointUsage {
+    pub endpoint: String,
+    pub count: i64,
+}
+
+/// Shape of a GraphQL query a session ran
+#[derive(SimpleObject, Clone, Debug)]
+pub struct GraphqlQueryStats {
+    /// Hash of the normalised query text and variables
+    pub hash: String,
+    pub depth: i32,
+    pub complexity: i32,
+    pub fields: Vec<String>,
+    pub introspection: bool,
+    /// Why validation rejected the query; depth and complexity are then estimated
+    pub rejected: Option<String>,
+}
+
+/// State the server keeps for one client session
+#[derive(SimpleObject, Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    pub started_at: String,
+    pub last_seen_at: String,
+    pub request_count: i64,
+    /// Mean bot confidence over all requests of the session
+    pub cumulative_confidence: f32,
+    pub intelligence_level: IntelligenceLevel,
+    /// Calls to bot-only fields and intent submissions
+    pub bot_field_uses: i64,
+    /// Most recent requests, oldest first
+    pub history: Vec<SessionRequest>,
+    pub bot_endpoints: Vec<EndpointUsage>,
+    /// Recent GraphQL queries, oldest first
+    pub queries: Vec<GraphqlQueryStats>,
+    /// Score of the most bot-like pattern in `queries`
+    pub query_pattern_score: Option<f32>,
+    pub query_pattern: Option<String>,
+}
+
+/// Explanation for a flight offer, built from the fare rules of its cabin
 #[derive(SimpleObject, Serialize)]
 pub struct OfferExplanation {
     pub flight_id: i64,
+    pub fare_family: String,
+    /// Fare less the taxes in `taxComponents`
     pub base_fare: f64,
     pub taxes_fees: f64,
+    pub tax_components: Vec<TaxComponent>,
+    /// Cheapest fare on the route divided by this one
     pub comparative_value: f64,
     pub cancellation_policy: String,
     pub seat_details: SeatDetails,
     pub structured_explanation: serde_json::Value,
+    /// Rules every field above was derived from
+    pub sources: Vec<RuleSource>,
+}
+
+/// Tax or fee included in a fare
+#[derive(SimpleObject, Serialize, Clone)]
+pub struct TaxComponent {
+    pub code: String,
+    pub name: String,
+    /// Airport levying it; absent for taxes on every flight
+    pub airport: Option<String>,
+    pub amount: f64,
+    pub rule: String,
+}
+
+/// Rules an explanation field was derived from
+#[derive(SimpleObject, Serialize, Clone)]
+pub struct RuleSource {
+    /// Field name, with a `structuredExplanation.` prefix for keys of that object
+    pub field: String,
+    /// Rule ids such as `fare_family:ECONOMY_STANDARD` or `tax:US-SEGMENT`
+    pub rules: Vec<String>,
+    pub detail: String,
 }
 
 /// Seat details for flight offers
 #[derive(SimpleObject, Serialize)]
 pub struct SeatDetails {
+    /// Seat the details were derived from, when one was chosen
+    pub seat: Option<String>,
+    pub cabin: String,
+    pub exit_row: bool,
     pub pitch_inches: f32,
     pub width_inches: f32,
     pub recline_degrees: f32,
@@ -54,181 +249,435 @@ pub struct SeatDetails {
 pub struct OfferInsights {
     pub flight_id: i64,
     pub price_comparison: PriceComparison,
-    pub convenience_score: f32,
-    pub reliability_score: f32,
+    /// Other flights on the route departing within a few days, closest departure first
+    pub alternatives: Vec<AlternativeFlight>,
     pub structured_data: serde_json::Value,
 }
 
-/// Price comparison data
+/// Price comparison data, computed from the recorded price history
 #[derive(SimpleObject, Serialize)]
 pub struct PriceComparison {
+    /// Average of the prices recorded on the route for flights departing within a few days
     pub average_price: f64,
+    /// Share of those prices below this fare; lower is a better deal
     pub percentile: f32,
+    pub trend: PriceTrend,
+    /// Change of this fare since it was first recorded, in percent
+    pub trend_percent: f64,
+    /// Number of recorded prices compared
+    pub sample_size: i64,
+    /// Prices recorded for this flight, oldest first
     pub price_history: Vec<HistoricalPrice>,
 }
 
+/// Direction of a fare since it was first recorded
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize)]
+pub enum PriceTrend {
+    Rising,
+    Falling,
+    Stable,
+}
+
 /// Historical price point
 #[derive(SimpleObject, Serialize)]
 pub struct HistoricalPrice {
     pub date: String,
     pub price: f64,
+    /// What recorded the price: `listed`, `change` or `search`
+    pub source: String,
+}
+
+/// Another flight on the same route
+#[derive(SimpleObject, Serialize)]
+pub struct AlternativeFlight {
+    pub flight_id: i64,
+    pub departure_time: String,
+    pub price: f64,
+    /// Its price minus this fare
+    pub price_difference: f64,
+    /// Minutes it departs after this flight; negative when earlier
+    pub time_difference_minutes: i64,
+}
+
+/// State of a price negotiation
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum NegotiationStatus {
+    /// Waiting for a counter-offer or acceptance
+    Open,
+    /// A price was agreed and an offer issued for it
+    Agreed,
+    /// The last round passed without agreement
+    Closed,
+    /// Not concluded before it expired
+    Expired,
+}
+
+impl NegotiationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NegotiationStatus::Open => "OPEN",
+            NegotiationStatus::Agreed => "AGREED",
+            NegotiationStatus::Closed => "CLOSED",
+            NegotiationStatus::Expired => "EXPIRED",
+        }
+    }
+}
+
+impl std::str::FromStr for NegotiationStatus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "OPEN" => Ok(NegotiationStatus::Open),
+            "AGREED" => Ok(NegotiationStatus::Agreed),
+            "CLOSED" => Ok(NegotiationStatus::Closed),
+            "EXPIRED" => Ok(NegotiationStatus::Expired),
+            other => Err(format!("Unknown negotiation status '{}'", other)),
+        }
+    }
+}
+
+/// One counter-offer and the asking price it was answered with
+#[derive(SimpleObject, Clone, Debug)]
+pub struct NegotiationRound {
+    pub round: i64,
+    pub proposed_price: f64,
+    pub asking_price: f64,
+    pub accepted: bool,
+}
+
+/// Price negotiation over the fare of a flight; prices are per seated passenger
+#[derive(SimpleObject, Clone, Debug)]
+pub struct Negotiation {
+    pub negotiation_id: String,
+    pub flight_id: i64,
+    pub cabin: Cabin,
+    pub passengers: i64,
+    pub status: NegotiationStatus,
+    /// Policy rule that set the opening and floor prices
+    pub rule: String,
+    pub list_price: f64,
+    pub asking_price: f64,
+    pub agreed_price: Option<f64>,
+    /// Agreed price, or else the asking price, for every passenger
+    pub total_price: f64,
+    pub rounds_left: i64,
+    pub rounds: Vec<NegotiationRound>,
+    /// Offer bookFlight books at the agreed price, once agreed
+    pub offer_id: Option<String>,
+    pub offer_expires_at: Option<String>,
+    /// When the negotiation closes if no price is agreed
+    pub expires_at: String,
+}
+
+/// Agent that signed the request; key management needs the agent's private key
+fn signing_agent<'a>(ctx: &'a Context<'_>, operation: &'static str) -> async_graphql::Result<&'a str> {
+    ctx.data_opt::<BotInfo>()
+        .filter(|info| info.verified)
+        .and_then(|info| info.agent_id.as_deref())
+        .ok_or_else(|| ApiError::AgentNotVerified { operation }.extend())
+}
+
+/// Intelligence level of the caller; requests without detection data count as L0
+fn intelligence_level(ctx: &Context<'_>) -> IntelligenceLevel {
+    ctx.data_opt::<BotInfo>().map(|info| info.intelligence_level).unwrap_or_default()
 }
 
-/// Root Query type for Bot-specific GraphQL
+/// Query fields only offered to bots; the shared fields come from [`QueryRoot`]
+#[derive(Default)]
 pub struct BotQueryRoot;
 
 #[Object]
 impl BotQueryRoot {
-    /// Search flights by origin, destination, and (optional) dates
-    /// Same as the regular schema, but with structured data for bots
-    #[graphql(name = "searchFlights")]
-    async fn search_flights(
+    /// Request structured explanation of a flight offer
+    ///
+    /// Seat details come from `seat`, or the seat assigned on `bookingId`,
+    /// falling back to a standard economy seat.
+    #[graphql(name = "requestExplanation", guard = "ScopeGuard(ApiScope::Explain)")]
+    async fn request_explanation(
         &self,
         ctx: &Context<'_>,
-        origin: String,
-        destination: String,
-        dates: Vec<String>,
-    ) -> async_graphql::Result<Vec<FlightOffer>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        
-        // Log the bot search
-        info!("Bot searching flights: {} to {}, dates: {:?}", origin, destination, dates);
-        
-        let flights = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE origin = ? AND destination = ?",
-        )
-        .bind(origin)
-        .bind(destination)
-        .fetch_all(pool)
-        .await?;
-        
-        Ok(flights)
-    }
-    
-    /// Request structured explanation of a flight offer
-    #[graphql(name = "requestExplanation")]
-    async fn request_explanation(&self, ctx: &Context<'_>, flight_id: i64) -> async_graphql::Result<OfferExplanation> {
+        flight_id: i64,
+        booking_id: Option<i64>,
+        seat: Option<String>,
+    ) -> async_graphql::Result<OfferExplanation> {
         let pool = ctx.data::<SqlitePool>()?;
         
         // Fetch the flight data
-        let flight = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
-        )
-        .bind(flight_id)
-        .fetch_one(pool)
-        .await?;
+        let flight = booking::fetch_flight(pool, flight_id).await?;
+
+        let seat = match (seat, booking_id) {
+            (Some(seat), _) => Some(seat),
+            (None, Some(booking_id)) => seatmap::assigned_seat(pool, booking_id, flight_id).await?,
+            (None, None) => None,
+        };
+        let aircraft = seatmap::aircraft_for_flight(pool, flight_id).await?;
+        let seat_details = match seat {
+            Some(seat) => {
+                let info = seatmap::locate_seat(&aircraft, flight_id, &seat)?;
+                SeatDetails {
+                    seat: Some(info.seat),
+                    cabin: info.layout.cabin.clone(),
+                    exit_row: info.exit_row,
+                    pitch_inches: info.layout.pitch_inches as f32,
+                    width_inches: info.layout.width_inches as f32,
+                    recline_degrees: info.layout.recline_degrees as f32,
+                    has_power: info.layout.has_power,
+                    has_wifi: info.has_wifi,
+                }
+            }
+            None => {
+                let layout = aircraft
+                    .cabins
+                    .iter()
+                    .find(|c| c.cabin == Cabin::Economy.as_str())
+                    .or(aircraft.cabins.first())
+                    .ok_or_else(|| async_graphql::Error::new(format!("Flight {} has no seat configuration", flight_id)))?;
+                SeatDetails {
+                    seat: None,
+                    cabin: layout.cabin.clone(),
+                    exit_row: false,
+                    pitch_inches: layout.pitch_inches as f32,
+                    width_inches: layout.width_inches as f32,
+                    recline_degrees: layout.recline_degrees as f32,
+                    has_power: layout.has_power,
+                    has_wifi: aircraft.has_wifi,
+                }
+            }
+        };
         
         // Log the explanation request
         info!("Bot requested explanation for flight {}", flight_id);
-        
-        // In a real implementation, this would generate dynamic explanations
-        // For now, return static data
+
+        let cabin: Cabin = seat_details.cabin.parse()?;
+        let rules = fare_rules::for_flight(pool, &flight, cabin).await?;
+        let fare = rules.fare(flight.price);
+        let (base_fare, tax_components) = rules.breakdown(fare)?;
+        let taxes_fees = round_cents(tax_components.iter().map(|t| t.amount).sum());
+        let (comparative_value, comparison) = fare_rules::comparative_value(pool, &flight, &rules).await?;
+        let loyalty_points = (base_fare * rules.family.loyalty_rate).floor() as i64;
+        let tax_rules: Vec<String> = tax_components.iter().map(|t| t.rule.clone()).collect();
+
+        let source = |field: &str, rules: Vec<String>, detail: String| RuleSource { field: field.to_string(), rules, detail };
+        let mut sources = vec![
+            source(
+                "fareFamily",
+                vec![rules.family_rule()],
+                format!("Fare family sold in the {} cabin", cabin.as_str()),
+            ),
+            source(
+                "baseFare",
+                std::iter::once(rules.family_rule()).chain(tax_rules.iter().cloned()).collect(),
+                format!(
+                    "Listed fare of {:.2} times the {} cabin multiplier of {}, less the included taxes",
+                    flight.price,
+                    cabin.as_str(),
+                    rules.family.fare_multiplier
+                ),
+            ),
+            source("taxesFees", tax_rules, format!("Sum of {} tax components", tax_components.len())),
+            comparison,
+            source("cancellationPolicy", vec![rules.refund_rule()], "Refund schedule of the fare family".to_string()),
+            source(
+                "seatDetails",
+                vec![format!("aircraft:{}", aircraft.code)],
+                format!("{} cabin layout of the {}", seat_details.cabin, aircraft.name),
+            ),
+            source(
+                "structuredExplanation.baggage_allowance",
+                vec![rules.baggage_rule()],
+                format!("Allowance of the {} cabin", cabin.as_str()),
+            ),
+            source(
+                "structuredExplanation.meal_service",
+                vec![rules.family_rule()],
+                format!("Meals {}included in {}", if rules.family.meal_included { "" } else { "not " }, rules.family.name),
+            ),
+            source(
+                "structuredExplanation.loyalty_points",
+                vec![rules.family_rule()],
+                format!("Base fare times {} points per unit", rules.family.loyalty_rate),
+            ),
+            source("structuredExplanation.change_fee", vec![rules.change_rule()], "Earliest change fee of the fare family".to_string()),
+        ];
+
+        let level = intelligence_level(ctx);
+        let mut structured_explanation = serde_json::json!({
+            "fare_class": cabin.as_str(),
+            "fare_family": rules.family.code,
+            "baggage_allowance": {
+                "carry_on": rules.baggage.carry_on,
+                "checked": rules.baggage.checked,
+                "weight_limit_kg": rules.baggage.weight_limit_kg
+            },
+            "meal_service": rules.family.meal_included,
+            "loyalty_points": loyalty_points,
+            "change_fee": rules.change_fee(),
+            "detail_level": level.as_str()
+        });
+        // L2 agents compare offers programmatically, so spell out the money flows
+        if level == IntelligenceLevel::L2 {
+            let mut components = vec![serde_json::json!({ "component": "base_fare", "amount": base_fare })];
+            components.extend(tax_components.iter().map(|t| {
+                serde_json::json!({ "component": t.code, "name": t.name, "amount": t.amount, "rule": t.rule })
+            }));
+            structured_explanation["price_components"] = serde_json::json!(components);
+            structured_explanation["refund_schedule"] = rules
+                .refunds
+                .iter()
+                .map(|(hours, percent)| serde_json::json!({ "hours_before_departure": hours, "refund_percent": percent }))
+                .collect();
+            structured_explanation["change_schedule"] = rules
+                .changes
+                .iter()
+                .map(|(hours, fee)| serde_json::json!({ "hours_before_departure": hours, "allowed": fee.is_some(), "fee": fee }))
+                .collect();
+            sources.push(source(
+                "structuredExplanation.refund_schedule",
+                vec![rules.refund_rule()],
+                "Refund rules of the fare family".to_string(),
+            ));
+            sources.push(source(
+                "structuredExplanation.change_schedule",
+                vec![rules.change_rule()],
+                "Change rules of the fare family".to_string(),
+            ));
+        }
         let explanation = OfferExplanation {
             flight_id: flight.id,
-            base_fare: flight.price * 0.85,
-            taxes_fees: flight.price * 0.15,
-            comparative_value: 0.78,
-            cancellation_policy: "Cancellable with 70% refund up to 24 hours before departure".to_string(),
-            seat_details: SeatDetails {
-                pitch_inches: 32.0,
-                width_inches: 18.5,
-                recline_degrees: 5.0,
-                has_power: true,
-                has_wifi: flight.price > 200.0,
-            },
-            structured_explanation: serde_json::json!({
-                "fare_class": "Economy",
-                "baggage_allowance": {
-                    "carry_on": 1,
-                    "checked": 1,
-                    "weight_limit_kg": 23
-                },
-                "meal_service": flight.price > 200.0,
-                "loyalty_points": (flight.price as i32) / 10,
-                "change_fee": (flight.price * 0.1) as i32
-            }),
+            fare_family: rules.family.name.clone(),
+            base_fare,
+            taxes_fees,
+            tax_components,
+            comparative_value,
+            cancellation_policy: rules.cancellation_policy(),
+            seat_details,
+            structured_explanation,
+            sources,
         };
-        
+
         Ok(explanation)
     }
-    
+
     /// Get comparative insights for a flight offer
-    #[graphql(name = "offerInsights")]
+    #[graphql(name = "offerInsights", guard = "ScopeGuard(ApiScope::Explain)")]
     async fn offer_insights(&self, ctx: &Context<'_>, flight_id: i64) -> async_graphql::Result<OfferInsights> {
         let pool = ctx.data::<SqlitePool>()?;
-        
-        // Fetch the flight data
-        let flight = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
-        )
-        .bind(flight_id)
-        .fetch_one(pool)
-        .await?;
-        
-        // Log the insights request
+        let flight = booking::fetch_flight(pool, flight_id).await?;
         info!("Bot requested insights for flight {}", flight_id);
-        
-        // Generate mock historical prices
-        let mut price_history = Vec::new();
-        let base_price = flight.price;
-        
-        // Create 5 historical price points
-        for i in 1..=5 {
-            let variance = 0.95 + (i as f64 * 0.02);
-            price_history.push(HistoricalPrice {
-                date: format!("2024-{:02}-01", i + 1),
-                price: base_price * variance,
-            });
-        }
-        
-        // In a real implementation, this would generate dynamic insights
+
+        let price_comparison = price_history::compare(pool, &flight).await?;
+        let alternatives = price_history::alternatives(pool, &flight).await?;
         let insights = OfferInsights {
             flight_id: flight.id,
-            price_comparison: PriceComparison {
-                average_price: base_price * 1.05,
-                percentile: 35.0, // Lower percentile = better deal
-                price_history,
-            },
-            convenience_score: 0.75,
-            reliability_score: 0.88,
             structured_data: serde_json::json!({
-                "delay_probability": 0.12,
-                "cancellation_risk": 0.03,
-                "airport_transfer_time": {
-                    "origin": 25,
-                    "destination": 30
-                },
-                "alternative_flights": [
-                    {
-                        "id": flight.id + 1,
-                        "price_difference": "+$35",
-                        "time_difference": "-45min"
-                    },
-                    {
-                        "id": flight.id - 1,
-                        "price_difference": "-$20",
-                        "time_difference": "+90min"
-                    }
-                ]
+                "price_trend": price_comparison.trend,
+                "alternative_flights": alternatives,
             }),
+            price_comparison,
+            alternatives,
         };
         
         Ok(insights)
     }
     
+    /// Server-side bot-detection result for this request, with a per-signal breakdown
+    #[graphql(name = "detectionReport")]
+    async fn detection_report(&self, ctx: &Context<'_>) -> async_graphql::Result<DetectionReport> {
+        let info = ctx
+            .data_opt::<BotInfo>()
+            .ok_or_else(|| async_graphql::Error::new("No detection data for this request"))?;
+        Ok(DetectionReport {
+            confidence: info.confidence_score,
+            is_bot: info.is_likely_bot(),
+            declared_agent_type: info.agent_type.clone(),
+            session_id: info.session_id.clone(),
+            intelligence_level: info.intelligence_level,
+            verified: info.verified,
+            agent_id: info.agent_id.clone(),
+            api_key_id: info.api_key.as_ref().map(|key| key.key_id.clone()),
+            scopes: ALL_SCOPES.iter().copied().filter(|scope| info.has_scope(*scope)).collect(),
+            signals: info
+                .signals
+                .iter()
+                .map(|s| DetectionSignal {
+                    name: s.name.to_string(),
+                    score: s.score,
+                    weight: s.weight,
+                    detail: s.detail.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    /// A negotiation opened with negotiateOffer, with every round so far
+    #[graphql(name = "negotiation", guard = "ScopeGuard(ApiScope::Negotiate)")]
+    async fn negotiation(&self, ctx: &Context<'_>, negotiation_id: String) -> async_graphql::Result<Negotiation> {
+        let pool = ctx.data::<SqlitePool>()?;
+        let config = ctx.data::<NegotiationConfig>()?;
+        let owner = Owner::of(ctx.data_opt::<BotInfo>());
+        negotiation::get(pool, config, &owner, &negotiation_id).await
+    }
+
+    /// The caller's session as of the start of this request
+    #[graphql(name = "currentSession")]
+    async fn current_session(&self, ctx: &Context<'_>) -> Option<Session> {
+        ctx.data_opt::<Session>().cloned()
+    }
+
+    /// Aggregated behavior reports of the caller's session. Other sessions
+    /// need the `read-behavior` scope.
+    #[graphql(name = "behaviorAggregate")]
+    async fn behavior_aggregate(
+        &self,
+        ctx: &Context<'_>,
+        session_id: Option<String>,
+    ) -> async_graphql::Result<Option<BehaviorAggregate>> {
+        let pool = ctx.data::<SqlitePool>()?;
+        let info = ctx.data_opt::<BotInfo>().ok_or_else(|| {
+            ApiError::Unauthorized { operation: "behaviorAggregate".to_string(), scope: ApiScope::ReadBehavior }.extend()
+        })?;
+        let session_id = session_id.unwrap_or_else(|| info.session_id.clone());
+        if session_id != info.session_id {
+            if let Some(err) = api_keys::check_scope(info, ApiScope::ReadBehavior, "behaviorAggregate") {
+                return Err(err.extend());
+            }
+        }
+        Ok(behavior::session_aggregate(pool, &session_id).await?)
+    }
+
+    /// Aggregates of the most recently active sessions; needs the `read-behavior` scope
+    #[graphql(name = "behaviorAggregates", guard = "ScopeGuard(ApiScope::ReadBehavior)")]
+    async fn behavior_aggregates(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 50)] limit: i64,
+    ) -> async_graphql::Result<Vec<BehaviorAggregate>> {
+        let pool = ctx.data::<SqlitePool>()?;
+        behavior::recent_aggregates(pool, limit).await
+    }
+
+    /// API keys of the signing agent, newest first
+    #[graphql(name = "apiKeys")]
+    async fn api_keys(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<ApiKeyInfo>> {
+        let pool = ctx.data::<SqlitePool>()?;
+        let agent_id = signing_agent(ctx, "apiKeys")?;
+        api_keys::list(pool, agent_id).await
+    }
+
     /// Get a booking with structured data for bots
-    #[graphql(name = "getStructuredBooking")]
+    #[graphql(name = "getStructuredBooking", guard = "ScopeGuard(ApiScope::Explain)")]
     async fn get_structured_booking(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<serde_json::Value> {
         let pool = ctx.data::<SqlitePool>()?;
+        booking::check_owner(pool, id, &Owner::of(ctx.data_opt::<BotInfo>())).await?;
         
         // Fetch the booking using the existing query
-        let (booking_id, flight_id, passenger_details, payment_details, booking_time): (i64, i64, String, String, String) =
-            sqlx::query_as(
-                "SELECT id, flight_id, passenger_details, payment_details, booking_time FROM bookings WHERE id = ?",
-            )
-            .bind(id)
-            .fetch_one(pool)
-            .await?;
+        let (booking_id, flight_id, booking_time): (i64, i64, String) =
+            sqlx::query_as("SELECT id, flight_id, booking_time FROM bookings WHERE id = ?")
+                .bind(id)
+                .fetch_one(pool)
+                .await?;
+        let payment = booking::payment_summary(pool, booking_id).await?;
             
         let flight = sqlx::query_as::<_, FlightOffer>(
             "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
@@ -236,25 +685,32 @@ impl BotQueryRoot {
         .bind(flight_id)
         .fetch_one(pool)
         .await?;
+        let (origin, destination) = airports::for_flight(ctx.data::<DataLoader<AirportLoader>>()?, &flight).await?;
         
         // Return structured JSON for easier bot consumption
         let structured_booking = serde_json::json!({
             "booking": {
                 "id": booking_id,
                 "created_at": booking_time,
-                "passenger": passenger_details,
-                // Mask payment details for security
-                "payment_last4": payment_details.chars().rev().take(4).collect::<String>().chars().rev().collect::<String>(),
+                "passengers": passengers::for_booking(pool, booking_id).await?,
+                "payment": {
+                    "brand": payment.brand,
+                    "last4": payment.last4,
+                    "status": payment.status.as_str(),
+                    "amount": payment.amount
+                },
             },
             "flight": {
                 "id": flight.id,
                 "route": {
                     "origin": {
                         "code": flight.origin,
+                        "time_zone": origin.as_ref().map(|airport| &airport.time_zone),
                         "departure_time": flight.departure_time
                     },
                     "destination": {
                         "code": flight.destination,
+                        "time_zone": destination.as_ref().map(|airport| &airport.time_zone),
                         "arrival_time": flight.arrival_time
                     }
                 },
@@ -264,8 +720,8 @@ impl BotQueryRoot {
                 }
             },
             "machine_readable": {
-                "duration_minutes": 180, // Mock value
-                "miles": 1250, // Mock value
+                "duration_minutes": airports::block_minutes(&flight)?,
+                "miles": origin.zip(destination).map(|(origin, destination)| airports::distance_miles(&origin, &destination)),
                 "carbon_offset_available": true
             }
         });
@@ -274,139 +730,150 @@ impl BotQueryRoot {
     }
 }
 
-/// Root Mutation type for Bot-specific GraphQL
+/// Mutation fields only offered to bots; the shared fields come from [`MutationRoot`]
+#[derive(Default)]
 pub struct BotMutationRoot;
 
 #[Object]
 impl BotMutationRoot {
-    /// Submit user intent data (search, booking, abandonment)
+    /// Submit user intent data (search, booking, abandonment) and return the stored intent id
     #[graphql(name = "submitIntent")]
-    async fn submit_intent(&self, _ctx: &Context<'_>, intent: BotIntent) -> async_graphql::Result<bool> {
+    async fn submit_intent(&self, ctx: &Context<'_>, intent: BotIntent) -> async_graphql::Result<i64> {
+        let pool = ctx.data::<SqlitePool>()?;
+
         // Log the intent data
         info!("Bot intent received: {:?}", intent);
-        
-        // In a production system, store in database
-        // For this demo, we just log it
-        
-        Ok(true)
+
+        intents::record(pool, ctx.data_opt::<BotInfo>(), &intent).await
     }
     
-    /// Submit behavior metrics from client-side tracking
+    /// Submit behavior metrics from client-side tracking and return the stored report id
     #[graphql(name = "submitBehaviorMetrics")]
-    async fn submit_behavior_metrics(&self, _ctx: &Context<'_>, metrics: serde_json::Value) -> async_graphql::Result<bool> {
+    async fn submit_behavior_metrics(&self, ctx: &Context<'_>, metrics: serde_json::Value) -> async_graphql::Result<i64> {
+        let pool = ctx.data::<SqlitePool>()?;
+
         // Log the metrics data
         info!("Bot behavior metrics: {}", metrics);
-        
-        // In a real implementation, this would be stored in a database
-        // For this demo, just log it
-        
-        Ok(true)
+
+        let sessions = ctx.data::<SessionStore>()?;
+        behavior::record(pool, sessions, ctx.data_opt::<BotInfo>(), &metrics).await
     }
     
-    /// Book a flight with passenger and payment details - bot optimized version
-    #[graphql(name = "bookFlight")]
+    /// Book a flight with passenger and payment details - bot optimized version.
+    /// Replaces the shared bookFlight so existing bots can keep sending a Float flightId.
+    #[graphql(name = "bookFlight", guard = "ScopeGuard(ApiScope::Book)")]
+    #[allow(clippy::too_many_arguments)]
     async fn book_flight(
         &self,
         ctx: &Context<'_>,
-        passenger_details: String,
+        passengers: Vec<PassengerInput>,
         payment: String,
-        flight_id: f64, // Note: Match the type from the frontend (Float)
-    ) -> async_graphql::Result<crate::schema::BookingConfirmation> {
+        #[graphql(deprecation = "Float ids are kept for existing bots; book an offerId from buildOffer instead")]
+        flight_id: Option<f64>,
+        trip_id: Option<String>,
+        offer_id: Option<String>,
+        #[graphql(desc = "Economy if omitted; must match the offer when booking one")] cabin: Option<Cabin>,
+        #[graphql(default)] seats: Vec<SeatSelectionInput>,
+    ) -> async_graphql::Result<BookingConfirmation> {
+        info!(
+            "Bot booking flight: id={:?}, trip={:?}, offer={:?}, passengers={}",
+            flight_id,
+            trip_id,
+            offer_id,
+            passengers.len()
+        );
+        let flight_id = match flight_id {
+            Some(id) if id.fract() != 0.0 => {
+                return Err(async_graphql::Error::new(format!("Invalid flightId {}", id)));
+            }
+            id => id.map(|id| id as i64),
+        };
+        let booking = schema::book(ctx, passengers, payment, flight_id, trip_id, offer_id, cabin, seats).await?;
+        info!("Bot booking {} confirmed, total {}", booking.booking_id, booking.total_price);
+        Ok(booking)
+    }
+
+    /// Register an Ed25519 public key (base64) for signing requests
+    #[graphql(name = "registerAgent")]
+    async fn register_agent(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        public_key: String,
+    ) -> async_graphql::Result<AgentRegistration> {
         let pool = ctx.data::<SqlitePool>()?;
-        
-        // Log the bot booking
-        info!("Bot booking flight: id={}, passenger={}", flight_id, passenger_details);
-        
-        let flight_id = flight_id as i64; // Convert to i64 for SQLite
-        
-        let mut tx = pool.begin().await?;
-        let result = sqlx::query(
-            "INSERT INTO bookings (flight_id, passenger_details, payment_details, booking_time) VALUES (?, ?, ?, datetime('now'))",
-        )
-        .bind(flight_id)
-        .bind(&passenger_details)
-        .bind(&payment)
-        .execute(&mut tx)
-        .await?;
-        
-        let booking_id = result.last_insert_rowid();
-        tx.commit().await?;
-        
-        let flight = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
-        )
-        .bind(flight_id)
-        .fetch_one(pool)
-        .await?;
-        
-        Ok(crate::schema::BookingConfirmation { booking_id, flight })
+        agents::register(pool, &name, &public_key).await
     }
-    
-    /// Simulate a negotiation with the booking system
-    #[graphql(name = "negotiateOffer")]
+
+    /// Issue an API key limited to `scopes`, which the agent must be granted;
+    /// requests must be signed by the agent
+    #[graphql(name = "issueApiKey")]
+    async fn issue_api_key(
+        &self,
+        ctx: &Context<'_>,
+        scopes: Vec<ApiScope>,
+        label: Option<String>,
+    ) -> async_graphql::Result<IssuedApiKey> {
+        let pool = ctx.data::<SqlitePool>()?;
+        let agent_id = signing_agent(ctx, "issueApiKey")?;
+        api_keys::issue(pool, agent_id, &scopes, label.as_deref()).await
+    }
+
+    /// Revoke one of the signing agent's API keys; false if it has no such active key
+    #[graphql(name = "revokeApiKey")]
+    async fn revoke_api_key(&self, ctx: &Context<'_>, key_id: String) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<SqlitePool>()?;
+        let agent_id = signing_agent(ctx, "revokeApiKey")?;
+        api_keys::revoke(pool, agent_id, &key_id).await
+    }
+
+    /// Open a price negotiation for the fare of a flight; a proposed price is
+    /// answered as the first counter-offer. Needs the `negotiate` scope.
+    #[graphql(name = "negotiateOffer", guard = "ScopeGuard(ApiScope::Negotiate)")]
     async fn negotiate_offer(
-        &self, 
-        ctx: &Context<'_>, 
+        &self,
+        ctx: &Context<'_>,
         flight_id: i64,
-        negotiation_context: serde_json::Value
-    ) -> async_graphql::Result<serde_json::Value> {
+        #[graphql(default)] cabin: Cabin,
+        #[graphql(default = 1)] passengers: i64,
+        proposed_price: Option<f64>,
+    ) -> async_graphql::Result<Negotiation> {
         let pool = ctx.data::<SqlitePool>()?;
-        
-        // Fetch the flight data
-        let flight = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
-        )
-        .bind(flight_id)
-        .fetch_one(pool)
-        .await?;
-        
-        // Log the negotiation attempt
-        info!(
-            "Bot negotiation attempt for flight {}: {}",
-            flight_id, negotiation_context
-        );
-        
-        // Parse negotiation parameters (simplified)
-        let negotiation_type = if let Some(n_type) = negotiation_context.get("type") {
-            n_type.as_str().unwrap_or("discount")
-        } else {
-            "discount"
-        };
-        
-        // Prepare response based on negotiation type
-        let response = match negotiation_type {
-            "discount" => {
-                // Offer small discount
-                serde_json::json!({
-                    "success": true,
-                    "original_price": flight.price,
-                    "negotiated_price": (flight.price * 0.95).round(),
-                    "discount_percent": 5,
-                    "discount_reason": "Loyalty member pricing",
-                    "expiration": "30 minutes"
-                })
-            },
-            "upgrade" => {
-                // Offer seat upgrade
-                serde_json::json!({
-                    "success": true,
-                    "original_seat": "Economy",
-                    "upgraded_seat": "Economy Plus",
-                    "upgrade_fee": (flight.price * 0.15).round(),
-                    "benefits": ["More legroom", "Priority boarding", "Free drink"],
-                    "expiration": "30 minutes"
-                })
-            },
-            _ => {
-                // No negotiation available
-                serde_json::json!({
-                    "success": false,
-                    "reason": "No negotiation available for this request type",
-                    "alternative_offers": []
-                })
-            }
-        };
-        
-        Ok(response)
+        let config = ctx.data::<NegotiationConfig>()?;
+        info!("Bot negotiation opened for flight {} ({:?} proposed)", flight_id, proposed_price);
+        let owner = Owner::of(ctx.data_opt::<BotInfo>());
+        negotiation::start(pool, config, &owner, flight_id, cabin, passengers, proposed_price).await
     }
-} 
\ No newline at end of file
+
+    /// Counter the asking price of an open negotiation with a price per passenger
+    #[graphql(name = "counterOffer", guard = "ScopeGuard(ApiScope::Negotiate)")]
+    async fn counter_offer(
+        &self,
+        ctx: &Context<'_>,
+        negotiation_id: String,
+        proposed_price: f64,
+    ) -> async_graphql::Result<Negotiation> {
+        let pool = ctx.data::<SqlitePool>()?;
+        let config = ctx.data::<NegotiationConfig>()?;
+        let owner = Owner::of(ctx.data_opt::<BotInfo>());
+        negotiation::counter(pool, config, &owner, &negotiation_id, proposed_price).await
+    }
+
+    /// Accept the current asking price of an open negotiation
+    #[graphql(name = "acceptNegotiation", guard = "ScopeGuard(ApiScope::Negotiate)")]
+    async fn accept_negotiation(&self, ctx: &Context<'_>, negotiation_id: String) -> async_graphql::Result<Negotiation> {
+        let pool = ctx.data::<SqlitePool>()?;
+        let config = ctx.data::<NegotiationConfig>()?;
+        let owner = Owner::of(ctx.data_opt::<BotInfo>());
+        negotiation::accept(pool, config, &owner, &negotiation_id).await
+    }
+} 
+/// Bot schema queries: every human query plus the bot-only ones
+#[derive(MergedObject, Default)]
+pub struct BotQuery(QueryRoot, BotQueryRoot);
+
+/// Bot schema mutations: every human mutation plus the bot-only ones.
+/// [`BotMutationRoot`] comes last: a merged object registers and resolves a
+/// field from its last member, so its bookFlight replaces the shared one.
+#[derive(MergedObject, Default)]
+pub struct BotMutation(MutationRoot, BotMutationRoot);
@@ -0,0 +1,490 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::db;
+
+/// Server configuration, built from defaults, an optional TOML file and
+/// `BOT_SHOP_*` environment variables (in increasing order of precedence)
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    #[serde(rename = "static")]
+    pub static_files: StaticConfig,
+    pub cors: CorsConfig,
+    pub detection: DetectionConfig,
+    pub graphql: GraphqlConfig,
+    pub rate_limit: RateLimitConfig,
+    pub negotiation: NegotiationConfig,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the HTTP server binds to
+    pub listen: SocketAddr,
+    /// Reverse proxies whose `X-Forwarded-For` header is believed; requests
+    /// from any other peer are keyed on the peer address
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { listen: SocketAddr::from(([127, 0, 0, 1], 8000)), trusted_proxies: Vec::new() }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// SQLite URL, e.g. `sqlite://data/bot-shop.db` or `sqlite::memory:`
+    pub url: String,
+    /// Directory SQLite uses for temporary files (`SQLITE_TMPDIR`)
+    pub tmp_dir: PathBuf,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig { url: db::DEFAULT_DATABASE_URL.to_string(), tmp_dir: PathBuf::from("./tmp") }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaticConfig {
+    /// Entry point of the React app served at `/`
+    pub index_file: PathBuf,
+    /// Directory served under `/static`
+    pub assets_dir: PathBuf,
+}
+
+impl Default for StaticConfig {
+    fn default() -> Self {
+        StaticConfig {
+            index_file: PathBuf::from("./static/index.html"),
+            assets_dir: PathBuf::from("./static/static"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API from a browser; `*` allows any.
+    /// Empty disables CORS headers entirely.
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetectionConfig {
+    /// Confidence at or above which a client is treated as a bot
+    pub bot_threshold: f32,
+    /// Client-hint score used when the client sends no `X-Bot-Confidence` header
+    pub default_confidence: f32,
+    /// Requests from one client closer together than this count as bursty
+    pub burst_interval_ms: u64,
+    pub weights: SignalWeights,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        DetectionConfig {
+            bot_threshold: 0.55,
+            default_confidence: 0.5,
+            burst_interval_ms: 1000,
+            weights: SignalWeights::default(),
+        }
+    }
+}
+
+/// Relative weight of each bot-detection signal; only the ratios matter
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignalWeights {
+    pub user_agent: f32,
+    pub header_order: f32,
+    pub missing_headers: f32,
+    pub timing: f32,
+    pub cookies: f32,
+    pub client_hint: f32,
+    /// Applied only once the session has sent behavior reports
+    pub behavior: f32,
+    /// Applied only once the session has run a GraphQL query
+    pub graphql: f32,
+}
+
+impl Default for SignalWeights {
+    fn default() -> Self {
+        SignalWeights {
+            user_agent: 0.35,
+            header_order: 0.1,
+            missing_headers: 0.2,
+            timing: 0.15,
+            cookies: 0.1,
+            client_hint: 0.1,
+            behavior: 0.3,
+            graphql: 0.2,
+        }
+    }
+}
+
+impl SignalWeights {
+    pub fn entries(&self) -> [(&'static str, f32); 8] {
+        [
+            ("user_agent", self.user_agent),
+            ("header_order", self.header_order),
+            ("missing_headers", self.missing_headers),
+            ("timing", self.timing),
+            ("cookies", self.cookies),
+            ("client_hint", self.client_hint),
+            ("behavior", self.behavior),
+            ("graphql", self.graphql),
+        ]
+    }
+}
+
+/// Limits enforced on every GraphQL query, on both schemas
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphqlConfig {
+    /// Deepest allowed selection nesting
+    pub max_depth: usize,
+    /// Highest allowed query complexity (one per selected field)
+    pub max_complexity: usize,
+}
+
+impl Default for GraphqlConfig {
+    fn default() -> Self {
+        GraphqlConfig { max_depth: 15, max_complexity: 500 }
+    }
+}
+
+/// Token-bucket rate limits on `/graphql` and `/bot/*`. Every request draws a
+/// token from the bucket of its session, its client IP and, for identified
+/// agents, the agent; the policy follows the kind of traffic.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Clients detection takes for humans
+    pub human: RatePolicy,
+    /// Detected bots without credentials
+    pub bot: RatePolicy,
+    /// Agents identified by a signature or API key
+    pub agent: RatePolicy,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            human: RatePolicy { burst: 60, per_minute: 120 },
+            bot: RatePolicy { burst: 20, per_minute: 60 },
+            agent: RatePolicy { burst: 60, per_minute: 300 },
+        }
+    }
+}
+
+/// Size and refill rate of a token bucket
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RatePolicy {
+    /// Requests that can be made at once
+    pub burst: u32,
+    /// Tokens added back per minute
+    pub per_minute: u32,
+}
+
+/// Price negotiation with agents through `negotiateOffer` and `counterOffer`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NegotiationConfig {
+    /// Minutes a negotiation stays open
+    pub session_minutes: i64,
+    /// Counter-offers an agent can make before the negotiation closes
+    pub max_rounds: i64,
+    /// Minutes an agreed price can be booked
+    pub offer_minutes: i64,
+    /// Policy rules; the first one matching the route and load factor applies,
+    /// and flights no rule matches are not discounted
+    pub rules: Vec<NegotiationRule>,
+}
+
+impl Default for NegotiationConfig {
+    fn default() -> Self {
+        let rule = |name: &str, min_load_factor, max_load_factor, opening, max| NegotiationRule {
+            name: name.to_string(),
+            origin: None,
+            destination: None,
+            min_load_factor,
+            max_load_factor,
+            opening_discount_percent: opening,
+            max_discount_percent: max,
+            floor_price: None,
+            concession: 0.5,
+        };
+        NegotiationConfig {
+            session_minutes: 30,
+            max_rounds: 5,
+            offer_minutes: 30,
+            rules: vec![
+                rule("high-demand", 0.85, 1.0, 0.0, 0.0),
+                rule("low-demand", 0.0, 0.5, 5.0, 20.0),
+                rule("standard", 0.0, 1.0, 0.0, 10.0),
+            ],
+        }
+    }
+}
+
+/// How far the fare of matching flights can be negotiated down
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NegotiationRule {
+    /// Reported with every negotiation the rule governs
+    pub name: String,
+    /// Airport codes the rule is limited to; any if omitted
+    pub origin: Option<String>,
+    pub destination: Option<String>,
+    /// Share of the cabin already sold (0 to 1) the rule applies between, inclusive
+    #[serde(default)]
+    pub min_load_factor: f64,
+    #[serde(default = "full_load")]
+    pub max_load_factor: f64,
+    /// Discount of the first asking price
+    #[serde(default)]
+    pub opening_discount_percent: f64,
+    /// Largest discount ever agreed; sets the floor price
+    pub max_discount_percent: f64,
+    /// Lowest price per passenger, whatever the discount
+    pub floor_price: Option<f64>,
+    /// Share of the gap between the asking and floor price conceded each round
+    #[serde(default = "half")]
+    pub concession: f64,
+}
+
+fn full_load() -> f64 {
+    1.0
+}
+
+fn half() -> f64 {
+    0.5
+}
+
+impl NegotiationRule {
+    /// Whether the rule governs a flight on this route at this load factor
+    pub fn matches(&self, origin: &str, destination: &str, load_factor: f64) -> bool {
+        self.origin.as_deref().is_none_or(|o| o.eq_ignore_ascii_case(origin))
+            && self.destination.as_deref().is_none_or(|d| d.eq_ignore_ascii_case(destination))
+            && (self.min_load_factor..=self.max_load_factor).contains(&load_factor)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env { var: &'static str, message: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "Cannot read config {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "Invalid config {}: {}", path.display(), err),
+            ConfigError::Env { var, message } => write!(f, "Invalid {}: {}", var, message),
+            ConfigError::Invalid(message) => write!(f, "Invalid configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Path given with `--config <path>` or `--config=<path>`
+pub fn config_path_from_args(args: impl IntoIterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn env_parse<T: std::str::FromStr>(var: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    match std::env::var(var) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|err: T::Err| ConfigError::Env { var, message: err.to_string() }),
+        Err(_) => Ok(None),
+    }
+}
+
+impl Config {
+    /// Load the configuration from an optional TOML file, then apply
+    /// environment overrides and validate the result
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+        Config::from_toml(&text).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(listen) = env_parse("BOT_SHOP_LISTEN")? {
+            self.server.listen = listen;
+        }
+        if let Ok(proxies) = std::env::var("BOT_SHOP_TRUSTED_PROXIES") {
+            self.server.trusted_proxies = proxies
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(|p| p.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|err: std::net::AddrParseError| ConfigError::Env {
+                    var: "BOT_SHOP_TRUSTED_PROXIES",
+                    message: err.to_string(),
+                })?;
+        }
+        // DATABASE_URL is the conventional name and is honoured as well
+        if let Ok(url) = std::env::var("BOT_SHOP_DATABASE_URL").or_else(|_| std::env::var("DATABASE_URL")) {
+            self.database.url = url;
+        }
+        if let Some(dir) = env_parse("BOT_SHOP_TMP_DIR")? {
+            self.database.tmp_dir = dir;
+        }
+        if let Some(file) = env_parse("BOT_SHOP_STATIC_INDEX")? {
+            self.static_files.index_file = file;
+        }
+        if let Some(dir) = env_parse("BOT_SHOP_STATIC_DIR")? {
+            self.static_files.assets_dir = dir;
+        }
+        if let Ok(origins) = std::env::var("BOT_SHOP_CORS_ORIGINS") {
+            self.cors.allowed_origins = origins
+                .split(',')
+                .map(|o| o.trim().to_string())
+                .filter(|o| !o.is_empty())
+                .collect();
+        }
+        if let Some(threshold) = env_parse("BOT_SHOP_BOT_THRESHOLD")? {
+            self.detection.bot_threshold = threshold;
+        }
+        if let Some(confidence) = env_parse("BOT_SHOP_DEFAULT_CONFIDENCE")? {
+            self.detection.default_confidence = confidence;
+        }
+        if let Some(interval) = env_parse("BOT_SHOP_BURST_INTERVAL_MS")? {
+            self.detection.burst_interval_ms = interval;
+        }
+        if let Some(depth) = env_parse("BOT_SHOP_GRAPHQL_MAX_DEPTH")? {
+            self.graphql.max_depth = depth;
+        }
+        if let Some(complexity) = env_parse("BOT_SHOP_GRAPHQL_MAX_COMPLEXITY")? {
+            self.graphql.max_complexity = complexity;
+        }
+        if let Some(enabled) = env_parse("BOT_SHOP_RATE_LIMIT")? {
+            self.rate_limit.enabled = enabled;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !self.database.url.starts_with("sqlite:") {
+            return Err(ConfigError::Invalid(format!(
+                "database.url must be a sqlite: URL, got '{}'",
+                self.database.url
+            )));
+        }
+        for origin in &self.cors.allowed_origins {
+            let valid = origin == "*"
+                || ((origin.starts_with("http://") || origin.starts_with("https://"))
+                    && !origin.ends_with('/')
+                    && origin.parse::<axum::http::HeaderValue>().is_ok());
+            if !valid {
+                return Err(ConfigError::Invalid(format!(
+                    "cors.allowed_origins entry '{}' must be '*' or a scheme://host[:port] origin",
+                    origin
+                )));
+            }
+        }
+        let detection = &self.detection;
+        for (name, value) in [
+            ("detection.bot_threshold", detection.bot_threshold),
+            ("detection.default_confidence", detection.default_confidence),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(ConfigError::Invalid(format!("{} must be between 0 and 1, got {}", name, value)));
+            }
+        }
+        let weights = detection.weights.entries();
+        if let Some((name, weight)) = weights.iter().find(|(_, w)| !(*w >= 0.0 && w.is_finite())) {
+            return Err(ConfigError::Invalid(format!("detection.weights.{} must be non-negative, got {}", name, weight)));
+        }
+        if weights.iter().map(|(_, w)| w).sum::<f32>() <= 0.0 {
+            return Err(ConfigError::Invalid("detection.weights must not all be zero".to_string()));
+        }
+        if self.graphql.max_depth == 0 || self.graphql.max_complexity == 0 {
+            return Err(ConfigError::Invalid("graphql.max_depth and graphql.max_complexity must be positive".to_string()));
+        }
+        let limits = &self.rate_limit;
+        for (name, policy) in [("human", limits.human), ("bot", limits.bot), ("agent", limits.agent)] {
+            if policy.burst == 0 || policy.per_minute == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "rate_limit.{}.burst and rate_limit.{}.per_minute must be positive",
+                    name, name
+                )));
+            }
+        }
+        let negotiation = &self.negotiation;
+        if negotiation.session_minutes <= 0 || negotiation.max_rounds <= 0 || negotiation.offer_minutes <= 0 {
+            return Err(ConfigError::Invalid(
+                "negotiation.session_minutes, max_rounds and offer_minutes must be positive".to_string(),
+            ));
+        }
+        for rule in &negotiation.rules {
+            let invalid = |message: &str| ConfigError::Invalid(format!("negotiation rule '{}': {}", rule.name, message));
+            if !(0.0..=1.0).contains(&rule.min_load_factor)
+                || !(0.0..=1.0).contains(&rule.max_load_factor)
+                || rule.min_load_factor > rule.max_load_factor
+            {
+                return Err(invalid("load factors must be between 0 and 1, min before max"));
+            }
+            if !(0.0..=100.0).contains(&rule.max_discount_percent)
+                || !(0.0..=rule.max_discount_percent).contains(&rule.opening_discount_percent)
+            {
+                return Err(invalid("discounts must be between 0 and 100, opening at most max"));
+            }
+            if !(rule.concession > 0.0 && rule.concession <= 1.0) {
+                return Err(invalid("concession must be above 0 and at most 1"));
+            }
+            if rule.floor_price.is_some_and(|floor| floor < 0.0) {
+                return Err(invalid("floor_price must not be negative"));
+            }
+        }
+        Ok(())
+    }
+}
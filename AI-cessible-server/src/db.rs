@@ -0,0 +1,116 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::SqlitePool;
+use tracing::info;
+
+use crate::airports;
+
+/// Database used when no URL is configured
+pub const DEFAULT_DATABASE_URL: &str = "sqlite://data/bot-shop.db";
+
+/// Ordered schema migrations from `migrations/`, embedded at compile time
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Schema version of a database compared with the migrations this build knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaVersion {
+    /// Highest applied migration, `None` for an empty database
+    pub current: Option<i64>,
+    pub latest: i64,
+}
+
+impl SchemaVersion {
+    pub fn is_current(&self) -> bool {
+        self.current == Some(self.latest)
+    }
+}
+
+/// File backing a `sqlite:` URL, or `None` for in-memory databases
+fn database_file(url: &str) -> Option<&Path> {
+    let path = url.strip_prefix("sqlite://").or_else(|| url.strip_prefix("sqlite:"))?;
+    let path = path.split('?').next().unwrap_or_default();
+    (!path.is_empty() && path != ":memory:").then(|| Path::new(path))
+}
+
+/// Open a connection pool, creating the database file (and its directory) if needed
+pub async fn connect(url: &str) -> Result<SqlitePool, sqlx::Error> {
+    if let Some(dir) = database_file(url).and_then(Path::parent) {
+        if !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
+    let options = SqliteConnectOptions::from_str(url)?
+        .create_if_missing(true)
+        .foreign_keys(true);
+    SqlitePoolOptions::new().connect_with(options).await
+}
+
+/// Open an existing database read-only; fails instead of creating a missing file
+pub async fn connect_read_only(url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(url)?.read_only(true);
+    SqlitePoolOptions::new().connect_with(options).await
+}
+
+/// Apply every pending migration in order, then localize any flight times
+/// still stored without a UTC offset. The schema requires offsets since
+/// migration 21; checking on every run rather than only when it is applied
+/// lets a failed localization be retried once the data is fixed.
+pub async fn migrate(pool: &SqlitePool) -> Result<(), MigrateError> {
+    MIGRATOR.run(pool).await?;
+    let localized = airports::localize_flight_times(pool).await.map_err(MigrateError::Execute)?;
+    if localized > 0 {
+        info!("Added airport UTC offsets to the times of {} flights", localized);
+    }
+    Ok(())
+}
+
+/// Current schema version of the database without applying anything
+pub async fn schema_version(pool: &SqlitePool) -> Result<SchemaVersion, sqlx::Error> {
+    let latest = MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+    let (tracked,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'",
+    )
+    .fetch_one(pool)
+    .await?;
+    if tracked == 0 {
+        return Ok(SchemaVersion { current: None, latest });
+    }
+    let (current,): (Option<i64>,) =
+        sqlx::query_as("SELECT MAX(version) FROM _sqlx_migrations WHERE success = 1")
+            .fetch_one(pool)
+            .await?;
+    Ok(SchemaVersion { current, latest })
+}
+
+/// Insert the demo flights into an empty database
+pub async fn seed_sample_flights(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM flights")
+        .fetch_one(pool)
+        .await?;
+    if count.0 > 0 {
+        return Ok(());
+    }
+    let sample_flights = vec![
+        ("NYC", "LAX", "2025-06-01T08:00:00-04:00", "2025-06-01T11:00:00-07:00", 199.0, "A320"),
+        ("NYC", "SFO", "2025-06-02T09:00:00-04:00", "2025-06-02T12:30:00-07:00", 249.0, "A320"),
+        ("LAX", "SEA", "2025-06-03T07:00:00-07:00", "2025-06-03T09:45:00-07:00", 149.0, "E175"),
+        ("LAX", "SEA", "2025-06-01T13:30:00-07:00", "2025-06-01T16:15:00-07:00", 159.0, "E175"),
+    ];
+    for (origin, destination, dep, arr, price, aircraft) in sample_flights {
+        sqlx::query(
+            "INSERT INTO flights (origin, destination, departure_time, arrival_time, price, aircraft_code) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(origin)
+        .bind(destination)
+        .bind(dep)
+        .bind(arr)
+        .bind(price)
+        .bind(aircraft)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
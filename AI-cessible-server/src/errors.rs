@@ -0,0 +1,243 @@
+use std::fmt;
+
+use async_graphql::ErrorExtensions;
+use tracing::error;
+
+use crate::api_keys::ApiScope;
+use crate::payment::PaymentError;
+use crate::schema::Cabin;
+
+/// Domain errors surfaced to GraphQL clients with a machine-readable `code` extension
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    FlightNotFound(i64),
+    BookingNotFound(i64),
+    CabinNotOffered { flight_id: i64, cabin: Cabin },
+    SoldOut { flight_id: i64, cabin: Cabin, remaining: i64 },
+    SeatNotFound { flight_id: i64, seat: String },
+    SeatUnavailable { flight_id: i64, seat: String },
+    InvalidPassenger { index: usize, field: String, message: String },
+    Payment(PaymentError),
+    /// Operation reserved for agents that sign their requests
+    AgentNotVerified { operation: &'static str },
+    /// Operation needs a scope the public does not have and the caller sent no credentials
+    Unauthorized { operation: String, scope: ApiScope },
+    /// The caller's API key was not issued with the scope the operation needs
+    MissingScope { operation: String, scope: ApiScope },
+    /// The caller's rate limit bucket is empty
+    RateLimited { policy: &'static str, retry_after_secs: u64 },
+    UnknownAddon(String),
+    /// The add-on cannot be sold with this flight, cabin or offer
+    AddonNotEligible { code: String, flight_id: Option<i64>, reason: String },
+    /// Quantity below one, or above the add-on's limit for the passengers
+    InvalidAddonQuantity { code: String, quantity: i64, max: Option<i64> },
+    OfferNotFound(String),
+    /// The offer's time to live has passed
+    OfferExpired { offer_id: String, expires_at: String },
+    OfferAlreadyBooked(String),
+    /// Fares or add-on prices moved since the offer was quoted
+    PriceChanged { offer_id: String, quoted: f64, current: f64 },
+    NegotiationNotFound(String),
+    /// The negotiation was agreed or closed and takes no more offers
+    NegotiationClosed { negotiation_id: String, status: &'static str },
+    NegotiationExpired { negotiation_id: String, expires_at: String },
+    /// Another request changed the negotiation while this one was answering it
+    NegotiationConflict(String),
+    /// A failure on the server's side, such as the database; details are only logged
+    Internal,
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::FlightNotFound(_) => "FLIGHT_NOT_FOUND",
+            ApiError::BookingNotFound(_) => "BOOKING_NOT_FOUND",
+            ApiError::CabinNotOffered { .. } => "CABIN_NOT_OFFERED",
+            ApiError::SoldOut { .. } => "SOLD_OUT",
+            ApiError::SeatNotFound { .. } => "SEAT_NOT_FOUND",
+            ApiError::SeatUnavailable { .. } => "SEAT_UNAVAILABLE",
+            ApiError::InvalidPassenger { .. } => "INVALID_PASSENGER",
+            ApiError::Payment(PaymentError::Declined(_)) => "PAYMENT_DECLINED",
+            ApiError::Payment(PaymentError::InvalidCard(_) | PaymentError::UnknownToken) => "INVALID_PAYMENT_METHOD",
+            ApiError::Payment(PaymentError::InvalidState(_) | PaymentError::Unavailable(_)) => "PAYMENT_FAILED",
+            ApiError::AgentNotVerified { .. } => "AGENT_NOT_VERIFIED",
+            ApiError::Unauthorized { .. } => "UNAUTHORIZED",
+            ApiError::MissingScope { .. } => "INSUFFICIENT_SCOPE",
+            ApiError::RateLimited { .. } => "RATE_LIMITED",
+            ApiError::UnknownAddon(_) => "UNKNOWN_ADDON",
+            ApiError::AddonNotEligible { .. } => "ADDON_NOT_ELIGIBLE",
+            ApiError::InvalidAddonQuantity { .. } => "INVALID_ADDON_QUANTITY",
+            ApiError::OfferNotFound(_) => "OFFER_NOT_FOUND",
+            ApiError::OfferExpired { .. } => "OFFER_EXPIRED",
+            ApiError::OfferAlreadyBooked(_) => "OFFER_ALREADY_BOOKED",
+            ApiError::PriceChanged { .. } => "PRICE_CHANGED",
+            ApiError::NegotiationNotFound(_) => "NEGOTIATION_NOT_FOUND",
+            ApiError::NegotiationClosed { .. } => "NEGOTIATION_CLOSED",
+            ApiError::NegotiationExpired { .. } => "NEGOTIATION_EXPIRED",
+            ApiError::NegotiationConflict(_) => "NEGOTIATION_CONFLICT",
+            ApiError::Internal => "INTERNAL_ERROR",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::FlightNotFound(id) => write!(f, "Flight {} not found", id),
+            ApiError::BookingNotFound(id) => write!(f, "Booking {} not found", id),
+            ApiError::CabinNotOffered { flight_id, cabin } => {
+                write!(f, "Flight {} has no {} cabin", flight_id, cabin.as_str())
+            }
+            ApiError::SoldOut { flight_id, cabin, remaining } => write!(
+                f,
+                "Not enough {} seats on flight {} ({} remaining)",
+                cabin.as_str(),
+                flight_id,
+                remaining
+            ),
+            ApiError::SeatNotFound { flight_id, seat } => {
+                write!(f, "Seat {} does not exist in the booked cabin of flight {}", seat, flight_id)
+            }
+            ApiError::SeatUnavailable { flight_id, seat } => {
+                write!(f, "Seat {} on flight {} is already taken", seat, flight_id)
+            }
+            ApiError::InvalidPassenger { index, field, message } => {
+                write!(f, "Passenger {}: {} {}", index + 1, field, message)
+            }
+            ApiError::Payment(err) => write!(f, "{}", err),
+            ApiError::AgentNotVerified { operation } => {
+                write!(f, "{} is only available to verified agents; sign the request with a registered key", operation)
+            }
+            ApiError::Unauthorized { operation, scope } => write!(
+                f,
+                "{} requires the '{}' scope; send an API key that has it or sign the request",
+                operation,
+                scope.as_str()
+            ),
+            ApiError::MissingScope { operation, scope } => {
+                write!(f, "API key lacks the '{}' scope required by {}", scope.as_str(), operation)
+            }
+            ApiError::RateLimited { policy, retry_after_secs } => {
+                write!(f, "Rate limit of the {} policy exceeded; retry in {}s", policy, retry_after_secs)
+            }
+            ApiError::UnknownAddon(code) => write!(f, "Unknown add-on '{}'", code),
+            ApiError::AddonNotEligible { code, flight_id: Some(flight_id), reason } => {
+                write!(f, "Add-on {} is not available on flight {}: {}", code, flight_id, reason)
+            }
+            ApiError::AddonNotEligible { code, flight_id: None, reason } => {
+                write!(f, "Add-on {} is not available: {}", code, reason)
+            }
+            ApiError::InvalidAddonQuantity { code, quantity, max: Some(max) } => {
+                write!(f, "Add-on {}: quantity {} exceeds the limit of {}", code, quantity, max)
+            }
+            ApiError::InvalidAddonQuantity { code, quantity, max: None } => {
+                write!(f, "Add-on {}: quantity {} must be at least 1", code, quantity)
+            }
+            ApiError::OfferNotFound(offer_id) => write!(f, "Offer {} not found", offer_id),
+            ApiError::OfferExpired { offer_id, expires_at } => {
+                write!(f, "Offer {} expired at {}; build a new offer", offer_id, expires_at)
+            }
+            ApiError::OfferAlreadyBooked(offer_id) => write!(f, "Offer {} has already been booked", offer_id),
+            ApiError::PriceChanged { offer_id, quoted, current } => write!(
+                f,
+                "Price of offer {} changed from {:.2} to {:.2}; build a new offer",
+                offer_id, quoted, current
+            ),
+            ApiError::NegotiationNotFound(negotiation_id) => write!(f, "Negotiation {} not found", negotiation_id),
+            ApiError::NegotiationClosed { negotiation_id, status } => {
+                write!(f, "Negotiation {} is {} and takes no more offers", negotiation_id, status)
+            }
+            ApiError::NegotiationExpired { negotiation_id, expires_at } => {
+                write!(f, "Negotiation {} expired at {}; open a new one", negotiation_id, expires_at)
+            }
+            ApiError::NegotiationConflict(negotiation_id) => {
+                write!(f, "Negotiation {} was changed by another request; fetch it and retry", negotiation_id)
+            }
+            ApiError::Internal => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        error!("Database error: {}", err);
+        ApiError::Internal
+    }
+}
+
+impl ErrorExtensions for ApiError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string()).extend_with(|_, e| {
+            e.set("code", self.code());
+            match self {
+                ApiError::FlightNotFound(id) => e.set("flightId", *id),
+                ApiError::BookingNotFound(id) => e.set("bookingId", *id),
+                ApiError::CabinNotOffered { flight_id, cabin } => {
+                    e.set("flightId", *flight_id);
+                    e.set("cabin", cabin.as_str());
+                }
+                ApiError::SoldOut { flight_id, cabin, remaining } => {
+                    e.set("flightId", *flight_id);
+                    e.set("cabin", cabin.as_str());
+                    e.set("seatsRemaining", *remaining);
+                }
+                ApiError::SeatNotFound { flight_id, seat } | ApiError::SeatUnavailable { flight_id, seat } => {
+                    e.set("flightId", *flight_id);
+                    e.set("seat", seat.as_str());
+                }
+                ApiError::InvalidPassenger { index, field, .. } => {
+                    e.set("passengerIndex", *index as i64);
+                    e.set("field", field.as_str());
+                }
+                ApiError::Payment(_) => {}
+                ApiError::AgentNotVerified { operation } => e.set("operation", *operation),
+                ApiError::Unauthorized { operation, scope } | ApiError::MissingScope { operation, scope } => {
+                    e.set("operation", operation.as_str());
+                    e.set("scope", scope.as_str());
+                }
+                ApiError::RateLimited { policy, retry_after_secs } => {
+                    e.set("policy", *policy);
+                    e.set("retryAfter", *retry_after_secs);
+                }
+                ApiError::UnknownAddon(code) => e.set("addon", code.as_str()),
+                ApiError::AddonNotEligible { code, flight_id, .. } => {
+                    e.set("addon", code.as_str());
+                    if let Some(flight_id) = flight_id {
+                        e.set("flightId", *flight_id);
+                    }
+                }
+                ApiError::InvalidAddonQuantity { code, quantity, max } => {
+                    e.set("addon", code.as_str());
+                    e.set("quantity", *quantity);
+                    if let Some(max) = max {
+                        e.set("max", *max);
+                    }
+                }
+                ApiError::OfferNotFound(offer_id) | ApiError::OfferAlreadyBooked(offer_id) => {
+                    e.set("offerId", offer_id.as_str())
+                }
+                ApiError::OfferExpired { offer_id, expires_at } => {
+                    e.set("offerId", offer_id.as_str());
+                    e.set("expiresAt", expires_at.as_str());
+                }
+                ApiError::PriceChanged { offer_id, quoted, current } => {
+                    e.set("offerId", offer_id.as_str());
+                    e.set("quotedPrice", *quoted);
+                    e.set("currentPrice", *current);
+                }
+                ApiError::NegotiationNotFound(negotiation_id) | ApiError::NegotiationConflict(negotiation_id) => {
+                    e.set("negotiationId", negotiation_id.as_str())
+                }
+                ApiError::NegotiationClosed { negotiation_id, status } => {
+                    e.set("negotiationId", negotiation_id.as_str());
+                    e.set("status", *status);
+                }
+                ApiError::NegotiationExpired { negotiation_id, expires_at } => {
+                    e.set("negotiationId", negotiation_id.as_str());
+                    e.set("expiresAt", expires_at.as_str());
+                }
+                ApiError::Internal => {}
+            }
+        })
+    }
+}
@@ -0,0 +1,210 @@
+use sqlx::SqlitePool;
+
+use crate::addons::round_cents;
+use crate::bot_schema::{RuleSource, TaxComponent};
+use crate::schema::{Cabin, FlightOffer};
+
+/// Fare family sold in a cabin
+#[derive(sqlx::FromRow)]
+pub struct FareFamily {
+    pub code: String,
+    pub name: String,
+    pub meal_included: bool,
+    /// Loyalty points per unit of base fare
+    pub loyalty_rate: f64,
+    /// Multiple of a flight's listed (economy) price charged for the family
+    pub fare_multiplier: f64,
+}
+
+/// Bags included with a fare
+#[derive(sqlx::FromRow)]
+pub struct BaggageAllowance {
+    pub carry_on: i64,
+    pub checked: i64,
+    pub weight_limit_kg: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct TaxRule {
+    id: String,
+    code: String,
+    name: String,
+    airport: Option<String>,
+    amount: f64,
+    percent: f64,
+}
+
+/// Multiple of a flight's listed (economy) price charged in `cabin`
+pub async fn cabin_fare_multiplier(pool: &SqlitePool, cabin: Cabin) -> async_graphql::Result<f64> {
+    let row: Option<(f64,)> = sqlx::query_as("SELECT fare_multiplier FROM fare_families WHERE cabin = ?")
+        .bind(cabin.as_str())
+        .fetch_optional(pool)
+        .await?;
+    row.map(|(multiplier,)| multiplier)
+        .ok_or_else(|| async_graphql::Error::new(format!("No fare family is sold in the {} cabin", cabin.as_str())))
+}
+
+/// Every rule that prices and governs the fare of a flight in one cabin
+pub struct FareRules {
+    pub cabin: Cabin,
+    pub family: FareFamily,
+    /// Refund percent by minimum hours before departure, latest threshold first
+    pub refunds: Vec<(i64, f64)>,
+    /// Change fee by minimum hours before departure, latest threshold first;
+    /// `None` where changes are not allowed
+    pub changes: Vec<(i64, Option<f64>)>,
+    pub baggage: BaggageAllowance,
+    taxes: Vec<TaxRule>,
+}
+
+/// Rule ids reported in explanation sources
+impl FareRules {
+    pub fn family_rule(&self) -> String {
+        format!("fare_family:{}", self.family.code)
+    }
+
+    pub fn refund_rule(&self) -> String {
+        format!("refund:{}", self.family.code)
+    }
+
+    pub fn change_rule(&self) -> String {
+        format!("change:{}", self.family.code)
+    }
+
+    pub fn baggage_rule(&self) -> String {
+        format!("baggage:{}", self.cabin.as_str())
+    }
+
+    /// Tax-inclusive fare in this cabin of a flight listed at `price`
+    pub fn fare(&self, price: f64) -> f64 {
+        round_cents(price * self.family.fare_multiplier)
+    }
+
+    /// Split a tax-inclusive fare into its base fare and tax components.
+    /// Rounding is absorbed by the base fare, so the parts add up to `price`;
+    /// a fare below the route's fixed taxes cannot be split and is an error.
+    pub fn breakdown(&self, price: f64) -> async_graphql::Result<(f64, Vec<TaxComponent>)> {
+        let fixed: f64 = self.taxes.iter().map(|t| t.amount).sum();
+        let percent: f64 = self.taxes.iter().map(|t| t.percent).sum();
+        if price < fixed {
+            return Err(async_graphql::Error::new(format!(
+                "Fare {:.2} is below the {:.2} of fixed taxes on its route",
+                price, fixed
+            )));
+        }
+        let base = (price - fixed) / (1.0 + percent / 100.0);
+        let taxes: Vec<TaxComponent> = self
+            .taxes
+            .iter()
+            .map(|t| TaxComponent {
+                code: t.code.clone(),
+                name: t.name.clone(),
+                airport: t.airport.clone(),
+                amount: round_cents(t.amount + base * t.percent / 100.0),
+                rule: format!("tax:{}", t.id),
+            })
+            .collect();
+        let total_tax: f64 = taxes.iter().map(|t| t.amount).sum();
+        Ok((round_cents(price - total_tax).max(0.0), taxes))
+    }
+
+    /// Human-readable refund schedule
+    pub fn cancellation_policy(&self) -> String {
+        let mut parts = Vec::new();
+        for (index, (hours, percent)) in self.refunds.iter().enumerate() {
+            let refund = match *percent {
+                p if p <= 0.0 => "no refund".to_string(),
+                p if p >= 100.0 => "full refund".to_string(),
+                p => format!("{}% refund", p),
+            };
+            parts.push(match (*hours, index) {
+                (0, 0) => format!("{} up to departure", refund),
+                (0, _) => format!("{} after that", refund),
+                (hours, _) => format!("{} up to {} hours before departure", refund, hours),
+            });
+        }
+        if parts.is_empty() {
+            return "No refund".to_string();
+        }
+        let policy = parts.join("; ");
+        let mut chars = policy.chars();
+        chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+    }
+
+    /// Fee for changing at the earliest threshold, or `None` if changes are never allowed
+    pub fn change_fee(&self) -> Option<f64> {
+        self.changes.iter().find_map(|(_, fee)| *fee)
+    }
+}
+
+/// Load the fare rules of a flight in a cabin
+pub async fn for_flight(pool: &SqlitePool, flight: &FlightOffer, cabin: Cabin) -> async_graphql::Result<FareRules> {
+    let family = sqlx::query_as::<_, FareFamily>(
+        "SELECT code, name, meal_included, loyalty_rate, fare_multiplier FROM fare_families WHERE cabin = ?",
+    )
+    .bind(cabin.as_str())
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| async_graphql::Error::new(format!("No fare family is defined for the {} cabin", cabin.as_str())))?;
+    let refunds = sqlx::query_as(
+        "SELECT min_hours_before_departure, refund_percent FROM fare_refund_rules WHERE fare_family = ? ORDER BY min_hours_before_departure DESC",
+    )
+    .bind(&family.code)
+    .fetch_all(pool)
+    .await?;
+    let changes = sqlx::query_as(
+        "SELECT min_hours_before_departure, fee FROM fare_change_rules WHERE fare_family = ? ORDER BY min_hours_before_departure DESC",
+    )
+    .bind(&family.code)
+    .fetch_all(pool)
+    .await?;
+    let baggage = sqlx::query_as::<_, BaggageAllowance>(
+        "SELECT carry_on, checked, weight_limit_kg FROM baggage_allowances WHERE cabin = ?",
+    )
+    .bind(cabin.as_str())
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| async_graphql::Error::new(format!("No baggage allowance is defined for the {} cabin", cabin.as_str())))?;
+    let taxes = sqlx::query_as::<_, TaxRule>(
+        "SELECT id, code, name, airport, amount, percent FROM tax_components \
+         WHERE airport IS NULL OR (applies_at = 'origin' AND airport = ?) OR (applies_at = 'destination' AND airport = ?) \
+         ORDER BY airport IS NOT NULL, id",
+    )
+    .bind(&flight.origin)
+    .bind(&flight.destination)
+    .fetch_all(pool)
+    .await?;
+    Ok(FareRules { cabin, family, refunds, changes, baggage, taxes })
+}
+
+/// This fare against the cheapest fare in the same cabin on the same route:
+/// 1.0 for the cheapest, lower for dearer flights
+pub async fn comparative_value(
+    pool: &SqlitePool,
+    flight: &FlightOffer,
+    rules: &FareRules,
+) -> async_graphql::Result<(f64, RuleSource)> {
+    let (cheapest, flights): (Option<f64>, i64) =
+        sqlx::query_as("SELECT MIN(price), COUNT(*) FROM flights WHERE origin = ? AND destination = ?")
+            .bind(&flight.origin)
+            .bind(&flight.destination)
+            .fetch_one(pool)
+            .await?;
+    let fare = rules.fare(flight.price);
+    let cheapest = cheapest.map_or(fare, |price| rules.fare(price));
+    let value = if fare > 0.0 { (cheapest / fare * 100.0).round() / 100.0 } else { 1.0 };
+    let source = RuleSource {
+        field: "comparativeValue".to_string(),
+        rules: vec![format!("route_fares:{}-{}", flight.origin, flight.destination), rules.family_rule()],
+        detail: format!(
+            "Cheapest of {} {} fares from {} to {} ({:.2}) divided by this fare ({:.2})",
+            flights,
+            rules.cabin.as_str(),
+            flight.origin,
+            flight.destination,
+            cheapest,
+            fare
+        ),
+    };
+    Ok((value, source))
+}
@@ -0,0 +1,66 @@
+use async_graphql::ErrorExtensions;
+use sqlx::SqlitePool;
+
+use crate::bot_detection::BotInfo;
+use crate::bot_schema::{BotIntent, BotIntentRecord};
+use crate::errors::ApiError;
+
+/// Store an intent submitted over REST or GraphQL and return its id.
+/// The agent type and confidence come from server-side detection, not the payload.
+pub async fn record(pool: &SqlitePool, bot_info: Option<&BotInfo>, intent: &BotIntent) -> async_graphql::Result<i64> {
+    if intent.intent_type.trim().is_empty() {
+        return Err(async_graphql::Error::new("intentType is required"));
+    }
+    if let Some(booking_id) = intent.booking_id {
+        let exists: Option<(i64,)> = sqlx::query_as("SELECT id FROM bookings WHERE id = ?")
+            .bind(booking_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|err| ApiError::from(err).extend())?;
+        if exists.is_none() {
+            return Err(ApiError::BookingNotFound(booking_id).extend());
+        }
+    }
+
+    let (agent_type, confidence, session_id, level, agent_id) = match bot_info {
+        Some(info) => (
+            info.agent_type.as_str(),
+            info.confidence_score,
+            Some(info.session_id.as_str()),
+            Some(info.intelligence_level.as_str()),
+            info.agent_id.as_deref(),
+        ),
+        None => ("unknown", 0.0, None, None, None),
+    };
+    let result = sqlx::query(
+        "INSERT INTO bot_intents (agent_type, confidence, intent_type, query_params, reason, additional_context, search_id, offer_id, booking_id, session_id, intelligence_level, agent_id, recorded_time) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'))",
+    )
+    .bind(agent_type)
+    .bind(confidence)
+    .bind(intent.intent_type.trim())
+    .bind(intent.query_params.as_ref().map(|v| v.to_string()))
+    .bind(&intent.reason)
+    .bind(intent.additional_context.as_ref().map(|v| v.to_string()))
+    .bind(&intent.search_id)
+    .bind(&intent.offer_id)
+    .bind(intent.booking_id)
+    .bind(session_id)
+    .bind(level)
+    .bind(agent_id)
+    .execute(pool)
+    .await
+    .map_err(|err| ApiError::from(err).extend())?;
+    Ok(result.last_insert_rowid())
+}
+
+/// Stored intents, newest first: those of `agent_id`, or every intent for `None`
+pub async fn list(pool: &SqlitePool, agent_id: Option<&str>) -> async_graphql::Result<Vec<BotIntentRecord>> {
+    let rows = sqlx::query_as::<_, BotIntentRecord>(
+        "SELECT id, agent_type, confidence, intent_type, query_params, reason, additional_context, search_id, offer_id, booking_id, session_id, intelligence_level, agent_id, recorded_time FROM bot_intents WHERE ?1 IS NULL OR agent_id = ?1 ORDER BY id DESC",
+    )
+    .bind(agent_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|err| ApiError::from(err).extend())?;
+    Ok(rows)
+}
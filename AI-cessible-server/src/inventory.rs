@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::ErrorExtensions;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::errors::ApiError;
+use crate::schema::{Cabin, CabinAvailability};
+
+/// Seats remaining per cabin for a flight
+pub async fn availability(
+    pool: &SqlitePool,
+    flight_id: i64,
+) -> async_graphql::Result<Vec<CabinAvailability>> {
+    let rows: Vec<(i64, String, i64, i64)> = sqlx::query_as(
+        "SELECT flight_id, cabin, capacity, seats_sold FROM seat_inventory WHERE flight_id = ?",
+    )
+    .bind(flight_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(group_by_flight(rows).remove(&flight_id).unwrap_or_default())
+}
+
+/// Batches the availability lookups of every flight in a response into one query
+pub struct AvailabilityLoader {
+    pool: SqlitePool,
+}
+
+/// Loader registered as schema data for the `seatsRemaining` and `availability` fields
+pub fn loader(pool: SqlitePool) -> DataLoader<AvailabilityLoader> {
+    DataLoader::new(AvailabilityLoader { pool }, tokio::spawn)
+}
+
+impl Loader<i64> for AvailabilityLoader {
+    type Value = Vec<CabinAvailability>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, flight_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let placeholders = vec!["?"; flight_ids.len()].join(", ");
+        let sql = format!(
+            "SELECT flight_id, cabin, capacity, seats_sold FROM seat_inventory WHERE flight_id IN ({})",
+            placeholders
+        );
+        let mut query = sqlx::query_as::<_, (i64, String, i64, i64)>(&sql);
+        for flight_id in flight_ids {
+            query = query.bind(flight_id);
+        }
+        let rows = query.fetch_all(&self.pool).await.map_err(Arc::new)?;
+        let mut cabins = group_by_flight(rows);
+        // Flights without inventory rows have no cabins rather than no value
+        for flight_id in flight_ids {
+            cabins.entry(*flight_id).or_default();
+        }
+        Ok(cabins)
+    }
+}
+
+fn group_by_flight(rows: Vec<(i64, String, i64, i64)>) -> HashMap<i64, Vec<CabinAvailability>> {
+    let mut flights: HashMap<i64, Vec<CabinAvailability>> = HashMap::new();
+    for (flight_id, cabin, capacity, sold) in rows {
+        let Ok(cabin) = cabin.parse() else { continue };
+        flights.entry(flight_id).or_default().push(CabinAvailability {
+            cabin,
+            capacity,
+            seats_remaining: (capacity - sold).max(0),
+        });
+    }
+    for cabins in flights.values_mut() {
+        cabins.sort_by_key(|c| c.cabin as u8);
+    }
+    flights
+}
+
+/// Take `seats` seats in a cabin inside the booking transaction.
+/// The conditional update is atomic, so concurrent bookings cannot oversell.
+pub async fn reserve(
+    tx: &mut Transaction<'_, Sqlite>,
+    flight_id: i64,
+    cabin: Cabin,
+    seats: i64,
+) -> async_graphql::Result<()> {
+    let result = sqlx::query(
+        "UPDATE seat_inventory SET seats_sold = seats_sold + ? WHERE flight_id = ? AND cabin = ? AND capacity - seats_sold >= ?",
+    )
+    .bind(seats)
+    .bind(flight_id)
+    .bind(cabin.as_str())
+    .bind(seats)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 1 {
+        return Ok(());
+    }
+
+    let remaining: Option<(i64,)> = sqlx::query_as(
+        "SELECT capacity - seats_sold FROM seat_inventory WHERE flight_id = ? AND cabin = ?",
+    )
+    .bind(flight_id)
+    .bind(cabin.as_str())
+    .fetch_optional(&mut *tx)
+    .await?;
+    let error = match remaining {
+        Some((remaining,)) => ApiError::SoldOut { flight_id, cabin, remaining },
+        None => ApiError::CabinNotOffered { flight_id, cabin },
+    };
+    Err(error.extend())
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, FixedOffset};
+use sqlx::SqlitePool;
+
+use crate::schema::{FlightOffer, Itinerary, Layover};
+use crate::search;
+
+/// Minimum time on the ground between two connecting segments
+pub const MIN_CONNECTION_MINUTES: i64 = 45;
+/// Longest door-to-door journey we are willing to offer
+pub const MAX_JOURNEY_HOURS: i64 = 24;
+/// Itineraries may have at most this many intermediate stops
+pub const MAX_STOPS: usize = 2;
+
+/// Parse a stored flight time, which carries the UTC offset of its airport
+pub fn parse_time(value: &str) -> async_graphql::Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value)
+        .map_err(|_| async_graphql::Error::new(format!("Invalid flight time '{}'", value)))
+}
+
+/// A flight with its parsed schedule, used while building connections
+struct Leg {
+    offer: FlightOffer,
+    departs: DateTime<FixedOffset>,
+    arrives: DateTime<FixedOffset>,
+}
+
+/// Build direct, 1-stop and 2-stop itineraries between two airports.
+/// The first segment must depart on one of the requested dates (if any).
+pub async fn find_itineraries(
+    pool: &SqlitePool,
+    origin: &str,
+    destination: &str,
+    dates: &[String],
+    max_stops: usize,
+) -> async_graphql::Result<Vec<Itinerary>> {
+    if max_stops > MAX_STOPS {
+        return Err(async_graphql::Error::new(format!(
+            "maxStops cannot exceed {}",
+            MAX_STOPS
+        )));
+    }
+    let days = search::requested_days(dates)?;
+
+    let flights = match (days.first(), days.last()) {
+        (Some(first), Some(last)) => {
+            // Connections can run past the last requested day
+            let window_end = *last + Duration::days((MAX_JOURNEY_HOURS + 23) / 24);
+            sqlx::query_as::<_, FlightOffer>(
+                "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE substr(departure_time, 1, 10) BETWEEN ? AND ? ORDER BY julianday(departure_time), id",
+            )
+            .bind(first.to_string())
+            .bind(window_end.to_string())
+            .fetch_all(pool)
+            .await?
+        }
+        _ => {
+            sqlx::query_as::<_, FlightOffer>(
+                "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights ORDER BY julianday(departure_time), id",
+            )
+            .fetch_all(pool)
+            .await?
+        }
+    };
+
+    let mut by_origin: HashMap<String, Vec<Leg>> = HashMap::new();
+    for offer in flights {
+        let departs = parse_time(&offer.departure_time)?;
+        let arrives = parse_time(&offer.arrival_time)?;
+        by_origin.entry(offer.origin.clone()).or_default().push(Leg { offer, departs, arrives });
+    }
+
+    let mut itineraries = Vec::new();
+    let first_legs = by_origin
+        .get(origin)
+        .into_iter()
+        .flatten()
+        .filter(|leg| days.is_empty() || days.binary_search(&leg.departs.date_naive()).is_ok());
+    for leg in first_legs {
+        let mut path = vec![leg];
+        extend(&by_origin, destination, max_stops, &mut path, &mut itineraries);
+    }
+
+    itineraries.sort_by(|a, b| {
+        a.total_price
+            .total_cmp(&b.total_price)
+            .then(a.total_duration_minutes.cmp(&b.total_duration_minutes))
+    });
+    Ok(itineraries)
+}
+
+/// Depth-first walk of onward connections from the last leg in `path`
+fn extend<'a>(
+    by_origin: &'a HashMap<String, Vec<Leg>>,
+    destination: &str,
+    max_stops: usize,
+    path: &mut Vec<&'a Leg>,
+    out: &mut Vec<Itinerary>,
+) {
+    let last = *path.last().expect("path always has a first leg");
+    if last.offer.destination == destination {
+        out.push(to_itinerary(path));
+        return;
+    }
+    if path.len() > max_stops {
+        return;
+    }
+
+    let journey_start = path[0].departs;
+    let onward = by_origin.get(&last.offer.destination).into_iter().flatten();
+    for next in onward {
+        let connection = next.departs - last.arrives;
+        if connection < Duration::minutes(MIN_CONNECTION_MINUTES)
+            || next.arrives - journey_start > Duration::hours(MAX_JOURNEY_HOURS)
+        {
+            continue;
+        }
+        // Never route back through an airport already on the itinerary
+        if path.iter().any(|leg| leg.offer.origin == next.offer.destination) {
+            continue;
+        }
+        path.push(next);
+        extend(by_origin, destination, max_stops, path, out);
+        path.pop();
+    }
+}
+
+fn to_itinerary(path: &[&Leg]) -> Itinerary {
+    let first = path[0];
+    let last = path[path.len() - 1];
+    let layovers = path
+        .windows(2)
+        .map(|pair| Layover {
+            airport: pair[0].offer.destination.clone(),
+            duration_minutes: (pair[1].departs - pair[0].arrives).num_minutes(),
+        })
+        .collect();
+    Itinerary {
+        segments: path.iter().map(|leg| leg.offer.clone()).collect(),
+        layovers,
+        stops: (path.len() - 1) as i32,
+        departure_time: first.offer.departure_time.clone(),
+        arrival_time: last.offer.arrival_time.clone(),
+        total_duration_minutes: (last.arrives - first.departs).num_minutes(),
+        total_price: path.iter().map(|leg| leg.offer.price).sum(),
+    }
+}
@@ -1,11 +1,12 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 // Use axum's serve utility with a Tokio TCP listener
 use tokio::net::TcpListener;
 use axum::serve;
 use axum::{
     extract::Extension,
-    http::StatusCode,
-    response::{IntoResponse, Html, Json},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Html, Json, Response},
     routing::{get, post, get_service},
     Router,
     middleware,
@@ -14,172 +15,224 @@ use async_graphql::{EmptySubscription, Schema};
 use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
 use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
 use sqlx::SqlitePool;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
-use tracing_subscriber;
 use tracing::{info, debug};
-use std::sync::Arc;
 
 mod schema;
+mod config;
+mod db;
 mod bot_schema;
 mod bot_detection;
+mod search;
+mod itinerary;
+mod trips;
+mod booking;
+mod inventory;
+mod errors;
+mod intents;
+mod seatmap;
+mod passengers;
+mod payment;
+mod behavior;
+mod sessions;
+mod query_tracking;
+mod agents;
+mod api_keys;
+mod rate_limit;
+mod addons;
+mod offers;
+mod negotiation;
+mod fare_rules;
+mod price_history;
+mod airports;
 
 use schema::{MutationRoot, QueryRoot};
-use bot_schema::{BotQueryRoot, BotMutationRoot};
-use bot_detection::{bot_detection_middleware, BotInfo};
+use bot_schema::{BotIntentRecord, BotMutation, BotQuery, Session};
+use payment::{MockPaymentProcessor, SharedPaymentProcessor};
+use api_keys::ApiScope;
+use errors::ApiError;
+use bot_detection::{
+    bot_detection_middleware, mark_bot_endpoint, should_use_bot_api, ApiVariant, BotInfo, Detector, API_VARIANT_HEADER,
+};
+use config::{Config, CorsConfig};
+use query_tracking::QueryTracking;
+use rate_limit::{rate_limit_middleware, RateLimiter};
+use sessions::{OperationFields, SessionStore, SESSION_HEADER};
 
 /// Combined GraphQL schema type for regular users
 type AppSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
 
-/// Bot-specific GraphQL schema type
-type BotSchema = Schema<BotQueryRoot, BotMutationRoot, EmptySubscription>;
+/// Bot-specific GraphQL schema type: the human schema merged with bot-only fields
+type BotSchema = Schema<BotQuery, BotMutation, EmptySubscription>;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize tracing for request logging
     tracing_subscriber::fmt::init();
 
+    // Load configuration from `--config <file>` (if given) and the environment
+    let config_path = config::config_path_from_args(std::env::args().skip(1));
+    let config = Config::load(config_path.as_deref())?;
+
     // Ensure a writable temp directory for SQLite operations (e.g., journaling)
-    let tmp_dir = "./tmp";
+    let tmp_dir = &config.database.tmp_dir;
     std::fs::create_dir_all(tmp_dir)?;
     // SAFETY: setting environment variable is thread-safe at this point
     unsafe { std::env::set_var("SQLITE_TMPDIR", tmp_dir); }
 
-    // Initialize SQLite connection pool
-    // Use SQLite file in current directory
-    // Use in-memory SQLite database for demo (no file permissions issues)
-    let database_url = "sqlite::memory:";
-    let pool = SqlitePool::connect(database_url).await?;
-
-    // Create tables if they do not exist
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS flights (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            origin TEXT NOT NULL,
-            destination TEXT NOT NULL,
-            departure_time TEXT NOT NULL,
-            arrival_time TEXT NOT NULL,
-            price REAL NOT NULL
-        );
-        "#,
-    )
-    .execute(&pool)
-    .await?;
-
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS bookings (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            flight_id INTEGER NOT NULL,
-            passenger_details TEXT NOT NULL,
-            payment_details TEXT NOT NULL,
-            booking_time TEXT NOT NULL
-        );
-        "#,
-    )
-    .execute(&pool)
-    .await?;
-
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS bot_intents (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            agent_type TEXT NOT NULL,
-            confidence REAL NOT NULL,
-            intent_type TEXT NOT NULL,
-            query_params TEXT,
-            reason TEXT,
-            additional_context TEXT,
-            recorded_time TEXT NOT NULL
-        );
-        "#,
-    )
-    .execute(&pool)
-    .await?;
-
-    // Seed flight data if empty
-    let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM flights")
-        .fetch_one(&pool)
-        .await?;
-    if count.0 == 0 {
-        let sample_flights = vec![
-            ("NYC", "LAX", "2025-06-01T08:00:00", "2025-06-01T11:00:00", 199.0),
-            ("NYC", "SFO", "2025-06-02T09:00:00", "2025-06-02T12:30:00", 249.0),
-            ("LAX", "SEA", "2025-06-03T07:00:00", "2025-06-03T09:45:00", 149.0),
-        ];
-        for (origin, destination, dep, arr, price) in sample_flights {
-            sqlx::query(
-                "INSERT INTO flights (origin, destination, departure_time, arrival_time, price) VALUES (?, ?, ?, ?, ?)",
-            )
-            .bind(origin)
-            .bind(destination)
-            .bind(dep)
-            .bind(arr)
-            .bind(price)
-            .execute(&pool)
-            .await?;
-        }
+    // `--schema-version` reports the schema state without touching the database
+    let database_url = &config.database.url;
+    if std::env::args().any(|arg| arg == "--schema-version") {
+        let pool = db::connect_read_only(database_url).await?;
+        let version = db::schema_version(&pool).await?;
+        let current = version.current.map_or("none".to_string(), |v| v.to_string());
+        let state = if version.is_current() { "up to date" } else { "migrations pending" };
+        println!("{}: schema version {} of {} ({})", database_url, current, version.latest, state);
+        return Ok(());
     }
 
+    // Open the configured SQLite database, bring the schema up to date and
+    // seed demo data on first start
+    let pool = db::connect(database_url).await?;
+    db::migrate(&pool).await?;
+    let version = db::schema_version(&pool).await?;
+    info!("Database {} at schema version {:?}", database_url, version.current);
+    db::seed_sample_flights(&pool).await?;
+
+    // Both schemas share one payment processor so tokens work across APIs;
+    // its tokens are stored in the database and survive restarts
+    let payments: SharedPaymentProcessor = Arc::new(MockPaymentProcessor::new(pool.clone()));
+
+    // Sessions are shared by detection and the query tracking of both schemas
+    let sessions = SessionStore::default();
+    let limits = &config.graphql;
+
     // Build GraphQL schema for human users
     let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
         .data(pool.clone())
+        .data(payments.clone())
+        .data(inventory::loader(pool.clone()))
+        .data(airports::loader(pool.clone()))
+        .extension(QueryTracking::new(sessions.clone()))
+        .limit_depth(limits.max_depth)
+        .limit_complexity(limits.max_complexity)
         .finish();
 
     // Build GraphQL schema for bots
-    let bot_schema = Schema::build(BotQueryRoot, BotMutationRoot, EmptySubscription)
+    let bot_schema = Schema::build(BotQuery::default(), BotMutation::default(), EmptySubscription)
         .data(pool.clone())
+        .data(payments.clone())
+        .data(inventory::loader(pool.clone()))
+        .data(airports::loader(pool.clone()))
+        .data(config.negotiation.clone())
+        .data(sessions.clone())
+        .extension(QueryTracking::new(sessions.clone()))
+        .limit_depth(limits.max_depth)
+        .limit_complexity(limits.max_complexity)
         .finish();
 
     // Paths for React static files
-    let static_dir = ServeDir::new("./static").append_index_html_on_directories(true);
-    let index_file = ServeFile::new("./static/index.html");
+    let index_file = ServeFile::new(&config.static_files.index_file);
+
+    // Detection identifies clients by peer address, or behind trusted proxies by forwarded address
+    let detector = Detector::new(config.detection.clone(), config.server.trusted_proxies.clone(), pool.clone(), sessions.clone());
+
+    // Bot endpoints; sessions that use them are counted as using the bot API
+    let bot_endpoints = Router::new()
+        // Bot-specific GraphQL endpoint
+        .route("/bot/graphql", post(bot_graphql_handler))
+        // Bot intent endpoint
+        .route("/bot/intent", post(intent_handler).get(list_intents_handler))
+        // Public keys for signed agent requests
+        .route("/bot/agents", post(register_agent_handler))
+        .layer(middleware::map_response(mark_bot_endpoint));
 
     // Build Axum application with routes and static file fallback
     let app = Router::new()
         // First define all routes
         // Regular GraphQL endpoint
         .route("/graphql", get(graphql_playground).post(graphql_handler))
-        // Bot-specific GraphQL endpoint
-        .route("/bot/graphql", post(bot_graphql_handler))
-        // Bot intent endpoint
-        .route("/bot/intent", post(intent_handler).get(list_intents_handler))
-        // Behavior metrics endpoint for client-side tracking
+        .merge(bot_endpoints)
+        // Behavior metrics endpoint for client-side tracking; the web app
+        // reports here, so it is not a bot endpoint
         .route("/bot/behaviorMetrics", post(behavior_metrics_handler))
         // Serve the React app entrypoint
         .route("/", get_service(index_file))
         // Serve static files using proper nesting
-        .nest_service("/static", ServeDir::new("./static/static"))
-        // Then apply middleware to all routes
-        .route_layer(middleware::from_fn(bot_detection_middleware))
+        .nest_service("/static", ServeDir::new(&config.static_files.assets_dir))
+        // Then apply middleware to all routes; layers added later run first,
+        // so detection has classified the request before it is rate limited
+        .route_layer(middleware::from_fn_with_state(RateLimiter::new(config.rate_limit.clone(), sessions.clone()), rate_limit_middleware))
+        .route_layer(middleware::from_fn_with_state(detector, bot_detection_middleware))
         // Add schema data to all routes
         .layer(Extension(schema))
         .layer(Extension(bot_schema))
         .layer(Extension(pool.clone()))
+        .layer(Extension(sessions))
+        .layer(cors_layer(&config.cors))
         // Add tracing layer
         .layer(TraceLayer::new_for_http());
 
-    // Start the server
-    // Listen on localhost to satisfy sandbox permissions
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8000));
+    // Start the server on the configured address
+    let addr = config.server.listen;
     println!("Server running at http://{}", addr);
     // Bind the TCP listener and serve our application
     let listener = TcpListener::bind(addr).await?;
-    serve(listener, app).await?;
+    // Peer addresses identify clients for the timing signal and rate limits
+    serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
 
-/// Handler for standard GraphQL queries and mutations
+/// CORS headers for the configured origins; no headers when none are configured
+fn cors_layer(cors: &CorsConfig) -> CorsLayer {
+    if cors.allowed_origins.is_empty() {
+        return CorsLayer::new();
+    }
+    let origins = if cors.allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(cors.allowed_origins.iter().filter_map(|o| o.parse::<HeaderValue>().ok()))
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers(Any)
+        .expose_headers(
+            [API_VARIANT_HEADER, SESSION_HEADER, "ratelimit-limit", "ratelimit-remaining", "ratelimit-reset", "retry-after"]
+                .map(HeaderName::from_static),
+        )
+}
+
+/// Handler for `/graphql`: detected bots are served the bot schema
+/// unless they opt out with `X-Api-Variant: human`
 async fn graphql_handler(
     Extension(schema): Extension<AppSchema>,
+    Extension(bot_schema): Extension<BotSchema>,
     bot_info: Option<Extension<BotInfo>>,
+    session: Option<Extension<Session>>,
+    headers: HeaderMap,
     req: GraphQLRequest,
-) -> GraphQLResponse {
+) -> impl IntoResponse {
+    let requested = ApiVariant::requested(&headers);
+    let use_bot_api = match &bot_info {
+        Some(Extension(info)) => should_use_bot_api(info, requested),
+        None => requested == Some(ApiVariant::Bot),
+    };
+    let session = session.map(|Extension(session)| session);
+    if use_bot_api {
+        return execute_bot_request(bot_schema, bot_info.map(|Extension(info)| info), session, req).await;
+    }
+
     // Create a request with BotInfo data if available
     let mut request = req.into_inner();
-    
+    let fields = sessions::operation_fields(&mut request);
+    if let Some(session) = session {
+        request = request.data(session);
+    }
+
     if let Some(Extension(info)) = bot_info {
         // Log the detection info
         debug!(
@@ -192,28 +245,49 @@ async fn graphql_handler(
         request = request.data(info_clone);
     }
     
-    schema.execute(request).await.into()
+    let response: GraphQLResponse = schema.execute(request).await.into();
+    ([(API_VARIANT_HEADER, ApiVariant::Human.as_str())], Extension(fields), response)
 }
 
 /// Handler for bot-specific GraphQL queries and mutations
 async fn bot_graphql_handler(
     Extension(bot_schema): Extension<BotSchema>,
     bot_info: Option<Extension<BotInfo>>,
+    session: Option<Extension<Session>>,
+    req: GraphQLRequest,
+) -> impl IntoResponse {
+    execute_bot_request(
+        bot_schema,
+        bot_info.map(|Extension(info)| info),
+        session.map(|Extension(session)| session),
+        req,
+    )
+    .await
+}
+
+/// Run a request against the bot schema
+async fn execute_bot_request(
+    bot_schema: BotSchema,
+    bot_info: Option<BotInfo>,
+    session: Option<Session>,
     req: GraphQLRequest,
-) -> GraphQLResponse {
+) -> ([(&'static str, &'static str); 1], Extension<OperationFields>, GraphQLResponse) {
     // Create a request with BotInfo data if available
     let mut request = req.into_inner();
-    
-    if let Some(Extension(info)) = bot_info {
+    let fields = sessions::operation_fields(&mut request);
+    if let Some(session) = session {
+        request = request.data(session);
+    }
+
+    if let Some(info) = bot_info {
         // Clone the info for logging
         let agent_type = info.agent_type.clone();
         let confidence = info.confidence_score;
         let query = request.query.clone();
         let is_bot = info.is_likely_bot();
         
-        // Add the bot info to the request context (clone it before moving)
-        let info_clone = info.clone();
-        request = request.data(info_clone);
+        // Add the bot info to the request context
+        request = request.data(info);
         
         // Log bot API usage
         info!(
@@ -228,79 +302,112 @@ async fn bot_graphql_handler(
         info!("Bot API request from unknown client");
     }
     
-    bot_schema.execute(request).await.into()
+    let response: GraphQLResponse = bot_schema.execute(request).await.into();
+    ([(API_VARIANT_HEADER, ApiVariant::Bot.as_str())], Extension(fields), response)
 }
 
-/// Handler for client-side behavior metrics
+/// Handler for client-side behavior metrics; responds with the stored report id
 async fn behavior_metrics_handler(
     bot_info: Option<Extension<BotInfo>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(sessions): Extension<SessionStore>,
     Json(payload): Json<serde_json::Value>,
 ) -> impl IntoResponse {
+    let bot_info = bot_info.map(|Extension(info)| info);
     // Log the received metrics
-    if let Some(Extension(info)) = bot_info {
-        let agent_type = info.agent_type.clone();
-        let confidence = info.confidence_score;
-        
-        debug!(
-            "Received metrics from client: agent={}, confidence={}, metrics={}",
-            agent_type,
-            confidence,
+    match &bot_info {
+        Some(info) => debug!(
+            "Received metrics from client: agent={}, confidence={}, session={}, metrics={}",
+            info.agent_type,
+            info.confidence_score,
+            info.session_id,
             payload
-        );
-    } else {
-        debug!("Received metrics from unknown client: {}", payload);
+        ),
+        None => debug!("Received metrics from unknown client: {}", payload),
+    }
+
+    match behavior::record(&pool, &sessions, bot_info.as_ref(), &payload).await {
+        Ok(id) => (StatusCode::CREATED, Json(serde_json::json!({ "id": id }))),
+        Err(err) => error_response(err),
     }
-    
-    // Simply acknowledge receipt
-    StatusCode::OK
 }
 
-/// Handler for explicit bot intent
+/// Handler for explicit bot intent; responds with the stored intent id
 async fn intent_handler(
     bot_info: Option<Extension<BotInfo>>,
     Extension(pool): Extension<SqlitePool>,
     Json(intent): Json<crate::bot_schema::BotIntent>,
 ) -> impl IntoResponse {
-    let (agent_type, confidence) = if let Some(Extension(info)) = bot_info {
-        info!(
+    let bot_info = bot_info.map(|Extension(info)| info);
+    match &bot_info {
+        Some(info) => info!(
             "Bot intent: agent={}, confidence={}, intent={:?}",
             info.agent_type,
             info.confidence_score,
             intent
-        );
-        (info.agent_type.clone(), info.confidence_score)
+        ),
+        None => info!("Bot intent from unknown agent: {:?}", intent),
+    }
+
+    match intents::record(&pool, bot_info.as_ref(), &intent).await {
+        Ok(id) => (StatusCode::CREATED, Json(serde_json::json!({ "id": id }))),
+        Err(err) => error_response(err),
+    }
+}
+
+/// Error body of the REST endpoints: 500 for server-side failures, 400 for anything the client can fix
+fn error_response(err: async_graphql::Error) -> (StatusCode, Json<serde_json::Value>) {
+    let code = err.extensions.as_ref().and_then(|e| e.get("code")).cloned();
+    let status = if code == Some(async_graphql::Value::from(ApiError::Internal.code())) {
+        StatusCode::INTERNAL_SERVER_ERROR
     } else {
-        info!("Bot intent from unknown agent: {:?}", intent);
-        ("unknown".to_string(), 0.0)
+        StatusCode::BAD_REQUEST
     };
+    (status, Json(serde_json::json!({ "error": err.message, "code": code })))
+}
 
-    let _ = sqlx::query(
-        "INSERT INTO bot_intents (agent_type, confidence, intent_type, query_params, reason, additional_context, recorded_time) VALUES (?,?,?,?,?,?,datetime('now'))"
-    )
-    .bind(agent_type)
-    .bind(confidence)
-    .bind(&intent.intent_type)
-    .bind(intent.query_params.as_ref().map(|v| v.to_string()))
-    .bind(&intent.reason)
-    .bind(intent.additional_context.as_ref().map(|v| v.to_string()))
-    .execute(&pool)
-    .await;
-
-    StatusCode::OK
+/// Body of `POST /bot/agents`
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AgentKey {
+    name: String,
+    public_key: String,
 }
 
-/// Retrieve stored bot intents
-async fn list_intents_handler(
+/// Register an agent's public key; responds with the agent id to sign with
+async fn register_agent_handler(
     Extension(pool): Extension<SqlitePool>,
+    Json(key): Json<AgentKey>,
 ) -> impl IntoResponse {
-    let rows = sqlx::query_as::<_, crate::bot_schema::BotIntentRecord>(
-        "SELECT id, agent_type, confidence, intent_type, query_params, reason, additional_context, recorded_time FROM bot_intents ORDER BY id DESC"
-    )
-    .fetch_all(&pool)
-    .await
-    .unwrap_or_default();
+    match agents::register(&pool, &key.name, &key.public_key).await {
+        Ok(agent) => (StatusCode::CREATED, Json(serde_json::json!({ "agentId": agent.agent_id, "name": agent.name }))),
+        Err(err) => error_response(err),
+    }
+}
 
-    Json(rows)
+/// Retrieve the calling agent's stored intents; needs the `read-intents` scope
+async fn list_intents_handler(
+    bot_info: Option<Extension<BotInfo>>,
+    Extension(pool): Extension<SqlitePool>,
+) -> Response {
+    let Some(Extension(info)) = bot_info else {
+        return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({ "error": "No credentials" }))).into_response();
+    };
+    if let Some(err) = api_keys::check_scope(&info, ApiScope::ReadIntents, "GET /bot/intent") {
+        let status = match err {
+            ApiError::MissingScope { .. } => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        let body = serde_json::json!({ "error": err.to_string(), "code": err.code(), "scope": ApiScope::ReadIntents.as_str() });
+        return (status, Json(body)).into_response();
+    }
+    let Some(agent_id) = info.agent_id.as_deref() else {
+        return Json(Vec::<BotIntentRecord>::new()).into_response();
+    };
+    match intents::list(&pool, Some(agent_id)).await {
+        Ok(rows) => Json(rows).into_response(),
+        Err(err) => error_response(err).into_response(),
+    }
 }
 
 /// GraphQL playground endpoint for human users
@@ -310,4 +417,5 @@ async fn graphql_playground() -> impl IntoResponse {
 
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests;
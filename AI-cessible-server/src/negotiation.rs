@@ -0,0 +1,322 @@
+use async_graphql::ErrorExtensions;
+use sqlx::{Executor, Sqlite, SqlitePool, Transaction};
+
+use crate::addons::round_cents;
+use crate::bot_schema::{Negotiation, NegotiationRound, NegotiationStatus};
+use crate::config::NegotiationConfig;
+use crate::errors::ApiError;
+use crate::passengers::MAX_PASSENGERS;
+use crate::booking::Owner;
+use crate::schema::{Cabin, FlightOffer};
+use crate::{booking, fare_rules, offers};
+
+/// Rule name reported for flights no policy rule matches
+const NO_RULE: &str = "none";
+
+#[derive(sqlx::FromRow)]
+struct NegotiationRow {
+    id: String,
+    agent_id: Option<String>,
+    session_id: Option<String>,
+    flight_id: i64,
+    cabin: String,
+    passengers: i64,
+    rule: String,
+    list_price: f64,
+    floor_price: f64,
+    asking_price: f64,
+    agreed_price: Option<f64>,
+    status: String,
+    offer_id: Option<String>,
+    offer_expires_at: Option<String>,
+    expires_at: String,
+    expired: bool,
+}
+
+const NEGOTIATION_SELECT: &str = "SELECT n.id, n.agent_id, n.session_id, n.flight_id, n.cabin, n.passengers, n.rule, n.list_price, \
+     n.floor_price, n.asking_price, n.agreed_price, n.status, n.offer_id, o.expires_at AS offer_expires_at, \
+     n.expires_at, n.expires_at <= datetime('now') AS expired \
+     FROM negotiations n LEFT JOIN offers o ON o.id = n.offer_id WHERE n.id = ?";
+
+/// Open a negotiation over the fare of a flight, priced by the first policy
+/// rule matching its route and the cabin's load factor. A proposed price is
+/// handled as the first counter-offer.
+#[allow(clippy::too_many_arguments)]
+pub async fn start(
+    pool: &SqlitePool,
+    config: &NegotiationConfig,
+    owner: &Owner,
+    flight_id: i64,
+    cabin: Cabin,
+    passengers: i64,
+    proposed_price: Option<f64>,
+) -> async_graphql::Result<Negotiation> {
+    if !(1..=MAX_PASSENGERS as i64).contains(&passengers) {
+        return Err(async_graphql::Error::new(format!("passengers must be between 1 and {}", MAX_PASSENGERS)));
+    }
+    if owner.agent_id.is_none() && owner.session_id.is_none() {
+        return Err(async_graphql::Error::new("Negotiations need a session or an agent to belong to"));
+    }
+    let flight = booking::fetch_flight(pool, flight_id).await?;
+    let (capacity, sold): (i64, i64) =
+        sqlx::query_as("SELECT capacity, seats_sold FROM seat_inventory WHERE flight_id = ? AND cabin = ?")
+            .bind(flight_id)
+            .bind(cabin.as_str())
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| ApiError::CabinNotOffered { flight_id, cabin }.extend())?;
+    if capacity - sold < passengers {
+        return Err(ApiError::SoldOut { flight_id, cabin, remaining: (capacity - sold).max(0) }.extend());
+    }
+
+    let load_factor = if capacity > 0 { sold as f64 / capacity as f64 } else { 1.0 };
+    let list_price = round_cents(flight.price * fare_rules::cabin_fare_multiplier(pool, cabin).await?);
+    let rule = config.rules.iter().find(|r| r.matches(&flight.origin, &flight.destination, load_factor));
+    let (rule_name, asking_price, floor_price) = match rule {
+        Some(rule) => {
+            let floor = (list_price * (1.0 - rule.max_discount_percent / 100.0))
+                .max(rule.floor_price.unwrap_or(0.0))
+                .min(list_price);
+            let asking = (list_price * (1.0 - rule.opening_discount_percent / 100.0)).max(floor);
+            (rule.name.as_str(), round_cents(asking), round_cents(floor))
+        }
+        None => (NO_RULE, list_price, list_price),
+    };
+
+    let negotiation_id = format!("neg_{}", uuid::Uuid::new_v4().simple());
+    sqlx::query(
+        "INSERT INTO negotiations (id, agent_id, session_id, flight_id, cabin, passengers, rule, list_price, floor_price, asking_price, status, created_time, expires_at) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'), datetime('now', ?))",
+    )
+    .bind(&negotiation_id)
+    .bind(&owner.agent_id)
+    .bind(&owner.session_id)
+    .bind(flight_id)
+    .bind(cabin.as_str())
+    .bind(passengers)
+    .bind(rule_name)
+    .bind(list_price)
+    .bind(floor_price)
+    .bind(asking_price)
+    .bind(NegotiationStatus::Open.as_str())
+    .bind(format!("+{} minutes", config.session_minutes))
+    .execute(pool)
+    .await?;
+
+    match proposed_price {
+        Some(price) => counter(pool, config, owner, &negotiation_id, price).await,
+        None => get(pool, config, owner, &negotiation_id).await,
+    }
+}
+
+/// Answer a counter-offer. An offer at or above the asking price is agreed at
+/// the asking price; otherwise the asking price concedes part of the way to
+/// the floor and the offer is agreed if it reaches it. Without agreement after
+/// the last round the negotiation closes.
+pub async fn counter(
+    pool: &SqlitePool,
+    config: &NegotiationConfig,
+    owner: &Owner,
+    negotiation_id: &str,
+    proposed_price: f64,
+) -> async_graphql::Result<Negotiation> {
+    if !(proposed_price > 0.0 && proposed_price.is_finite()) {
+        return Err(async_graphql::Error::new("proposedPrice must be a positive amount"));
+    }
+    let flight = booking::fetch_flight(pool, load(pool, owner, negotiation_id).await?.flight_id).await?;
+    let mut tx = pool.begin().await.map_err(|err| ApiError::from(err).extend())?;
+    let row = claim_open(&mut tx, owner, negotiation_id).await?;
+    let (round,): (i64,) = sqlx::query_as("SELECT COUNT(*) + 1 FROM negotiation_rounds WHERE negotiation_id = ?")
+        .bind(negotiation_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|err| ApiError::from(err).extend())?;
+
+    // The rule may have been reconfigured since; its last concession is kept otherwise
+    let concession = config.rules.iter().find(|r| r.name == row.rule).map_or(0.5, |r| r.concession);
+    let proposed_price = round_cents(proposed_price);
+    let (asking_price, agreed_price) = if proposed_price >= row.asking_price {
+        (row.asking_price, Some(row.asking_price))
+    } else {
+        let asking = round_cents(row.asking_price - (row.asking_price - row.floor_price) * concession);
+        (asking, (proposed_price >= asking).then_some(proposed_price))
+    };
+
+    let recorded = sqlx::query(
+        "INSERT INTO negotiation_rounds (negotiation_id, round, proposed_price, asking_price, accepted, recorded_time) \
+         VALUES (?, ?, ?, ?, ?, datetime('now')) ON CONFLICT (negotiation_id, round) DO NOTHING",
+    )
+    .bind(negotiation_id)
+    .bind(round)
+    .bind(proposed_price)
+    .bind(asking_price)
+    .bind(agreed_price.is_some())
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| ApiError::from(err).extend())?;
+    if recorded.rows_affected() == 0 {
+        return Err(ApiError::NegotiationConflict(negotiation_id.to_string()).extend());
+    }
+
+    match agreed_price {
+        Some(price) => agree(&mut tx, config, &row, &flight, price).await?,
+        None => {
+            let status = if round >= config.max_rounds { NegotiationStatus::Closed } else { NegotiationStatus::Open };
+            let updated = sqlx::query("UPDATE negotiations SET asking_price = ?, status = ? WHERE id = ? AND status = ?")
+                .bind(asking_price)
+                .bind(status.as_str())
+                .bind(negotiation_id)
+                .bind(NegotiationStatus::Open.as_str())
+                .execute(&mut *tx)
+                .await
+                .map_err(|err| ApiError::from(err).extend())?;
+            if updated.rows_affected() == 0 {
+                return Err(ApiError::NegotiationConflict(negotiation_id.to_string()).extend());
+            }
+        }
+    }
+    tx.commit().await.map_err(|err| ApiError::from(err).extend())?;
+    get(pool, config, owner, negotiation_id).await
+}
+
+/// Agree to the current asking price
+pub async fn accept(
+    pool: &SqlitePool,
+    config: &NegotiationConfig,
+    owner: &Owner,
+    negotiation_id: &str,
+) -> async_graphql::Result<Negotiation> {
+    let flight = booking::fetch_flight(pool, load(pool, owner, negotiation_id).await?.flight_id).await?;
+    let mut tx = pool.begin().await.map_err(|err| ApiError::from(err).extend())?;
+    let row = claim_open(&mut tx, owner, negotiation_id).await?;
+    agree(&mut tx, config, &row, &flight, row.asking_price).await?;
+    tx.commit().await.map_err(|err| ApiError::from(err).extend())?;
+    get(pool, config, owner, negotiation_id).await
+}
+
+/// A negotiation and its rounds, as seen by the agent or session that opened it
+pub async fn get(
+    pool: &SqlitePool,
+    config: &NegotiationConfig,
+    owner: &Owner,
+    negotiation_id: &str,
+) -> async_graphql::Result<Negotiation> {
+    let row = load(pool, owner, negotiation_id).await?;
+    let rounds = sqlx::query_as::<_, (i64, f64, f64, bool)>(
+        "SELECT round, proposed_price, asking_price, accepted FROM negotiation_rounds WHERE negotiation_id = ? ORDER BY round",
+    )
+    .bind(negotiation_id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(round, proposed_price, asking_price, accepted)| NegotiationRound {
+        round,
+        proposed_price,
+        asking_price,
+        accepted,
+    })
+    .collect::<Vec<_>>();
+
+    let mut status: NegotiationStatus = row.status.parse()?;
+    if status == NegotiationStatus::Open && row.expired {
+        status = NegotiationStatus::Expired;
+    }
+    let rounds_left = if status == NegotiationStatus::Open { (config.max_rounds - rounds.len() as i64).max(0) } else { 0 };
+    let price = row.agreed_price.unwrap_or(row.asking_price);
+    Ok(Negotiation {
+        negotiation_id: row.id,
+        flight_id: row.flight_id,
+        cabin: row.cabin.parse()?,
+        passengers: row.passengers,
+        status,
+        rule: row.rule,
+        list_price: row.list_price,
+        asking_price: row.asking_price,
+        agreed_price: row.agreed_price,
+        total_price: round_cents(price * row.passengers as f64),
+        rounds_left,
+        rounds,
+        offer_id: row.offer_id,
+        offer_expires_at: row.offer_expires_at,
+        expires_at: row.expires_at,
+    })
+}
+
+/// Negotiations opened by an agent are only visible to that agent, the
+/// others only to the session that opened them
+async fn load<'e, E>(executor: E, owner: &Owner, negotiation_id: &str) -> async_graphql::Result<NegotiationRow>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_as::<_, NegotiationRow>(NEGOTIATION_SELECT)
+        .bind(negotiation_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(|err| ApiError::from(err).extend())?
+        .filter(|row| match &row.agent_id {
+            Some(agent_id) => owner.agent_id.as_ref() == Some(agent_id),
+            None => row.session_id.is_some() && row.session_id == owner.session_id,
+        })
+        .ok_or_else(|| ApiError::NegotiationNotFound(negotiation_id.to_string()).extend())
+}
+
+/// Load a negotiation that still takes offers, holding the database's write
+/// lock for the rest of the transaction. The lock is taken by the first
+/// statement, before anything is read, so concurrent counter-offers and
+/// accepts of one negotiation run one after another and each sees the
+/// others' changes.
+async fn claim_open(
+    tx: &mut Transaction<'_, Sqlite>,
+    owner: &Owner,
+    negotiation_id: &str,
+) -> async_graphql::Result<NegotiationRow> {
+    let claimed = sqlx::query(
+        "UPDATE negotiations SET status = status WHERE id = ? AND status = ? AND expires_at > datetime('now')",
+    )
+    .bind(negotiation_id)
+    .bind(NegotiationStatus::Open.as_str())
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| ApiError::from(err).extend())?;
+    let row = load(&mut *tx, owner, negotiation_id).await?;
+    let status: NegotiationStatus = row.status.parse()?;
+    if status != NegotiationStatus::Open {
+        return Err(ApiError::NegotiationClosed { negotiation_id: row.id, status: status.as_str() }.extend());
+    }
+    if row.expired {
+        return Err(ApiError::NegotiationExpired { negotiation_id: row.id, expires_at: row.expires_at }.extend());
+    }
+    if claimed.rows_affected() == 0 {
+        return Err(ApiError::NegotiationConflict(row.id).extend());
+    }
+    Ok(row)
+}
+
+/// Record the agreed price and issue an offer bookFlight honors at that price
+async fn agree(
+    tx: &mut Transaction<'_, Sqlite>,
+    config: &NegotiationConfig,
+    row: &NegotiationRow,
+    flight: &FlightOffer,
+    price: f64,
+) -> async_graphql::Result<()> {
+    let cabin: Cabin = row.cabin.parse()?;
+    let (offer_id, _) =
+        offers::create_negotiated(tx, &row.id, flight, cabin, row.passengers, price, config.offer_minutes).await?;
+    let updated = sqlx::query(
+        "UPDATE negotiations SET asking_price = ?, agreed_price = ?, status = ?, offer_id = ? WHERE id = ? AND status = ?",
+    )
+    .bind(price)
+    .bind(price)
+    .bind(NegotiationStatus::Agreed.as_str())
+    .bind(&offer_id)
+    .bind(&row.id)
+    .bind(NegotiationStatus::Open.as_str())
+    .execute(&mut *tx)
+    .await
+    .map_err(|err| ApiError::from(err).extend())?;
+    if updated.rows_affected() == 0 {
+        return Err(ApiError::NegotiationConflict(row.id.clone()).extend());
+    }
+    Ok(())
+}
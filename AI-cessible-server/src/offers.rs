@@ -0,0 +1,211 @@
+use async_graphql::ErrorExtensions;
+use sqlx::{Executor, Sqlite, SqlitePool, Transaction};
+use tracing::warn;
+
+use crate::booking::{self, Quote};
+use crate::errors::ApiError;
+use crate::schema::{AddonSelection, Cabin, FlightOffer, LineItemKind, OfferLineItem, PassengerInput};
+use crate::{addons, inventory, passengers, trips};
+
+/// How long a quoted offer can be booked at its price
+pub const OFFER_MINUTES: i64 = 20;
+
+/// Largest difference between the quoted and current total still treated as unchanged
+const PRICE_TOLERANCE: f64 = 0.005;
+
+/// How long unbooked offers are kept after expiring, so booking one still
+/// reports `OFFER_EXPIRED` rather than `OFFER_NOT_FOUND` for a while
+const EXPIRED_RETENTION: &str = "-1 day";
+
+/// Store a quote so it can be booked by id; returns the offer id and when it expires
+pub async fn create(
+    pool: &SqlitePool,
+    flights: &[FlightOffer],
+    cabin: Cabin,
+    passengers: i64,
+    line_items: &[OfferLineItem],
+    total_price: f64,
+) -> async_graphql::Result<(String, String)> {
+    let mut tx = pool.begin().await?;
+    let offer = insert(&mut tx, flights, cabin, passengers, line_items, total_price, None, OFFER_MINUTES).await?;
+    tx.commit().await?;
+    Ok(offer)
+}
+
+/// Store the fare agreed in a negotiation, bookable for `minutes` at that
+/// price, inside the transaction that records the agreement
+pub async fn create_negotiated(
+    tx: &mut Transaction<'_, Sqlite>,
+    negotiation_id: &str,
+    flight: &FlightOffer,
+    cabin: Cabin,
+    passengers: i64,
+    price: f64,
+    minutes: i64,
+) -> async_graphql::Result<(String, String)> {
+    let fare = OfferLineItem {
+        kind: LineItemKind::Fare,
+        code: None,
+        description: format!("Negotiated fare {} to {}", flight.origin, flight.destination),
+        flight_id: Some(flight.id),
+        quantity: passengers,
+        unit_price: price,
+        amount: addons::round_cents(price * passengers as f64),
+    };
+    let total_price = fare.amount;
+    let flights = std::slice::from_ref(flight);
+    insert(tx, flights, cabin, passengers, &[fare], total_price, Some(negotiation_id), minutes).await
+}
+
+/// Delete unbooked offers that expired longer than [`EXPIRED_RETENTION`] ago
+pub async fn purge_expired<'e, E>(executor: E) -> Result<u64, sqlx::Error>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let result = sqlx::query("DELETE FROM offers WHERE booking_id IS NULL AND expires_at <= datetime('now', ?)")
+        .bind(EXPIRED_RETENTION)
+        .execute(executor)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Store an offer, first purging expired ones so the table stays bounded
+#[allow(clippy::too_many_arguments)]
+async fn insert(
+    tx: &mut Transaction<'_, Sqlite>,
+    flights: &[FlightOffer],
+    cabin: Cabin,
+    passengers: i64,
+    line_items: &[OfferLineItem],
+    total_price: f64,
+    negotiation_id: Option<&str>,
+    minutes: i64,
+) -> async_graphql::Result<(String, String)> {
+    if let Err(err) = purge_expired(&mut *tx).await {
+        warn!("Failed to purge expired offers: {}", err);
+    }
+    let offer_id = format!("offer_{}", uuid::Uuid::new_v4().simple());
+    let flight_ids: Vec<String> = flights.iter().map(|f| f.id.to_string()).collect();
+    let (expires_at,): (String,) = sqlx::query_as(
+        "INSERT INTO offers (id, flight_ids, cabin, passengers, line_items, total_price, negotiation_id, created_time, expires_at) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, datetime('now'), datetime('now', ?)) RETURNING expires_at",
+    )
+    .bind(&offer_id)
+    .bind(flight_ids.join(","))
+    .bind(cabin.as_str())
+    .bind(passengers)
+    .bind(serde_json::to_string(line_items)?)
+    .bind(total_price)
+    .bind(negotiation_id)
+    .bind(format!("+{} minutes", minutes))
+    .fetch_one(&mut *tx)
+    .await?;
+    Ok((offer_id, expires_at))
+}
+
+#[derive(sqlx::FromRow)]
+struct OfferRow {
+    flight_ids: String,
+    cabin: String,
+    passengers: i64,
+    line_items: String,
+    total_price: f64,
+    expires_at: String,
+    expired: bool,
+    booking_id: Option<i64>,
+    negotiation_id: Option<String>,
+}
+
+/// Load an offer for booking and check that it still holds: not booked or
+/// expired, quoted for as many seated passengers, seats still available and
+/// the fares and add-ons still priced at the quoted total. Negotiated offers
+/// keep their agreed fare.
+pub async fn revalidate(
+    pool: &SqlitePool,
+    offer_id: &str,
+    passengers: &[PassengerInput],
+) -> async_graphql::Result<(Vec<FlightOffer>, Cabin, Quote)> {
+    let offer = sqlx::query_as::<_, OfferRow>(
+        "SELECT flight_ids, cabin, passengers, line_items, total_price, expires_at, expires_at <= datetime('now') AS expired, booking_id, negotiation_id \
+         FROM offers WHERE id = ?",
+    )
+    .bind(offer_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::OfferNotFound(offer_id.to_string()).extend())?;
+    if offer.booking_id.is_some() {
+        return Err(ApiError::OfferAlreadyBooked(offer_id.to_string()).extend());
+    }
+    if offer.expired {
+        return Err(ApiError::OfferExpired { offer_id: offer_id.to_string(), expires_at: offer.expires_at }.extend());
+    }
+    let seated = passengers::seated_count(passengers);
+    if seated != offer.passengers {
+        return Err(async_graphql::Error::new(format!(
+            "Offer {} was quoted for {} seated passengers, not {}",
+            offer_id, offer.passengers, seated
+        )));
+    }
+
+    let cabin: Cabin = offer.cabin.parse()?;
+    let mut flights = Vec::new();
+    for id in offer.flight_ids.split(',') {
+        flights.push(booking::fetch_flight(pool, id.parse()?).await?);
+    }
+    trips::validate_sequence(&flights)?;
+    for flight in &flights {
+        let remaining = inventory::availability(pool, flight.id)
+            .await?
+            .into_iter()
+            .find(|c| c.cabin == cabin)
+            .map(|c| c.seats_remaining)
+            .ok_or_else(|| ApiError::CabinNotOffered { flight_id: flight.id, cabin }.extend())?;
+        if remaining < seated {
+            return Err(ApiError::SoldOut { flight_id: flight.id, cabin, remaining }.extend());
+        }
+    }
+
+    let quoted: Vec<OfferLineItem> = serde_json::from_str(&offer.line_items)?;
+    let quote = |line_items| Quote { offer_id: Some(offer_id.to_string()), line_items, total_price: offer.total_price };
+    if offer.negotiation_id.is_some() {
+        return Ok((flights, cabin, quote(quoted)));
+    }
+
+    // Re-price the quoted add-ons against the current fares and catalog
+    let selections: Vec<AddonSelection> = quoted
+        .into_iter()
+        .filter(|item| item.kind == LineItemKind::Addon)
+        .map(|item| AddonSelection {
+            code: item.code.unwrap_or_default(),
+            quantity: item.quantity,
+            flight_id: item.flight_id,
+        })
+        .collect();
+    let (line_items, total_price) = addons::price_offer(pool, &flights, cabin, offer.passengers, &selections).await?;
+    if (total_price - offer.total_price).abs() > PRICE_TOLERANCE {
+        return Err(ApiError::PriceChanged {
+            offer_id: offer_id.to_string(),
+            quoted: offer.total_price,
+            current: total_price,
+        }
+        .extend());
+    }
+    Ok((flights, cabin, quote(line_items)))
+}
+
+/// Mark an offer as booked inside the booking transaction, so it is only booked once
+pub async fn mark_booked(
+    tx: &mut Transaction<'_, Sqlite>,
+    offer_id: &str,
+    booking_id: i64,
+) -> async_graphql::Result<()> {
+    let result = sqlx::query("UPDATE offers SET booking_id = ? WHERE id = ? AND booking_id IS NULL")
+        .bind(booking_id)
+        .bind(offer_id)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::OfferAlreadyBooked(offer_id.to_string()).extend());
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use async_graphql::ErrorExtensions;
+use chrono::{Datelike, NaiveDate};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::errors::ApiError;
+use crate::schema::{Contact, DocumentType, Passenger, PassengerInput, PassengerType, TravelDocument};
+
+/// Most passengers accepted on a single booking
+pub const MAX_PASSENGERS: usize = 9;
+
+/// Row shape of the `passengers` table
+#[derive(sqlx::FromRow)]
+struct PassengerRow {
+    id: i64,
+    first_name: String,
+    last_name: String,
+    date_of_birth: Option<String>,
+    passenger_type: String,
+    email: Option<String>,
+    phone: Option<String>,
+    loyalty_number: Option<String>,
+    document_type: Option<String>,
+    document_number: Option<String>,
+    document_issuing_country: Option<String>,
+    document_expiry: Option<String>,
+}
+
+fn invalid(index: usize, field: &str, message: impl Into<String>) -> async_graphql::Error {
+    ApiError::InvalidPassenger { index, field: field.to_string(), message: message.into() }.extend()
+}
+
+fn parse_date(index: usize, field: &str, value: &str) -> async_graphql::Result<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .map_err(|_| invalid(index, field, "expected a YYYY-MM-DD date"))
+}
+
+/// Whole years between a birth date and a later date
+fn age_on(born: NaiveDate, on: NaiveDate) -> i32 {
+    let mut age = on.year() - born.year();
+    if (on.month(), on.day()) < (born.month(), born.day()) {
+        age -= 1;
+    }
+    age
+}
+
+/// Validate the passengers of a booking travelling from `departs` until `returns`
+pub fn validate(
+    passengers: &[PassengerInput],
+    departs: NaiveDate,
+    returns: NaiveDate,
+) -> async_graphql::Result<()> {
+    if passengers.is_empty() || passengers.len() > MAX_PASSENGERS {
+        return Err(async_graphql::Error::new(format!(
+            "A booking needs between 1 and {} passengers",
+            MAX_PASSENGERS
+        )));
+    }
+
+    for (index, p) in passengers.iter().enumerate() {
+        if p.first_name.trim().is_empty() {
+            return Err(invalid(index, "firstName", "is required"));
+        }
+        if p.last_name.trim().is_empty() {
+            return Err(invalid(index, "lastName", "is required"));
+        }
+
+        match &p.date_of_birth {
+            Some(dob) => {
+                let born = parse_date(index, "dateOfBirth", dob)?;
+                if born > departs {
+                    return Err(invalid(index, "dateOfBirth", "is after the departure date"));
+                }
+                let age = age_on(born, departs);
+                let expected = match age {
+                    0..=1 => PassengerType::Infant,
+                    2..=11 => PassengerType::Child,
+                    _ => PassengerType::Adult,
+                };
+                if expected != p.passenger_type {
+                    return Err(invalid(
+                        index,
+                        "passengerType",
+                        format!("age {} at departure requires {:?}", age, expected),
+                    ));
+                }
+            }
+            None if p.passenger_type != PassengerType::Adult => {
+                return Err(invalid(index, "dateOfBirth", "is required for children and infants"));
+            }
+            None => {}
+        }
+
+        if let Some(contact) = &p.contact {
+            if let Some(email) = &contact.email {
+                let valid = email
+                    .split_once('@')
+                    .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.'));
+                if !valid {
+                    return Err(invalid(index, "contact.email", "is not a valid email address"));
+                }
+            }
+            if let Some(phone) = &contact.phone {
+                let digits = phone.chars().filter(|c| c.is_ascii_digit()).count();
+                if !(7..=15).contains(&digits) {
+                    return Err(invalid(index, "contact.phone", "must contain 7 to 15 digits"));
+                }
+            }
+        }
+
+        if let Some(document) = &p.travel_document {
+            if document.number.trim().is_empty() {
+                return Err(invalid(index, "travelDocument.number", "is required"));
+            }
+            if document.issuing_country.trim().len() != 2 {
+                return Err(invalid(index, "travelDocument.issuingCountry", "must be an ISO 3166 alpha-2 code"));
+            }
+            let expiry = parse_date(index, "travelDocument.expiryDate", &document.expiry_date)?;
+            if expiry < returns {
+                return Err(invalid(index, "travelDocument.expiryDate", "expires before the end of the trip"));
+            }
+        }
+    }
+
+    let adults = passengers.iter().filter(|p| p.passenger_type == PassengerType::Adult).count();
+    let infants = passengers.iter().filter(|p| p.passenger_type == PassengerType::Infant).count();
+    if adults == 0 {
+        return Err(async_graphql::Error::new("At least one adult passenger is required"));
+    }
+    if infants > adults {
+        return Err(async_graphql::Error::new("Each infant must travel with an adult"));
+    }
+    Ok(())
+}
+
+/// Passengers who need a seat of their own (infants travel on a lap)
+pub fn seated_count(passengers: &[PassengerInput]) -> i64 {
+    passengers
+        .iter()
+        .filter(|p| p.passenger_type != PassengerType::Infant)
+        .count() as i64
+}
+
+/// Store the passengers of a booking inside the booking transaction
+pub async fn insert(
+    tx: &mut Transaction<'_, Sqlite>,
+    booking_id: i64,
+    passengers: &[PassengerInput],
+) -> async_graphql::Result<()> {
+    for (index, p) in passengers.iter().enumerate() {
+        let contact = p.contact.as_ref();
+        let document = p.travel_document.as_ref();
+        sqlx::query(
+            "INSERT INTO passengers (booking_id, passenger_index, first_name, last_name, date_of_birth, passenger_type, email, phone, loyalty_number, document_type, document_number, document_issuing_country, document_expiry) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(booking_id)
+        .bind(index as i64)
+        .bind(p.first_name.trim())
+        .bind(p.last_name.trim())
+        .bind(p.date_of_birth.as_deref())
+        .bind(p.passenger_type.as_str())
+        .bind(contact.and_then(|c| c.email.as_deref()))
+        .bind(contact.and_then(|c| c.phone.as_deref()))
+        .bind(p.loyalty_number.as_deref())
+        .bind(document.map(|d| d.document_type.as_str()))
+        .bind(document.map(|d| d.number.trim()))
+        .bind(document.map(|d| d.issuing_country.trim().to_ascii_uppercase()))
+        .bind(document.map(|d| d.expiry_date.trim()))
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Passengers of a booking in the order they were entered
+pub async fn for_booking(pool: &SqlitePool, booking_id: i64) -> async_graphql::Result<Vec<Passenger>> {
+    let rows = sqlx::query_as::<_, PassengerRow>(
+        "SELECT id, first_name, last_name, date_of_birth, passenger_type, email, phone, loyalty_number, document_type, document_number, document_issuing_country, document_expiry FROM passengers WHERE booking_id = ? ORDER BY passenger_index",
+    )
+    .bind(booking_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Passenger {
+            id: row.id,
+            first_name: row.first_name,
+            last_name: row.last_name,
+            date_of_birth: row.date_of_birth,
+            passenger_type: row.passenger_type.parse().unwrap_or(PassengerType::Adult),
+            contact: (row.email.is_some() || row.phone.is_some()).then_some(Contact {
+                email: row.email,
+                phone: row.phone,
+            }),
+            loyalty_number: row.loyalty_number,
+            travel_document: match (row.document_type, row.document_number) {
+                (Some(document_type), Some(number)) => Some(TravelDocument {
+                    document_type: document_type.parse().unwrap_or(DocumentType::Passport),
+                    number_last4: number.chars().skip(number.chars().count().saturating_sub(4)).collect(),
+                    issuing_country: row.document_issuing_country.unwrap_or_default(),
+                    expiry_date: row.document_expiry.unwrap_or_default(),
+                }),
+                _ => None,
+            },
+        })
+        .collect())
+}
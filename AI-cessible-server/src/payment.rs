@@ -0,0 +1,254 @@
+use std::sync::Arc;
+
+use async_graphql::ErrorExtensions;
+use async_trait::async_trait;
+use chrono::{Datelike, Utc};
+use sqlx::SqlitePool;
+
+use crate::errors::ApiError;
+use crate::schema::{CardInput, TokenizedCard};
+
+/// Payment processor shared through the GraphQL schema data
+pub type SharedPaymentProcessor = Arc<dyn PaymentProcessor>;
+
+/// Card details accepted for tokenization; never persisted
+#[derive(Clone, Debug)]
+pub struct CardDetails {
+    pub number: String,
+    pub expiry_month: u32,
+    pub expiry_year: i32,
+    pub cvc: Option<String>,
+}
+
+/// A stored payment method, referenced by token
+#[derive(Clone, Debug)]
+pub struct PaymentMethod {
+    pub token: String,
+    pub brand: String,
+    pub last4: String,
+}
+
+/// Result of a successful authorization
+#[derive(Clone, Debug)]
+pub struct Authorization {
+    pub id: String,
+    pub amount: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PaymentError {
+    InvalidCard(String),
+    UnknownToken,
+    Declined(String),
+    InvalidState(String),
+    /// The processor could not reach its storage
+    Unavailable(String),
+}
+
+impl std::fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaymentError::InvalidCard(reason) => write!(f, "Invalid card: {}", reason),
+            PaymentError::UnknownToken => write!(f, "Unknown payment token"),
+            PaymentError::Declined(reason) => write!(f, "Payment declined: {}", reason),
+            PaymentError::InvalidState(reason) => write!(f, "Payment operation not allowed: {}", reason),
+            PaymentError::Unavailable(reason) => write!(f, "Payment processor unavailable: {}", reason),
+        }
+    }
+}
+
+/// Pluggable payment backend. Implementations must never expose full card numbers.
+#[async_trait]
+pub trait PaymentProcessor: Send + Sync {
+    /// Exchange card details for a reusable token
+    async fn tokenize(&self, card: CardDetails) -> Result<PaymentMethod, PaymentError>;
+    /// Look up the payment method behind a token
+    async fn payment_method(&self, token: &str) -> Result<PaymentMethod, PaymentError>;
+    /// Reserve funds on a tokenized payment method
+    async fn authorize(&self, token: &str, amount: f64) -> Result<Authorization, PaymentError>;
+    /// Collect previously authorized funds
+    async fn capture(&self, authorization_id: &str) -> Result<(), PaymentError>;
+    /// Return captured funds or release an uncaptured authorization
+    async fn refund(&self, authorization_id: &str) -> Result<(), PaymentError>;
+}
+
+/// Card brand from the leading digits of the card number
+pub fn card_brand(number: &str) -> &'static str {
+    let prefix = |len: usize| number.get(..len).and_then(|p| p.parse::<u32>().ok()).unwrap_or(0);
+    if number.starts_with('4') {
+        "VISA"
+    } else if (51..=55).contains(&prefix(2)) || (2221..=2720).contains(&prefix(4)) {
+        "MASTERCARD"
+    } else if number.starts_with("34") || number.starts_with("37") {
+        "AMEX"
+    } else if number.starts_with("6011") || number.starts_with("65") {
+        "DISCOVER"
+    } else {
+        "UNKNOWN"
+    }
+}
+
+/// Luhn checksum used by all major card networks
+pub fn luhn_valid(number: &str) -> bool {
+    let mut sum = 0;
+    for (i, c) in number.chars().rev().enumerate() {
+        let Some(mut digit) = c.to_digit(10) else {
+            return false;
+        };
+        if i % 2 == 1 {
+            digit *= 2;
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+        sum += digit;
+    }
+    sum % 10 == 0
+}
+
+impl From<sqlx::Error> for PaymentError {
+    fn from(err: sqlx::Error) -> Self {
+        PaymentError::Unavailable(err.to_string())
+    }
+}
+
+/// Processor for local development and tests. Payment methods and
+/// authorizations are kept in the database, so the tokens stored on bookings
+/// can still be looked up and refunded after a restart.
+/// Card `4000000000000002` is always declined.
+pub struct MockPaymentProcessor {
+    pool: SqlitePool,
+}
+
+impl MockPaymentProcessor {
+    pub fn new(pool: SqlitePool) -> Self {
+        MockPaymentProcessor { pool }
+    }
+
+    /// Move an authorization from one of `from` to `to`, explaining why not otherwise
+    async fn transition(&self, authorization_id: &str, from: &[&str], to: &str) -> Result<(), PaymentError> {
+        let state: Option<(String,)> = sqlx::query_as("SELECT state FROM payment_authorizations WHERE id = ?")
+            .bind(authorization_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some((state,)) = state else {
+            return Err(PaymentError::InvalidState("unknown authorization".to_string()));
+        };
+        if !from.contains(&state.as_str()) {
+            return Err(PaymentError::InvalidState(format!("authorization is {}", state.to_lowercase())));
+        }
+        let result = sqlx::query("UPDATE payment_authorizations SET state = ? WHERE id = ? AND state = ?")
+            .bind(to)
+            .bind(authorization_id)
+            .bind(&state)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(PaymentError::InvalidState("authorization changed concurrently".to_string()));
+        }
+        Ok(())
+    }
+}
+
+const DECLINED_CARD_LAST4: &str = "0002";
+
+#[async_trait]
+impl PaymentProcessor for MockPaymentProcessor {
+    async fn tokenize(&self, card: CardDetails) -> Result<PaymentMethod, PaymentError> {
+        let number: String = card.number.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+        if !(12..=19).contains(&number.len()) || !luhn_valid(&number) {
+            return Err(PaymentError::InvalidCard("card number failed validation".to_string()));
+        }
+        if !(1..=12).contains(&card.expiry_month) {
+            return Err(PaymentError::InvalidCard("expiry month must be 1-12".to_string()));
+        }
+        let today = Utc::now().date_naive();
+        if (card.expiry_year, card.expiry_month) < (today.year(), today.month()) {
+            return Err(PaymentError::InvalidCard("card has expired".to_string()));
+        }
+        if let Some(cvc) = &card.cvc {
+            if !(3..=4).contains(&cvc.len()) || !cvc.chars().all(|c| c.is_ascii_digit()) {
+                return Err(PaymentError::InvalidCard("CVC must be 3 or 4 digits".to_string()));
+            }
+        }
+
+        let method = PaymentMethod {
+            token: format!("tok_{}", uuid::Uuid::new_v4().simple()),
+            brand: card_brand(&number).to_string(),
+            last4: number[number.len() - 4..].to_string(),
+        };
+        sqlx::query("INSERT INTO payment_methods (token, brand, last4, created_time) VALUES (?, ?, ?, datetime('now'))")
+            .bind(&method.token)
+            .bind(&method.brand)
+            .bind(&method.last4)
+            .execute(&self.pool)
+            .await?;
+        Ok(method)
+    }
+
+    async fn payment_method(&self, token: &str) -> Result<PaymentMethod, PaymentError> {
+        let row: Option<(String, String)> = sqlx::query_as("SELECT brand, last4 FROM payment_methods WHERE token = ?")
+            .bind(token)
+            .fetch_optional(&self.pool)
+            .await?;
+        let (brand, last4) = row.ok_or(PaymentError::UnknownToken)?;
+        Ok(PaymentMethod { token: token.to_string(), brand, last4 })
+    }
+
+    async fn authorize(&self, token: &str, amount: f64) -> Result<Authorization, PaymentError> {
+        let method = self.payment_method(token).await?;
+        if amount <= 0.0 {
+            return Err(PaymentError::InvalidState("amount must be positive".to_string()));
+        }
+        if method.last4 == DECLINED_CARD_LAST4 {
+            return Err(PaymentError::Declined("insufficient funds".to_string()));
+        }
+        let id = format!("auth_{}", uuid::Uuid::new_v4().simple());
+        sqlx::query(
+            "INSERT INTO payment_authorizations (id, token, amount, state, created_time) VALUES (?, ?, ?, 'AUTHORIZED', datetime('now'))",
+        )
+        .bind(&id)
+        .bind(token)
+        .bind(amount)
+        .execute(&self.pool)
+        .await?;
+        Ok(Authorization { id, amount })
+    }
+
+    async fn capture(&self, authorization_id: &str) -> Result<(), PaymentError> {
+        self.transition(authorization_id, &["AUTHORIZED"], "CAPTURED").await
+    }
+
+    async fn refund(&self, authorization_id: &str) -> Result<(), PaymentError> {
+        self.transition(authorization_id, &["AUTHORIZED", "CAPTURED"], "REFUNDED").await
+    }
+}
+
+/// Resolve the `payment` argument of bookFlight, a token issued by
+/// tokenizePayment. Raw card numbers are rejected: they carry no expiry date.
+pub async fn resolve_payment_method(
+    processor: &dyn PaymentProcessor,
+    payment: &str,
+) -> Result<PaymentMethod, PaymentError> {
+    let payment = payment.trim();
+    if !payment.starts_with("tok_") {
+        return Err(PaymentError::InvalidCard(
+            "payment must be a token from tokenizePayment, not card details".to_string(),
+        ));
+    }
+    processor.payment_method(payment).await
+}
+
+/// Tokenize card details submitted through GraphQL
+pub async fn tokenize(processor: &dyn PaymentProcessor, card: CardInput) -> async_graphql::Result<TokenizedCard> {
+    let method = processor
+        .tokenize(CardDetails {
+            number: card.number,
+            expiry_month: card.expiry_month,
+            expiry_year: card.expiry_year,
+            cvc: card.cvc,
+        })
+        .await
+        .map_err(|err| ApiError::Payment(err).extend())?;
+    Ok(TokenizedCard { token: method.token, brand: method.brand, last4: method.last4 })
+}
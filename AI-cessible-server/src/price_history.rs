@@ -0,0 +1,144 @@
+use sqlx::SqlitePool;
+use tracing::warn;
+
+use crate::addons::round_cents;
+use crate::bot_schema::{AlternativeFlight, HistoricalPrice, PriceComparison, PriceTrend};
+use crate::itinerary;
+use crate::schema::FlightOffer;
+
+/// A search records a flight's price at most once in this many minutes
+const SNAPSHOT_MINUTES: i64 = 60;
+/// Flights departing this many days either side are compared and offered as alternatives
+pub const WINDOW_DAYS: i64 = 3;
+const MAX_ALTERNATIVES: i64 = 5;
+/// Price moves smaller than this, in percent, count as stable
+const TREND_THRESHOLD_PERCENT: f64 = 2.0;
+
+/// Record the prices of searched flights in the background, so searches
+/// neither wait for nor fail on the write. Listings and price changes are
+/// recorded by triggers on `flights`.
+pub fn spawn_search_snapshot(pool: &SqlitePool, flights: &[FlightOffer]) {
+    if flights.is_empty() {
+        return;
+    }
+    let pool = pool.clone();
+    let flight_ids: Vec<i64> = flights.iter().map(|f| f.id).collect();
+    tokio::spawn(async move {
+        if let Err(err) = record_search_snapshot(&pool, &flight_ids).await {
+            warn!("Failed to record search prices of {} flights: {}", flight_ids.len(), err);
+        }
+    });
+}
+
+/// Record the current price of each flight in one statement, skipping flights
+/// whose price was recorded in the last [`SNAPSHOT_MINUTES`]
+pub async fn record_search_snapshot(pool: &SqlitePool, flight_ids: &[i64]) -> Result<u64, sqlx::Error> {
+    let placeholders = vec!["?"; flight_ids.len()].join(", ");
+    let sql = format!(
+        "INSERT INTO price_history (flight_id, price, source, recorded_time) \
+         SELECT f.id, f.price, 'search', datetime('now') FROM flights f \
+         WHERE f.id IN ({}) AND NOT EXISTS ( \
+             SELECT 1 FROM price_history h WHERE h.flight_id = f.id AND h.recorded_time > datetime('now', ?))",
+        placeholders
+    );
+    let mut query = sqlx::query(&sql);
+    for flight_id in flight_ids {
+        query = query.bind(flight_id);
+    }
+    let result = query.bind(format!("-{} minutes", SNAPSHOT_MINUTES)).execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+/// Compare a fare with the prices recorded on its route for flights departing
+/// within the window, and follow its own recorded prices for the trend. Each
+/// flight counts with its last price of every day it was recorded, so flights
+/// searched more often do not weigh more.
+pub async fn compare(pool: &SqlitePool, flight: &FlightOffer) -> async_graphql::Result<PriceComparison> {
+    let route_prices: Vec<(f64,)> = sqlx::query_as(
+        "SELECT h.price FROM price_history h JOIN flights f ON f.id = h.flight_id \
+         WHERE f.origin = ?1 AND f.destination = ?2 \
+         AND substr(f.departure_time, 1, 10) BETWEEN date(substr(?3, 1, 10), ?4) AND date(substr(?3, 1, 10), ?5) \
+         AND h.id = (SELECT MAX(d.id) FROM price_history d \
+                     WHERE d.flight_id = h.flight_id AND date(d.recorded_time) = date(h.recorded_time))",
+    )
+    .bind(&flight.origin)
+    .bind(&flight.destination)
+    .bind(&flight.departure_time)
+    .bind(format!("-{} days", WINDOW_DAYS))
+    .bind(format!("+{} days", WINDOW_DAYS))
+    .fetch_all(pool)
+    .await?;
+    let price_history: Vec<HistoricalPrice> = sqlx::query_as::<_, (String, f64, String)>(
+        "SELECT recorded_time, price, source FROM price_history WHERE flight_id = ? ORDER BY recorded_time, id",
+    )
+    .bind(flight.id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(date, price, source)| HistoricalPrice { date, price, source })
+    .collect();
+
+    let sample_size = route_prices.len() as i64;
+    let (average_price, percentile) = if route_prices.is_empty() {
+        (flight.price, 50.0)
+    } else {
+        let count = route_prices.len() as f64;
+        let average = route_prices.iter().map(|(p,)| p).sum::<f64>() / count;
+        // Mid-rank: equal prices count half, so a fare alone on its route sits at 50
+        let below = route_prices.iter().filter(|(p,)| *p < flight.price).count() as f64;
+        let equal = route_prices.iter().filter(|(p,)| *p == flight.price).count() as f64;
+        (round_cents(average), ((below + equal / 2.0) / count * 100.0).round())
+    };
+
+    let first = price_history.first().map_or(flight.price, |h| h.price);
+    let trend_percent = if first > 0.0 { ((flight.price - first) / first * 1000.0).round() / 10.0 } else { 0.0 };
+    let trend = if trend_percent >= TREND_THRESHOLD_PERCENT {
+        PriceTrend::Rising
+    } else if trend_percent <= -TREND_THRESHOLD_PERCENT {
+        PriceTrend::Falling
+    } else {
+        PriceTrend::Stable
+    };
+
+    Ok(PriceComparison {
+        average_price,
+        percentile: percentile as f32,
+        trend,
+        trend_percent,
+        sample_size,
+        price_history,
+    })
+}
+
+/// Other flights on the same route departing within the window, closest departure first
+pub async fn alternatives(pool: &SqlitePool, flight: &FlightOffer) -> async_graphql::Result<Vec<AlternativeFlight>> {
+    let departs = itinerary::parse_time(&flight.departure_time)?;
+    let others = sqlx::query_as::<_, FlightOffer>(
+        "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights \
+         WHERE origin = ?1 AND destination = ?2 AND id <> ?3 \
+         AND substr(departure_time, 1, 10) BETWEEN date(substr(?4, 1, 10), ?5) AND date(substr(?4, 1, 10), ?6) \
+         ORDER BY ABS(julianday(departure_time) - julianday(?4)), price LIMIT ?7",
+    )
+    .bind(&flight.origin)
+    .bind(&flight.destination)
+    .bind(flight.id)
+    .bind(&flight.departure_time)
+    .bind(format!("-{} days", WINDOW_DAYS))
+    .bind(format!("+{} days", WINDOW_DAYS))
+    .bind(MAX_ALTERNATIVES)
+    .fetch_all(pool)
+    .await?;
+
+    let mut alternatives = Vec::new();
+    for other in others {
+        let time_difference = itinerary::parse_time(&other.departure_time)? - departs;
+        alternatives.push(AlternativeFlight {
+            flight_id: other.id,
+            departure_time: other.departure_time,
+            price: other.price,
+            price_difference: round_cents(other.price - flight.price),
+            time_difference_minutes: time_difference.num_minutes(),
+        });
+    }
+    Ok(alternatives)
+}
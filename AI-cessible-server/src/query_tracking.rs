@@ -0,0 +1,219 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextValidation,
+};
+use async_graphql::parser::types::{ExecutableDocument, Selection, SelectionSet};
+use async_graphql::{ServerError, ServerResult, ValidationResult, Variables};
+use tracing::debug;
+
+use crate::bot_detection::BotInfo;
+use crate::sessions::SessionStore;
+
+/// Identical queries (same text and variables) among the recent ones that look scripted
+const REPEAT_THRESHOLD: usize = 3;
+
+/// Depth of a query that, after introspection, suggests a generated client
+const DEEP_QUERY_DEPTH: usize = 4;
+
+/// Median gap between GraphQL requests below which the cadence is machine-fast
+const FAST_CADENCE: Duration = Duration::from_millis(300);
+
+/// Gaps needed before cadence is judged
+const CADENCE_SAMPLES: usize = 3;
+
+/// Nesting followed when estimating the shape of a query that may be rejected,
+/// deep enough to exceed any sensible depth limit
+const MAX_ESTIMATED_DEPTH: usize = 64;
+
+/// Shape of one GraphQL request as recorded in its session
+#[derive(Clone, Debug)]
+pub struct QueryRecord {
+    /// Hash of the whitespace-normalised query text and its variables
+    pub hash: u64,
+    /// Depth and complexity from validation, or estimated from the document
+    /// when validation rejected the query
+    pub depth: usize,
+    pub complexity: usize,
+    /// Distinct field names selected anywhere in the document
+    pub fields: Vec<String>,
+    pub introspection: bool,
+    /// Why validation rejected the query, e.g. the depth or complexity limit
+    pub rejected: Option<String>,
+    pub at: Instant,
+}
+
+/// Scripted pattern found in a session's queries, scored 0 (human-like) to 1 (bot-like)
+#[derive(Clone, Debug)]
+pub struct QueryPattern {
+    pub score: f32,
+    pub detail: String,
+}
+
+/// Look for scripted patterns in a session's recent queries, oldest first
+pub fn query_pattern(queries: &[QueryRecord]) -> Option<QueryPattern> {
+    let last = queries.last()?;
+    let mut patterns: Vec<(f32, String)> = Vec::new();
+
+    let repeats = queries.iter().filter(|q| q.hash == last.hash).count();
+    if repeats >= REPEAT_THRESHOLD {
+        patterns.push((0.9, format!("identical query sent {} times", repeats)));
+    }
+
+    let introspected = queries.iter().position(|q| q.introspection);
+    if let Some(first) = introspected {
+        if queries[first + 1..].iter().any(|q| !q.introspection && q.depth >= DEEP_QUERY_DEPTH) {
+            patterns.push((0.8, "introspection followed by deep queries".to_string()));
+        }
+    }
+
+    let mut gaps: Vec<Duration> = queries.windows(2).map(|w| w[1].at.duration_since(w[0].at)).collect();
+    if gaps.len() >= CADENCE_SAMPLES {
+        gaps.sort();
+        let median = gaps[gaps.len() / 2];
+        if median < FAST_CADENCE {
+            patterns.push((0.8, format!("median {}ms between queries", median.as_millis())));
+        }
+    }
+
+    let pattern = patterns
+        .into_iter()
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(score, detail)| QueryPattern { score, detail })
+        .unwrap_or(QueryPattern { score: 0.0, detail: format!("{} queries, no scripted pattern", queries.len()) });
+    Some(pattern)
+}
+
+/// Shape of a parsed query: its hash, fields, and depth and complexity
+/// counted the way async-graphql does by default (one per field)
+struct DocumentShape {
+    hash: u64,
+    fields: Vec<String>,
+    depth: usize,
+    complexity: usize,
+}
+
+/// Walk a selection set, expanding fragment spreads, and return its depth
+fn walk(
+    document: &ExecutableDocument,
+    selection_set: &SelectionSet,
+    level: usize,
+    fields: &mut BTreeSet<String>,
+    complexity: &mut usize,
+) -> usize {
+    if level >= MAX_ESTIMATED_DEPTH {
+        return level;
+    }
+    let mut depth = level;
+    for selection in &selection_set.items {
+        let nested = match &selection.node {
+            Selection::Field(field) => {
+                fields.insert(field.node.name.node.to_string());
+                *complexity += 1;
+                walk(document, &field.node.selection_set.node, level + 1, fields, complexity)
+            }
+            Selection::InlineFragment(fragment) => {
+                walk(document, &fragment.node.selection_set.node, level, fields, complexity)
+            }
+            Selection::FragmentSpread(spread) => match document.fragments.get(&spread.node.fragment_name.node) {
+                Some(fragment) => walk(document, &fragment.node.selection_set.node, level, fields, complexity),
+                None => level,
+            },
+        };
+        depth = depth.max(nested);
+    }
+    depth
+}
+
+/// Hash, fields, depth and complexity of a parsed query
+fn document_shape(document: &ExecutableDocument, query: &str, variables: &Variables) -> DocumentShape {
+    let mut fields = BTreeSet::new();
+    let (mut depth, mut complexity) = (0, 0);
+    for (_, operation) in document.operations.iter() {
+        depth = depth.max(walk(document, &operation.node.selection_set.node, 0, &mut fields, &mut complexity));
+    }
+
+    let mut hasher = DefaultHasher::new();
+    query.split_whitespace().for_each(|token| token.hash(&mut hasher));
+    serde_json::to_string(variables).unwrap_or_default().hash(&mut hasher);
+    DocumentShape { hash: hasher.finish(), fields: fields.into_iter().collect(), depth, complexity }
+}
+
+/// Schema extension recording the shape, depth and complexity of every query
+/// in the caller's session. Depth and complexity limits are set on the schema
+/// builder; queries they reject are recorded too, so probing the limits shows
+/// up in the session.
+pub struct QueryTracking {
+    sessions: SessionStore,
+}
+
+impl QueryTracking {
+    pub fn new(sessions: SessionStore) -> Self {
+        QueryTracking { sessions }
+    }
+}
+
+impl ExtensionFactory for QueryTracking {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryTrackingExtension { sessions: self.sessions.clone(), shape: Mutex::new(None) })
+    }
+}
+
+struct QueryTrackingExtension {
+    sessions: SessionStore,
+    /// Shape from parsing, completed by validation
+    shape: Mutex<Option<DocumentShape>>,
+}
+
+#[async_trait::async_trait]
+impl Extension for QueryTrackingExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        *self.shape.lock().unwrap() = Some(document_shape(&document, query, variables));
+        Ok(document)
+    }
+
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await;
+        let session_id = ctx.data_opt::<BotInfo>().map(|info| info.session_id.as_str());
+        let shape = self.shape.lock().unwrap().take();
+        if let Err(errors) = &result {
+            debug!("GraphQL query rejected: {:?}", errors);
+        }
+        if let (Some(session_id), Some(shape)) = (session_id, shape) {
+            let introspection = shape.fields.iter().any(|f| f == "__schema" || f == "__type");
+            let (depth, complexity, rejected) = match &result {
+                Ok(validation) => (validation.depth, validation.complexity, None),
+                Err(errors) => {
+                    let reason = errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; ");
+                    (shape.depth, shape.complexity, Some(reason))
+                }
+            };
+            let record = QueryRecord {
+                hash: shape.hash,
+                depth,
+                complexity,
+                fields: shape.fields,
+                introspection,
+                rejected,
+                at: Instant::now(),
+            };
+            self.sessions.record_query(session_id, record);
+        }
+        result
+    }
+}
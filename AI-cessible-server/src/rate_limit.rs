@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_graphql::{ErrorExtensions, Pos};
+use axum::{
+    extract::{Request, State},
+    http::{header::RETRY_AFTER, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use tracing::debug;
+
+use crate::bot_detection::BotInfo;
+use crate::config::{RateLimitConfig, RatePolicy};
+use crate::errors::ApiError;
+use crate::sessions::SessionStore;
+
+/// Buckets kept in memory before full ones are dropped
+const MAX_BUCKETS: usize = 50_000;
+
+/// Kind of traffic, which picks the rate limit policy
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrafficClass {
+    Human,
+    Bot,
+    Agent,
+}
+
+impl TrafficClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrafficClass::Human => "human",
+            TrafficClass::Bot => "bot",
+            TrafficClass::Agent => "agent",
+        }
+    }
+
+    /// Identified agents first, then whatever detection decided
+    pub fn of(bot_info: &BotInfo) -> TrafficClass {
+        if bot_info.agent_id.is_some() {
+            TrafficClass::Agent
+        } else if bot_info.is_likely_bot() {
+            TrafficClass::Bot
+        } else {
+            TrafficClass::Human
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Policy the bucket last refilled under, which its traffic class picked
+    policy: RatePolicy,
+}
+
+impl Bucket {
+    /// Tokens at `now`, refilled at the bucket's own rate up to its own burst
+    fn level(&self, now: Instant) -> f64 {
+        let per_sec = f64::from(self.policy.per_minute) / 60.0;
+        (self.tokens + now.duration_since(self.updated).as_secs_f64() * per_sec).min(f64::from(self.policy.burst))
+    }
+}
+
+/// Outcome of drawing a token, with the state of the emptiest bucket involved
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateDecision {
+    pub allowed: bool,
+    pub class: TrafficClass,
+    pub policy: RatePolicy,
+    pub remaining: u32,
+    /// Seconds until the emptiest bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until a request would be allowed, for denied requests
+    pub retry_after_secs: Option<u64>,
+}
+
+impl RateDecision {
+    /// `RateLimit-*` headers, plus `Retry-After` when denied
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let policy = format!(
+            "{};w=60;burst={};policy=\"{}\"",
+            self.policy.per_minute,
+            self.policy.burst,
+            self.class.as_str()
+        );
+        let values = [
+            ("ratelimit-limit", self.policy.burst.to_string()),
+            ("ratelimit-remaining", self.remaining.to_string()),
+            ("ratelimit-reset", self.reset_secs.to_string()),
+            ("ratelimit-policy", policy),
+        ];
+        for (name, value) in values {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(name, value);
+            }
+        }
+        if let Some(retry_after) = self.retry_after_secs {
+            headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        headers
+    }
+}
+
+/// Token buckets shared by every request
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+    sessions: SessionStore,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, sessions: SessionStore) -> Self {
+        RateLimiter { config, buckets: Arc::new(Mutex::new(HashMap::new())), sessions }
+    }
+
+    fn policy(&self, class: TrafficClass) -> RatePolicy {
+        match class {
+            TrafficClass::Human => self.config.human,
+            TrafficClass::Bot => self.config.bot,
+            TrafficClass::Agent => self.config.agent,
+        }
+    }
+
+    /// Draw one token from each of `keys`' buckets, or from none if any is empty.
+    /// Buckets refill under the policy they were last used with and then
+    /// switch to the policy of `class`, keeping their tokens up to its burst.
+    pub fn check(&self, class: TrafficClass, keys: &[String], now: Instant) -> RateDecision {
+        let policy = self.policy(class);
+        let capacity = f64::from(policy.burst);
+        let per_sec = f64::from(policy.per_minute) / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            // Full buckets hold nothing a new one would not
+            buckets.retain(|_, b| b.level(now) < f64::from(b.policy.burst));
+        }
+        let mut lowest = capacity;
+        for key in keys {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket { tokens: capacity, updated: now, policy });
+            bucket.tokens = bucket.level(now).min(capacity);
+            bucket.updated = now;
+            bucket.policy = policy;
+            lowest = lowest.min(bucket.tokens);
+        }
+        let allowed = lowest >= 1.0;
+        if allowed {
+            for key in keys {
+                if let Some(bucket) = buckets.get_mut(key) {
+                    bucket.tokens -= 1.0;
+                }
+            }
+            lowest -= 1.0;
+        }
+
+        RateDecision {
+            allowed,
+            class,
+            policy,
+            remaining: lowest.max(0.0).floor() as u32,
+            reset_secs: ((capacity - lowest) / per_sec).ceil() as u64,
+            retry_after_secs: (!allowed).then(|| ((1.0 - lowest) / per_sec).ceil().max(1.0) as u64),
+        }
+    }
+}
+
+/// Only the APIs are limited; the app and its assets are not
+fn limited_path(path: &str) -> bool {
+    path == "/graphql" || path.starts_with("/bot/")
+}
+
+/// Buckets a request draws from: its client IP, its session if this server
+/// issued it and, for identified agents, the agent. Every bucket must have a
+/// token, so a new session only adds a bucket and never escapes the IP one.
+pub fn bucket_keys(bot_info: &BotInfo, sessions: &SessionStore) -> Vec<String> {
+    let mut keys = vec![format!("ip:{}", bot_info.client_ip)];
+    if sessions.issued_here(&bot_info.session_id) {
+        keys.push(format!("session:{}", bot_info.session_id));
+    }
+    if let Some(agent_id) = &bot_info.agent_id {
+        keys.push(format!("agent:{}", agent_id));
+    }
+    keys
+}
+
+/// Rate limiting middleware; runs after bot detection, whose result picks the policy
+pub async fn rate_limit_middleware(State(limiter): State<RateLimiter>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let bot_info = request.extensions().get::<BotInfo>();
+    let (Some(bot_info), true) = (bot_info, limiter.config.enabled && limited_path(&path)) else {
+        return next.run(request).await;
+    };
+
+    let keys = bucket_keys(bot_info, &limiter.sessions);
+    let decision = limiter.check(TrafficClass::of(bot_info), &keys, Instant::now());
+
+    if !decision.allowed {
+        debug!("Rate limited {} on {} ({} policy)", keys.join(", "), path, decision.class.as_str());
+        let err = ApiError::RateLimited {
+            policy: decision.class.as_str(),
+            retry_after_secs: decision.retry_after_secs.unwrap_or(1),
+        };
+        // GraphQL clients get a GraphQL error; REST clients the usual error body
+        let body = if path.ends_with("/graphql") {
+            let mut error = err.extend().into_server_error(Pos::default());
+            error.locations.clear();
+            serde_json::to_value(async_graphql::Response::from_errors(vec![error])).unwrap_or_default()
+        } else {
+            serde_json::json!({ "error": err.to_string(), "code": err.code() })
+        };
+        return (StatusCode::TOO_MANY_REQUESTS, decision.headers(), Json(body)).into_response();
+    }
+
+    let mut response = next.run(request).await;
+    response.headers_mut().extend(decision.headers());
+    response
+}
@@ -1,30 +1,515 @@
-use async_graphql::{Context, EmptySubscription, Object, SimpleObject};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, Enum, InputObject, Object, SimpleObject};
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 
+use crate::airports::AirportLoader;
+use crate::api_keys::{ApiScope, ScopeGuard};
+use crate::booking::Owner;
+use crate::bot_detection::BotInfo;
+use crate::inventory::AvailabilityLoader;
+use crate::payment::SharedPaymentProcessor;
+use crate::{addons, airports, booking, itinerary, offers, passengers, payment, search, seatmap, trips};
+
 /// Flight offer returned by the searchFlights query
 #[derive(sqlx::FromRow, SimpleObject, Clone)]
+#[graphql(complex)]
 pub struct FlightOffer {
     pub id: i64,
     pub origin: String,
     pub destination: String,
+    /// Local time at the origin with its UTC offset, e.g. `2025-06-01T08:00:00-04:00`
     pub departure_time: String,
+    /// Local time at the destination with its UTC offset
     pub arrival_time: String,
     pub price: f64,
 }
 
+/// Airport from the reference table
+#[derive(sqlx::FromRow, SimpleObject, Clone)]
+pub struct Airport {
+    /// IATA code
+    pub code: String,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// IANA time zone, e.g. `America/New_York`
+    pub time_zone: String,
+}
+
+#[ComplexObject]
+impl FlightOffer {
+    /// Seats left to sell, in one cabin or across all cabins
+    async fn seats_remaining(&self, ctx: &Context<'_>, cabin: Option<Cabin>) -> async_graphql::Result<i64> {
+        let cabins = self.availability(ctx).await?;
+        Ok(cabins
+            .iter()
+            .filter(|c| cabin.is_none_or(|cabin| cabin == c.cabin))
+            .map(|c| c.seats_remaining)
+            .sum())
+    }
+
+    /// Per-cabin seat availability
+    async fn availability(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<CabinAvailability>> {
+        let loader = ctx.data::<DataLoader<AvailabilityLoader>>()?;
+        Ok(loader.load_one(self.id).await?.unwrap_or_default())
+    }
+
+    /// Block time from departure to arrival, across time zones
+    async fn duration_minutes(&self) -> async_graphql::Result<i64> {
+        airports::block_minutes(self)
+    }
+
+    /// Great-circle distance between the airports, in statute miles; null
+    /// when either airport is missing from the reference table
+    async fn distance_miles(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<i64>> {
+        let loader = ctx.data::<DataLoader<AirportLoader>>()?;
+        match airports::for_flight(loader, self).await? {
+            (Some(origin), Some(destination)) => Ok(Some(airports::distance_miles(&origin, &destination))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Null when the airport is missing from the reference table
+    async fn origin_airport(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Airport>> {
+        let loader = ctx.data::<DataLoader<AirportLoader>>()?;
+        Ok(loader.load_one(self.origin.clone()).await?)
+    }
+
+    /// Null when the airport is missing from the reference table
+    async fn destination_airport(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Airport>> {
+        let loader = ctx.data::<DataLoader<AirportLoader>>()?;
+        Ok(loader.load_one(self.destination.clone()).await?)
+    }
+}
+
+/// Cabin classes sold on every flight
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Cabin {
+    #[default]
+    Economy,
+    PremiumEconomy,
+    Business,
+}
+
+impl Cabin {
+    /// Value stored in the database, matching the GraphQL enum name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Cabin::Economy => "ECONOMY",
+            Cabin::PremiumEconomy => "PREMIUM_ECONOMY",
+            Cabin::Business => "BUSINESS",
+        }
+    }
+}
+
+impl std::str::FromStr for Cabin {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ECONOMY" => Ok(Cabin::Economy),
+            "PREMIUM_ECONOMY" => Ok(Cabin::PremiumEconomy),
+            "BUSINESS" => Ok(Cabin::Business),
+            other => Err(format!("Unknown cabin '{}'", other)),
+        }
+    }
+}
+
+/// Seat inventory for one cabin of a flight
+#[derive(SimpleObject, Clone)]
+pub struct CabinAvailability {
+    pub cabin: Cabin,
+    pub capacity: i64,
+    pub seats_remaining: i64,
+}
+
+/// Flights departing on a single requested day
+#[derive(SimpleObject, Clone)]
+pub struct DateFlights {
+    pub date: String,
+    pub flights: Vec<FlightOffer>,
+    pub lowest_price: Option<f64>,
+}
+
+/// Time on the ground between two segments of an itinerary
+#[derive(SimpleObject, Clone)]
+pub struct Layover {
+    pub airport: String,
+    pub duration_minutes: i64,
+}
+
+/// A direct or connecting journey made of one or more flight segments
+#[derive(SimpleObject, Clone)]
+pub struct Itinerary {
+    pub segments: Vec<FlightOffer>,
+    pub layovers: Vec<Layover>,
+    pub stops: i32,
+    pub departure_time: String,
+    pub arrival_time: String,
+    pub total_duration_minutes: i64,
+    pub total_price: f64,
+}
+
+/// Fare category of a passenger, checked against their age at departure
+#[derive(Enum, Serialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PassengerType {
+    #[default]
+    Adult,
+    Child,
+    Infant,
+}
+
+impl PassengerType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PassengerType::Adult => "ADULT",
+            PassengerType::Child => "CHILD",
+            PassengerType::Infant => "INFANT",
+        }
+    }
+}
+
+impl std::str::FromStr for PassengerType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ADULT" => Ok(PassengerType::Adult),
+            "CHILD" => Ok(PassengerType::Child),
+            "INFANT" => Ok(PassengerType::Infant),
+            other => Err(format!("Unknown passenger type '{}'", other)),
+        }
+    }
+}
+
+/// Kind of travel document
+#[derive(Enum, Serialize, Copy, Clone, Eq, PartialEq, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DocumentType {
+    Passport,
+    NationalId,
+}
+
+impl DocumentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentType::Passport => "PASSPORT",
+            DocumentType::NationalId => "NATIONAL_ID",
+        }
+    }
+}
+
+impl std::str::FromStr for DocumentType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "PASSPORT" => Ok(DocumentType::Passport),
+            "NATIONAL_ID" => Ok(DocumentType::NationalId),
+            other => Err(format!("Unknown document type '{}'", other)),
+        }
+    }
+}
+
+/// Contact details for a passenger
+#[derive(InputObject, Clone)]
+pub struct ContactInput {
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+/// Travel document presented at check-in
+#[derive(InputObject, Clone)]
+pub struct TravelDocumentInput {
+    pub document_type: DocumentType,
+    pub number: String,
+    /// ISO 3166 alpha-2 country code
+    pub issuing_country: String,
+    /// YYYY-MM-DD
+    pub expiry_date: String,
+}
+
+/// A passenger travelling on a booking
+#[derive(InputObject, Clone)]
+pub struct PassengerInput {
+    pub first_name: String,
+    pub last_name: String,
+    /// YYYY-MM-DD, required for children and infants
+    pub date_of_birth: Option<String>,
+    #[graphql(default)]
+    pub passenger_type: PassengerType,
+    pub contact: Option<ContactInput>,
+    pub loyalty_number: Option<String>,
+    pub travel_document: Option<TravelDocumentInput>,
+}
+
+/// Stored contact details
+#[derive(SimpleObject, Serialize, Clone)]
+pub struct Contact {
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+/// Stored travel document, with the number masked
+#[derive(SimpleObject, Serialize, Clone)]
+pub struct TravelDocument {
+    pub document_type: DocumentType,
+    pub number_last4: String,
+    pub issuing_country: String,
+    pub expiry_date: String,
+}
+
+/// A passenger stored on a booking
+#[derive(SimpleObject, Serialize, Clone)]
+pub struct Passenger {
+    pub id: i64,
+    pub first_name: String,
+    pub last_name: String,
+    pub date_of_birth: Option<String>,
+    pub passenger_type: PassengerType,
+    pub contact: Option<Contact>,
+    pub loyalty_number: Option<String>,
+    pub travel_document: Option<TravelDocument>,
+}
+
+/// Occupancy of a single seat
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SeatStatus {
+    Free,
+    Held,
+    Assigned,
+}
+
+/// A seat on the seat map
+#[derive(SimpleObject, Clone)]
+pub struct Seat {
+    /// Row number and letter, e.g. `12C`
+    pub seat: String,
+    pub letter: String,
+    pub cabin: Cabin,
+    pub status: SeatStatus,
+    pub exit_row: bool,
+    pub has_power: bool,
+}
+
+/// One row of seats
+#[derive(SimpleObject, Clone)]
+pub struct SeatRow {
+    pub row: i32,
+    pub cabin: Cabin,
+    pub exit_row: bool,
+    pub seats: Vec<Seat>,
+}
+
+/// Seat map of a flight, derived from its aircraft configuration
+#[derive(SimpleObject, Clone)]
+pub struct SeatMap {
+    pub flight_id: i64,
+    pub aircraft_code: String,
+    pub aircraft: String,
+    pub has_wifi: bool,
+    pub rows: Vec<SeatRow>,
+}
+
+/// A temporary hold on a seat, redeemable in bookFlight with its token
+#[derive(SimpleObject, Clone)]
+pub struct SeatHold {
+    pub flight_id: i64,
+    pub seat: String,
+    pub hold_token: String,
+    pub expires_at: String,
+}
+
+/// Seat chosen for one flight of a booking
+#[derive(InputObject, Clone)]
+pub struct SeatSelectionInput {
+    pub flight_id: i64,
+    pub seat: String,
+    /// Token from holdSeat, required if the seat is currently held
+    pub hold_token: Option<String>,
+}
+
+/// Seat assigned to a booking on one flight
+#[derive(SimpleObject, Clone)]
+pub struct AssignedSeat {
+    pub flight_id: i64,
+    pub seat: String,
+}
+
+/// One origin/destination/date leg of a searchTrips request
+#[derive(InputObject, Clone)]
+pub struct TripSliceInput {
+    pub origin: String,
+    pub destination: String,
+    /// Exact day, range or flexible window, as accepted by searchFlights
+    pub date: String,
+}
+
+/// Shape of a trip derived from its slices
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TripType {
+    OneWay,
+    RoundTrip,
+    OpenJaw,
+    MultiCity,
+}
+
+/// A priced combination of flights, one per requested slice
+#[derive(SimpleObject, Clone)]
+pub struct TripOption {
+    /// Offer id covering every flight, accepted by buildOffer and bookFlight
+    pub trip_id: String,
+    pub trip_type: TripType,
+    pub flights: Vec<FlightOffer>,
+    pub total_price: f64,
+}
+
+/// Whether an add-on is sold per flight or once per booking
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AddonUnit {
+    Segment,
+    Booking,
+}
+
+/// Add-on from the catalog
+#[derive(SimpleObject, Clone)]
+pub struct Addon {
+    pub code: String,
+    pub name: String,
+    pub description: String,
+    pub unit: AddonUnit,
+    /// Most that can be bought per passenger (and per flight for segment add-ons)
+    pub max_per_passenger: i64,
+    /// Cabins the add-on is sold in
+    pub cabins: Vec<Cabin>,
+    /// Only sold on aircraft with Wi-Fi
+    pub requires_wifi: bool,
+}
+
+/// Add-on requested with buildOffer
+#[derive(InputObject, Clone)]
+pub struct AddonSelection {
+    pub code: String,
+    #[graphql(default = 1)]
+    pub quantity: i64,
+    /// Flight the add-on is for; every flight of the offer if omitted. Not allowed for booking add-ons.
+    pub flight_id: Option<i64>,
+}
+
+/// What a line item of an offer charges for
+#[derive(Enum, Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LineItemKind {
+    Fare,
+    Addon,
+}
+
+/// One priced line of an offer
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
+pub struct OfferLineItem {
+    pub kind: LineItemKind,
+    /// Add-on code, for add-on lines
+    pub code: Option<String>,
+    pub description: String,
+    /// Flight the line applies to; absent for booking add-ons
+    pub flight_id: Option<i64>,
+    pub quantity: i64,
+    pub unit_price: f64,
+    pub amount: f64,
+}
+
 /// Summary of a flight offer, including selected add-ons
 #[derive(SimpleObject)]
 pub struct OfferSummary {
+    /// Id accepted by bookFlight to book exactly this quote
+    pub offer_id: String,
+    /// When the offer stops being bookable at this price
+    pub expires_at: String,
+    /// First flight of the offer
     pub flight: FlightOffer,
+    pub segments: Vec<FlightOffer>,
+    pub cabin: Cabin,
+    /// Passengers needing a seat; fares and add-on limits are per seated passenger
+    pub passengers: i64,
+    /// Codes of the selected add-ons
     pub addons: Vec<String>,
+    /// Fares and add-ons, itemized
+    pub line_items: Vec<OfferLineItem>,
     pub total_price: f64,
 }
 
+/// Card details exchanged for a payment token by tokenizePayment
+#[derive(InputObject)]
+pub struct CardInput {
+    pub number: String,
+    pub expiry_month: u32,
+    pub expiry_year: i32,
+    pub cvc: Option<String>,
+}
+
+/// A tokenized card; only the token, brand and last 4 digits are ever returned
+#[derive(SimpleObject, Clone)]
+pub struct TokenizedCard {
+    /// Pass as `payment` to bookFlight
+    pub token: String,
+    pub brand: String,
+    pub last4: String,
+}
+
+/// Lifecycle of the payment taken for a booking
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PaymentStatus {
+    Authorized,
+    Captured,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Authorized => "AUTHORIZED",
+            PaymentStatus::Captured => "CAPTURED",
+        }
+    }
+}
+
+impl std::str::FromStr for PaymentStatus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "AUTHORIZED" => Ok(PaymentStatus::Authorized),
+            "CAPTURED" => Ok(PaymentStatus::Captured),
+            other => Err(format!("Unknown payment status '{}'", other)),
+        }
+    }
+}
+
+/// Masked payment information of a booking
+#[derive(SimpleObject, Clone)]
+pub struct PaymentSummary {
+    pub brand: String,
+    pub last4: String,
+    pub status: PaymentStatus,
+    pub amount: f64,
+}
+
 /// Confirmation data for a booked flight
 #[derive(SimpleObject)]
 pub struct BookingConfirmation {
     pub booking_id: i64,
+    /// First flight of the booking
     pub flight: FlightOffer,
+    pub segments: Vec<FlightOffer>,
+    pub cabin: Cabin,
+    /// Seats assigned during booking
+    pub seats: Vec<AssignedSeat>,
+    /// Offer the booking was made from
+    pub offer_id: Option<String>,
+    /// Fares and add-ons charged
+    pub line_items: Vec<OfferLineItem>,
+    pub total_price: f64,
+    pub payment: PaymentSummary,
 }
 
 /// Detailed booking information
@@ -32,18 +517,25 @@ pub struct BookingConfirmation {
 pub struct BookingDetail {
     pub booking_id: i64,
     pub flight: FlightOffer,
-    pub passenger_details: String,
-    pub payment_details: String,
+    pub segments: Vec<FlightOffer>,
+    pub passengers: Vec<Passenger>,
+    /// Fares and add-ons charged
+    pub line_items: Vec<OfferLineItem>,
+    pub payment: PaymentSummary,
     pub booking_time: String,
 }
 
 /// Root Query type for GraphQL
+#[derive(Default)]
 pub struct QueryRoot;
 
 #[Object]
 impl QueryRoot {
     /// Search flights by origin, destination, and (optional) dates
-    #[graphql(name = "searchFlights")]
+    ///
+    /// Each date may be an exact day (`2025-06-01`), an inclusive range
+    /// (`2025-06-01..2025-06-05`) or a flexible window (`2025-06-03±2`).
+    #[graphql(name = "searchFlights", guard = "ScopeGuard(ApiScope::Search)")]
     async fn search_flights(
         &self,
         ctx: &Context<'_>,
@@ -52,97 +544,209 @@ impl QueryRoot {
         dates: Vec<String>,
     ) -> async_graphql::Result<Vec<FlightOffer>> {
         let pool = ctx.data::<SqlitePool>()?;
-        let flights = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE origin = ? AND destination = ?",
-        )
-        .bind(origin)
-        .bind(destination)
-        .fetch_all(pool)
-        .await?;
-        Ok(flights)
-    }
-
-    /// Retrieve a booking by its ID
-    #[graphql(name = "getBooking")]
+        search::find_flights(pool, &origin, &destination, &dates).await
+    }
+
+    /// Search flights and group the results per requested day
+    #[graphql(name = "searchFlightsByDate", guard = "ScopeGuard(ApiScope::Search)")]
+    async fn search_flights_by_date(
+        &self,
+        ctx: &Context<'_>,
+        origin: String,
+        destination: String,
+        dates: Vec<String>,
+    ) -> async_graphql::Result<Vec<DateFlights>> {
+        let pool = ctx.data::<SqlitePool>()?;
+        search::find_flights_by_date(pool, &origin, &destination, &dates).await
+    }
+
+    /// Search direct and connecting itineraries (up to 2 stops)
+    #[graphql(name = "searchItineraries", guard = "ScopeGuard(ApiScope::Search)")]
+    async fn search_itineraries(
+        &self,
+        ctx: &Context<'_>,
+        origin: String,
+        destination: String,
+        dates: Vec<String>,
+        #[graphql(default = 1)] max_stops: u32,
+    ) -> async_graphql::Result<Vec<Itinerary>> {
+        let pool = ctx.data::<SqlitePool>()?;
+        itinerary::find_itineraries(pool, &origin, &destination, &dates, max_stops as usize).await
+    }
+
+    /// Search one-way, round-trip and multi-city trips with combined pricing
+    #[graphql(name = "searchTrips", guard = "ScopeGuard(ApiScope::Search)")]
+    async fn search_trips(
+        &self,
+        ctx: &Context<'_>,
+        slices: Vec<TripSliceInput>,
+    ) -> async_graphql::Result<Vec<TripOption>> {
+        let pool = ctx.data::<SqlitePool>()?;
+        trips::find_trips(pool, &slices).await
+    }
+
+    /// Seat map of a flight with the status of every seat
+    #[graphql(name = "seatMap", guard = "ScopeGuard(ApiScope::Search)")]
+    async fn seat_map(&self, ctx: &Context<'_>, flight_id: i64) -> async_graphql::Result<SeatMap> {
+        let pool = ctx.data::<SqlitePool>()?;
+        seatmap::seat_map(pool, flight_id).await
+    }
+
+    /// Add-ons that can be selected with buildOffer
+    #[graphql(name = "addonCatalog", guard = "ScopeGuard(ApiScope::Search)")]
+    async fn addon_catalog(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Addon>> {
+        let pool = ctx.data::<SqlitePool>()?;
+        addons::catalog(pool).await
+    }
+
+    /// Retrieve one of the caller's bookings by its ID; bookings of other
+    /// sessions and agents are not found
+    #[graphql(name = "getBooking", guard = "ScopeGuard(ApiScope::Book)")]
     async fn get_booking(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<BookingDetail> {
         let pool = ctx.data::<SqlitePool>()?;
-        let (booking_id, flight_id, passenger_details, payment_details, booking_time): (i64, i64, String, String, String) =
-            sqlx::query_as(
-                "SELECT id, flight_id, passenger_details, payment_details, booking_time FROM bookings WHERE id = ?",
-            )
-            .bind(id)
-            .fetch_one(pool)
-            .await?;
-        let flight = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
-        )
-        .bind(flight_id)
-        .fetch_one(pool)
-        .await?;
+        booking::check_owner(pool, id, &Owner::of(ctx.data_opt::<BotInfo>())).await?;
+        let (booking_id, flight_id, booking_time): (i64, i64, String) =
+            sqlx::query_as("SELECT id, flight_id, booking_time FROM bookings WHERE id = ?")
+                .bind(id)
+                .fetch_one(pool)
+                .await?;
+        let flight = booking::fetch_flight(pool, flight_id).await?;
+        let mut segments = booking::booking_segments(pool, booking_id).await?;
+        if segments.is_empty() {
+            segments.push(flight.clone());
+        }
         Ok(BookingDetail {
             booking_id,
             flight,
-            passenger_details,
-            payment_details,
+            segments,
+            passengers: passengers::for_booking(pool, booking_id).await?,
+            line_items: booking::line_items(pool, booking_id).await?,
+            payment: booking::payment_summary(pool, booking_id).await?,
             booking_time,
         })
     }
 }
 
 /// Root Mutation type for GraphQL
+#[derive(Default)]
 pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
-    /// Build an offer summary for a given flight and selected add-ons
-    #[graphql(name = "buildOffer")]
+    /// Price a flight (or trip) for a cabin and passenger count, with itemized fares and add-ons
+    #[graphql(name = "buildOffer", guard = "ScopeGuard(ApiScope::Book)")]
+    #[allow(clippy::too_many_arguments)]
     async fn build_offer(
         &self,
         ctx: &Context<'_>,
-        flight_id: i64,
+        flight_id: Option<i64>,
+        trip_id: Option<String>,
+        #[graphql(
+            default,
+            deprecation = "Plain codes select one of each add-on; use addonSelections for quantities and flights"
+        )]
         addons: Vec<String>,
+        #[graphql(default)] addon_selections: Vec<AddonSelection>,
+        #[graphql(default)] cabin: Cabin,
+        #[graphql(default = 1)] passengers: i64,
     ) -> async_graphql::Result<OfferSummary> {
         let pool = ctx.data::<SqlitePool>()?;
-        let flight = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
-        )
-        .bind(flight_id)
-        .fetch_one(pool)
-        .await?;
-        let mut total = flight.price;
-        for _ in &addons {
-            total += 10.0;
+        let segments = booking::resolve_flights(pool, flight_id, trip_id.as_deref()).await?;
+        // Plain codes from clients of the old `[String]` argument select one of each
+        let addons: Vec<AddonSelection> = addons
+            .into_iter()
+            .map(|code| AddonSelection { code, quantity: 1, flight_id: None })
+            .chain(addon_selections)
+            .collect();
+        let (line_items, total_price) = addons::price_offer(pool, &segments, cabin, passengers, &addons).await?;
+        let (offer_id, expires_at) =
+            offers::create(pool, &segments, cabin, passengers, &line_items, total_price).await?;
+        let mut codes: Vec<String> = Vec::new();
+        for item in &line_items {
+            if let Some(code) = item.code.as_ref().filter(|c| !codes.contains(c)) {
+                codes.push(code.clone());
+            }
         }
-        Ok(OfferSummary { flight, addons, total_price: total })
+        Ok(OfferSummary {
+            offer_id,
+            expires_at,
+            flight: segments[0].clone(),
+            segments,
+            cabin,
+            passengers,
+            addons: codes,
+            line_items,
+            total_price,
+        })
     }
 
-    /// Book a flight with passenger and payment details
-    #[graphql(name = "bookFlight")]
+    /// Hold a seat for a few minutes while the booking is completed
+    #[graphql(name = "holdSeat", guard = "ScopeGuard(ApiScope::Book)")]
+    async fn hold_seat(&self, ctx: &Context<'_>, flight_id: i64, seat: String) -> async_graphql::Result<SeatHold> {
+        let pool = ctx.data::<SqlitePool>()?;
+        seatmap::hold_seat(pool, flight_id, &seat).await
+    }
+
+    /// Book a flight (or every flight of a trip) with passenger and payment details.
+    /// With an offerId, books exactly the quoted flights, cabin and add-ons at the quoted total.
+    #[graphql(name = "bookFlight", guard = "ScopeGuard(ApiScope::Book)")]
+    #[allow(clippy::too_many_arguments)]
     async fn book_flight(
         &self,
         ctx: &Context<'_>,
-        passenger_details: String,
+        passengers: Vec<PassengerInput>,
         payment: String,
-        flight_id: i64,
+        flight_id: Option<i64>,
+        trip_id: Option<String>,
+        offer_id: Option<String>,
+        #[graphql(desc = "Economy if omitted; must match the offer when booking one")] cabin: Option<Cabin>,
+        #[graphql(default)] seats: Vec<SeatSelectionInput>,
     ) -> async_graphql::Result<BookingConfirmation> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let mut tx = pool.begin().await?;
-        let result = sqlx::query(
-            "INSERT INTO bookings (flight_id, passenger_details, payment_details, booking_time) VALUES (?, ?, ?, datetime('now'))",
-        )
-        .bind(flight_id)
-        .bind(&passenger_details)
-        .bind(&payment)
-        .execute(&mut tx)
-        .await?;
-        let booking_id = result.last_insert_rowid();
-        tx.commit().await?;
-        let flight = sqlx::query_as::<_, FlightOffer>(
-            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE id = ?",
-        )
-        .bind(flight_id)
-        .fetch_one(pool)
-        .await?;
-        Ok(BookingConfirmation { booking_id, flight })
-    }
-}
\ No newline at end of file
+        book(ctx, passengers, payment, flight_id, trip_id, offer_id, cabin, seats).await
+    }
+
+    /// Exchange card details for a payment token usable in bookFlight
+    #[graphql(name = "tokenizePayment", guard = "ScopeGuard(ApiScope::Book)")]
+    async fn tokenize_payment(&self, ctx: &Context<'_>, card: CardInput) -> async_graphql::Result<TokenizedCard> {
+        let processor = ctx.data::<SharedPaymentProcessor>()?;
+        payment::tokenize(processor.as_ref(), card).await
+    }
+}
+
+/// bookFlight shared by both schemas; the bot schema's variant takes a Float flightId
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn book(
+    ctx: &Context<'_>,
+    passengers: Vec<PassengerInput>,
+    payment: String,
+    flight_id: Option<i64>,
+    trip_id: Option<String>,
+    offer_id: Option<String>,
+    cabin: Option<Cabin>,
+    seats: Vec<SeatSelectionInput>,
+) -> async_graphql::Result<BookingConfirmation> {
+    let pool = ctx.data::<SqlitePool>()?;
+    let processor = ctx.data::<SharedPaymentProcessor>()?;
+    let (flights, cabin, quote) = match offer_id {
+        Some(offer_id) => {
+            if flight_id.is_some() || trip_id.is_some() {
+                return Err(async_graphql::Error::new("Provide exactly one of flightId, tripId or offerId"));
+            }
+            let (flights, offer_cabin, quote) = offers::revalidate(pool, &offer_id, &passengers).await?;
+            if cabin.is_some_and(|cabin| cabin != offer_cabin) {
+                return Err(async_graphql::Error::new(format!(
+                    "Offer {} was quoted for the {} cabin",
+                    offer_id,
+                    offer_cabin.as_str()
+                )));
+            }
+            (flights, offer_cabin, Some(quote))
+        }
+        None => {
+            let flights = booking::resolve_flights(pool, flight_id, trip_id.as_deref()).await?;
+            (flights, cabin.unwrap_or_default(), None)
+        }
+    };
+    let owner = Owner::of(ctx.data_opt::<BotInfo>());
+    booking::create_booking(pool, processor.as_ref(), flights, cabin, quote, &seats, &passengers, &payment, &owner).await
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeSet;
+
+use chrono::{Duration, NaiveDate};
+use sqlx::SqlitePool;
+
+use crate::price_history;
+use crate::schema::{DateFlights, FlightOffer};
+
+/// Maximum number of calendar days a single search may expand to
+pub const MAX_SEARCH_DAYS: usize = 31;
+
+/// A single entry of the `dates` argument accepted by searchFlights
+///
+/// Supported forms:
+/// - `2025-06-01` exact day
+/// - `2025-06-01..2025-06-05` inclusive range
+/// - `2025-06-03±2` (or `2025-06-03+-2`) flexible window of N days either side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DateSpec {
+    Exact(NaiveDate),
+    Range(NaiveDate, NaiveDate),
+    Flexible { center: NaiveDate, days: u32 },
+}
+
+impl DateSpec {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        if let Some((start, end)) = input.split_once("..") {
+            let start = parse_day(start)?;
+            let end = parse_day(end)?;
+            if end < start {
+                return Err(format!("Date range '{}' ends before it starts", input));
+            }
+            return Ok(DateSpec::Range(start, end));
+        }
+        if let Some((center, days)) = input.split_once('±').or_else(|| input.split_once("+-")) {
+            let center = parse_day(center)?;
+            let days = days
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid flexible window '{}'", input))?;
+            return Ok(DateSpec::Flexible { center, days });
+        }
+        parse_day(input).map(DateSpec::Exact)
+    }
+
+    /// Number of calendar days the spec covers, computed without expanding it
+    pub fn day_count(&self) -> i64 {
+        match *self {
+            DateSpec::Exact(_) => 1,
+            DateSpec::Range(start, end) => (end - start).num_days() + 1,
+            DateSpec::Flexible { days, .. } => days as i64 * 2 + 1,
+        }
+    }
+
+    /// Expand the spec into the calendar days it covers
+    pub fn days(&self) -> Vec<NaiveDate> {
+        let (start, end) = match *self {
+            DateSpec::Exact(day) => (day, day),
+            DateSpec::Range(start, end) => (start, end),
+            DateSpec::Flexible { center, days } => {
+                let days = Duration::days(days as i64);
+                (center - days, center + days)
+            }
+        };
+        start.iter_days().take_while(|day| *day <= end).collect()
+    }
+}
+
+fn parse_day(input: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", input.trim()))
+}
+
+/// Resolve the `dates` argument into the sorted set of days to search.
+/// Blank entries are ignored; an empty result means "any date".
+pub fn requested_days(dates: &[String]) -> async_graphql::Result<Vec<NaiveDate>> {
+    let mut days = BTreeSet::new();
+    for entry in dates.iter().filter(|d| !d.trim().is_empty()) {
+        let spec = DateSpec::parse(entry).map_err(async_graphql::Error::new)?;
+        // Guard before expanding so a huge range or window can't allocate unbounded days
+        if spec.day_count() > MAX_SEARCH_DAYS as i64 {
+            return Err(too_many_days());
+        }
+        days.extend(spec.days());
+        if days.len() > MAX_SEARCH_DAYS {
+            return Err(too_many_days());
+        }
+    }
+    Ok(days.into_iter().collect())
+}
+
+fn too_many_days() -> async_graphql::Error {
+    async_graphql::Error::new(format!(
+        "Search covers more than {} days",
+        MAX_SEARCH_DAYS
+    ))
+}
+
+/// Direct flights on a route, restricted to the requested dates; their prices
+/// are recorded as a search snapshot
+pub async fn find_flights(
+    pool: &SqlitePool,
+    origin: &str,
+    destination: &str,
+    dates: &[String],
+) -> async_graphql::Result<Vec<FlightOffer>> {
+    let days = requested_days(dates)?;
+    let flights = match (days.first(), days.last()) {
+        (Some(first), Some(last)) => sqlx::query_as::<_, FlightOffer>(
+            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE origin = ? AND destination = ? AND substr(departure_time, 1, 10) BETWEEN ? AND ? ORDER BY julianday(departure_time), id",
+        )
+        .bind(origin)
+        .bind(destination)
+        .bind(first.to_string())
+        .bind(last.to_string())
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .filter(|f| departure_day(f).is_some_and(|day| days.binary_search(&day).is_ok()))
+        .collect(),
+        _ => sqlx::query_as::<_, FlightOffer>(
+            "SELECT id, origin, destination, departure_time, arrival_time, price FROM flights WHERE origin = ? AND destination = ? ORDER BY julianday(departure_time), id",
+        )
+        .bind(origin)
+        .bind(destination)
+        .fetch_all(pool)
+        .await?,
+    };
+    price_history::spawn_search_snapshot(pool, &flights);
+    Ok(flights)
+}
+
+/// Direct flights grouped per requested day, including days with no flights
+pub async fn find_flights_by_date(
+    pool: &SqlitePool,
+    origin: &str,
+    destination: &str,
+    dates: &[String],
+) -> async_graphql::Result<Vec<DateFlights>> {
+    let days = requested_days(dates)?;
+    if days.is_empty() {
+        return Err(async_graphql::Error::new(
+            "At least one date is required to group results",
+        ));
+    }
+    let flights = find_flights(pool, origin, destination, dates).await?;
+
+    Ok(days
+        .into_iter()
+        .map(|day| {
+            let flights: Vec<FlightOffer> = flights
+                .iter()
+                .filter(|f| departure_day(f) == Some(day))
+                .cloned()
+                .collect();
+            let lowest_price = flights.iter().map(|f| f.price).reduce(f64::min);
+            DateFlights {
+                date: day.to_string(),
+                flights,
+                lowest_price,
+            }
+        })
+        .collect())
+}
+
+fn departure_day(flight: &FlightOffer) -> Option<NaiveDate> {
+    flight.departure_time.get(..10).and_then(|d| parse_day(d).ok())
+}
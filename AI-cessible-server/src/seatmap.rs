@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use async_graphql::ErrorExtensions;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::errors::ApiError;
+use crate::schema::{Cabin, Seat, SeatHold, SeatMap, SeatRow, SeatSelectionInput, SeatStatus};
+
+/// How long a held seat stays reserved before it is released
+pub const HOLD_MINUTES: i64 = 10;
+
+/// Cabin layout of an aircraft type, one block of rows per cabin
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct CabinLayout {
+    pub cabin: String,
+    pub first_row: i64,
+    pub last_row: i64,
+    pub seat_letters: String,
+    pub pitch_inches: f64,
+    pub width_inches: f64,
+    pub recline_degrees: f64,
+    pub has_power: bool,
+    pub exit_rows: String,
+}
+
+impl CabinLayout {
+    fn cabin(&self) -> Cabin {
+        self.cabin.parse().unwrap_or_default()
+    }
+
+    fn contains_row(&self, row: i64) -> bool {
+        (self.first_row..=self.last_row).contains(&row)
+    }
+
+    fn is_exit_row(&self, row: i64) -> bool {
+        self.exit_rows
+            .split(',')
+            .filter_map(|r| r.trim().parse::<i64>().ok())
+            .any(|r| r == row)
+    }
+}
+
+/// Aircraft configuration assigned to a flight
+pub struct Aircraft {
+    pub code: String,
+    pub name: String,
+    pub has_wifi: bool,
+    pub cabins: Vec<CabinLayout>,
+}
+
+/// A seat resolved against its aircraft configuration
+pub struct SeatInfo {
+    pub seat: String,
+    pub cabin: Cabin,
+    pub exit_row: bool,
+    pub layout: CabinLayout,
+    pub has_wifi: bool,
+}
+
+pub async fn aircraft_for_flight(pool: &SqlitePool, flight_id: i64) -> async_graphql::Result<Aircraft> {
+    let (code, name, has_wifi): (String, String, bool) = sqlx::query_as(
+        "SELECT a.code, a.name, a.has_wifi FROM flights f JOIN aircraft_configs a ON a.code = f.aircraft_code WHERE f.id = ?",
+    )
+    .bind(flight_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::FlightNotFound(flight_id).extend())?;
+
+    let cabins = sqlx::query_as::<_, CabinLayout>(
+        "SELECT cabin, first_row, last_row, seat_letters, pitch_inches, width_inches, recline_degrees, has_power, exit_rows FROM aircraft_cabins WHERE aircraft_code = ? ORDER BY first_row",
+    )
+    .bind(&code)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Aircraft { code, name, has_wifi, cabins })
+}
+
+/// Split a seat like `12C` into its row number and letter
+fn parse_seat(seat: &str) -> Option<(i64, char)> {
+    let seat = seat.trim().to_ascii_uppercase();
+    let letter = seat.chars().last()?;
+    let row = seat[..seat.len() - letter.len_utf8()].parse().ok()?;
+    letter.is_ascii_alphabetic().then_some((row, letter))
+}
+
+/// Look up a seat in the aircraft configuration
+pub fn locate_seat(aircraft: &Aircraft, flight_id: i64, seat: &str) -> async_graphql::Result<SeatInfo> {
+    let not_found = || ApiError::SeatNotFound { flight_id, seat: seat.to_string() }.extend();
+    let (row, letter) = parse_seat(seat).ok_or_else(not_found)?;
+    let layout = aircraft
+        .cabins
+        .iter()
+        .find(|c| c.contains_row(row) && c.seat_letters.contains(letter))
+        .ok_or_else(not_found)?;
+    Ok(SeatInfo {
+        seat: format!("{}{}", row, letter),
+        cabin: layout.cabin(),
+        exit_row: layout.is_exit_row(row),
+        layout: layout.clone(),
+        has_wifi: aircraft.has_wifi,
+    })
+}
+
+/// Current status of every occupied seat; seats missing from the map are free
+async fn occupied_seats(pool: &SqlitePool, flight_id: i64) -> async_graphql::Result<HashMap<String, SeatStatus>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT seat, status FROM seat_assignments WHERE flight_id = ? AND (status = 'ASSIGNED' OR held_until > datetime('now'))",
+    )
+    .bind(flight_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(seat, status)| {
+            let status = if status == "ASSIGNED" { SeatStatus::Assigned } else { SeatStatus::Held };
+            (seat, status)
+        })
+        .collect())
+}
+
+/// Build the full seat map for a flight
+pub async fn seat_map(pool: &SqlitePool, flight_id: i64) -> async_graphql::Result<SeatMap> {
+    let aircraft = aircraft_for_flight(pool, flight_id).await?;
+    let occupied = occupied_seats(pool, flight_id).await?;
+
+    let mut rows = Vec::new();
+    for layout in &aircraft.cabins {
+        let cabin = layout.cabin();
+        for row in layout.first_row..=layout.last_row {
+            let exit_row = layout.is_exit_row(row);
+            let seats = layout
+                .seat_letters
+                .chars()
+                .map(|letter| {
+                    let seat = format!("{}{}", row, letter);
+                    Seat {
+                        status: occupied.get(&seat).copied().unwrap_or(SeatStatus::Free),
+                        seat,
+                        letter: letter.to_string(),
+                        cabin,
+                        exit_row,
+                        has_power: layout.has_power,
+                    }
+                })
+                .collect();
+            rows.push(SeatRow { row: row as i32, cabin, exit_row, seats });
+        }
+    }
+
+    Ok(SeatMap {
+        flight_id,
+        aircraft_code: aircraft.code,
+        aircraft: aircraft.name,
+        has_wifi: aircraft.has_wifi,
+        rows,
+    })
+}
+
+/// Hold a free seat for a short time so it can be booked with the returned token
+pub async fn hold_seat(pool: &SqlitePool, flight_id: i64, seat: &str) -> async_graphql::Result<SeatHold> {
+    let aircraft = aircraft_for_flight(pool, flight_id).await?;
+    let info = locate_seat(&aircraft, flight_id, seat)?;
+    let hold_token = uuid::Uuid::new_v4().to_string();
+
+    let result = sqlx::query(
+        "INSERT INTO seat_assignments (flight_id, seat, status, hold_token, held_until) VALUES (?, ?, 'HELD', ?, datetime('now', ?)) \
+         ON CONFLICT(flight_id, seat) DO UPDATE SET status = 'HELD', hold_token = excluded.hold_token, held_until = excluded.held_until \
+         WHERE seat_assignments.status = 'HELD' AND seat_assignments.held_until <= datetime('now')",
+    )
+    .bind(flight_id)
+    .bind(&info.seat)
+    .bind(&hold_token)
+    .bind(format!("+{} minutes", HOLD_MINUTES))
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::SeatUnavailable { flight_id, seat: info.seat }.extend());
+    }
+
+    let (expires_at,): (String,) = sqlx::query_as(
+        "SELECT held_until FROM seat_assignments WHERE flight_id = ? AND seat = ?",
+    )
+    .bind(flight_id)
+    .bind(&info.seat)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(SeatHold { flight_id, seat: info.seat, hold_token, expires_at })
+}
+
+/// Validate a seat selection against the booked flights and cabin
+pub async fn validate_selection(
+    pool: &SqlitePool,
+    flight_ids: &[i64],
+    cabin: Cabin,
+    selection: &SeatSelectionInput,
+) -> async_graphql::Result<SeatInfo> {
+    if !flight_ids.contains(&selection.flight_id) {
+        return Err(async_graphql::Error::new(format!(
+            "Seat {} is for flight {}, which is not part of this booking",
+            selection.seat, selection.flight_id
+        )));
+    }
+    let aircraft = aircraft_for_flight(pool, selection.flight_id).await?;
+    let info = locate_seat(&aircraft, selection.flight_id, &selection.seat)?;
+    if info.cabin != cabin {
+        return Err(ApiError::SeatNotFound { flight_id: selection.flight_id, seat: info.seat }.extend());
+    }
+    Ok(info)
+}
+
+/// Assign a seat to a booking inside the booking transaction.
+/// Free seats, expired holds and holds matching `hold_token` can be assigned.
+pub async fn assign_seat(
+    tx: &mut Transaction<'_, Sqlite>,
+    booking_id: i64,
+    flight_id: i64,
+    seat: &str,
+    hold_token: Option<&str>,
+) -> async_graphql::Result<()> {
+    let result = sqlx::query(
+        "INSERT INTO seat_assignments (flight_id, seat, status, booking_id) VALUES (?, ?, 'ASSIGNED', ?) \
+         ON CONFLICT(flight_id, seat) DO UPDATE SET status = 'ASSIGNED', booking_id = excluded.booking_id, hold_token = NULL, held_until = NULL \
+         WHERE seat_assignments.status = 'HELD' AND (seat_assignments.hold_token = ? OR seat_assignments.held_until <= datetime('now'))",
+    )
+    .bind(flight_id)
+    .bind(seat)
+    .bind(booking_id)
+    .bind(hold_token)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::SeatUnavailable { flight_id, seat: seat.to_string() }.extend());
+    }
+    Ok(())
+}
+
+/// Seat assigned to a booking on a given flight, if one was chosen
+pub async fn assigned_seat(
+    pool: &SqlitePool,
+    booking_id: i64,
+    flight_id: i64,
+) -> async_graphql::Result<Option<String>> {
+    let seat: Option<(String,)> = sqlx::query_as(
+        "SELECT seat FROM seat_assignments WHERE booking_id = ? AND flight_id = ? AND status = 'ASSIGNED'",
+    )
+    .bind(booking_id)
+    .bind(flight_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(seat.map(|(seat,)| seat))
+}
@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_graphql::parser::types::Selection;
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::bot_detection::{classify_intelligence, IntelligenceLevel, BOT_ONLY_FIELDS};
+use crate::bot_schema::{BehaviorAggregate, EndpointUsage, GraphqlQueryStats, Session, SessionRequest};
+use crate::query_tracking::{self, QueryPattern, QueryRecord};
+
+/// Cookie carrying the session id for browsers
+pub const SESSION_COOKIE: &str = "bot_shop_session";
+
+/// Header carrying the session id; sent by clients and echoed on every response
+pub const SESSION_HEADER: &str = "x-session-id";
+
+/// Sessions kept in memory; past this the least recently used one is dropped
+pub const MAX_SESSIONS: usize = 10_000;
+
+/// Requests remembered per session
+const MAX_HISTORY: usize = 50;
+
+/// GraphQL query shapes remembered per session
+const MAX_QUERIES: usize = 20;
+
+/// Sessions without a request for this long may be dropped
+const SESSION_IDLE: Duration = Duration::from_secs(30 * 60);
+
+/// Bytes of the tag that marks a session id as issued by this server
+const SESSION_TAG_LEN: usize = 16;
+
+/// Where the session id of a request came from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SessionSource {
+    Header,
+    Cookie,
+    /// No usable id was sent, so a new session was started
+    Issued,
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// `Set-Cookie` value that keeps a browser in its session
+pub fn set_cookie(session_id: &str) -> String {
+    format!("{}={}; Path=/; HttpOnly; SameSite=Lax", SESSION_COOKIE, session_id)
+}
+
+/// Root fields a GraphQL handler executed, attached to its response so the
+/// session history can record them
+#[derive(Clone, Debug, Default)]
+pub struct OperationFields(pub Vec<String>);
+
+/// Root fields of the operation a GraphQL request will run
+pub fn operation_fields(request: &mut async_graphql::Request) -> OperationFields {
+    let operation_name = request.operation_name.clone();
+    let Ok(document) = request.parsed_query() else {
+        return OperationFields::default();
+    };
+    let fields = document
+        .operations
+        .iter()
+        .filter(|(name, _)| operation_name.is_none() || name.map(|n| n.as_str()) == operation_name.as_deref())
+        .flat_map(|(_, operation)| operation.node.selection_set.node.items.iter())
+        .filter_map(|selection| match &selection.node {
+            Selection::Field(field) => Some(field.node.name.node.to_string()),
+            _ => None,
+        })
+        .collect();
+    OperationFields(fields)
+}
+
+struct RequestEntry {
+    sequence: u64,
+    at: DateTime<Utc>,
+    method: String,
+    path: String,
+    confidence: f32,
+    fields: Vec<String>,
+    bot_api: bool,
+}
+
+struct SessionState {
+    started_at: DateTime<Utc>,
+    /// Position of the session's last request in [`Sessions::recency`]
+    touched: u64,
+    last_seen: Instant,
+    last_seen_at: DateTime<Utc>,
+    request_count: u64,
+    confidence_total: f64,
+    history: VecDeque<RequestEntry>,
+    bot_endpoints: BTreeMap<String, u64>,
+    /// Score of the session's behavior reports, updated as they are stored
+    behavior_score: Option<f64>,
+    /// Averaged navigation signal of the session's behavior reports
+    navigation: Option<f64>,
+    /// Whether the session ever loaded the web app
+    loaded_app: bool,
+    bot_field_uses: u64,
+    intelligence_level: IntelligenceLevel,
+    /// Recent GraphQL queries, oldest first
+    queries: Vec<QueryRecord>,
+}
+
+impl SessionState {
+    fn cumulative_confidence(&self) -> f32 {
+        (self.confidence_total / self.request_count.max(1) as f64) as f32
+    }
+
+    /// Without behavior reports, sessions that call the API without ever
+    /// loading the app navigate like scripts
+    fn navigation(&self) -> f64 {
+        self.navigation.unwrap_or(if self.loaded_app { 0.5 } else { 0.8 })
+    }
+
+    fn classify(&mut self) {
+        self.intelligence_level =
+            classify_intelligence(self.cumulative_confidence(), self.navigation(), self.bot_field_uses);
+    }
+}
+
+/// Session states and the order they were last used in
+#[derive(Default)]
+struct Sessions {
+    states: HashMap<String, SessionState>,
+    /// Session ids by the tick of their last request, least recent first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Sessions {
+    /// Drop the least recently used sessions while the store is full or they are idle
+    fn evict(&mut self, now: Instant) {
+        while let Some(entry) = self.recency.first_entry() {
+            let idle = self.states.get(entry.get()).is_none_or(|s| now.duration_since(s.last_seen) >= SESSION_IDLE);
+            if self.states.len() < MAX_SESSIONS && !idle {
+                break;
+            }
+            let session_id = entry.remove();
+            self.states.remove(&session_id);
+        }
+    }
+}
+
+/// In-memory state of the sessions seen by the server
+#[derive(Clone)]
+pub struct SessionStore {
+    sessions: Arc<Mutex<Sessions>>,
+    /// Key of the tags on issued session ids; new on every start, so ids
+    /// issued before a restart count as client-chosen
+    secret: Arc<[u8; 32]>,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        let mut secret = [0; 32];
+        secret[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+        secret[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+        SessionStore { sessions: Arc::default(), secret: Arc::new(secret) }
+    }
+}
+
+impl SessionStore {
+    fn tag(&self, nonce: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_slice()).expect("HMAC accepts any key length");
+        mac.update(nonce.as_bytes());
+        mac
+    }
+
+    /// New session id: a random nonce followed by its tag, both hex
+    fn issue(&self) -> String {
+        let nonce = uuid::Uuid::new_v4().simple().to_string();
+        let tag = self.tag(&nonce).finalize().into_bytes();
+        let tag: String = tag[..SESSION_TAG_LEN].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}{}", nonce, tag)
+    }
+
+    /// Whether this server issued the session id, rather than a client choosing it
+    pub fn issued_here(&self, session_id: &str) -> bool {
+        let nonce_len = 32;
+        if session_id.len() != nonce_len + SESSION_TAG_LEN * 2 || !session_id.is_ascii() {
+            return false;
+        }
+        let (nonce, tag) = session_id.split_at(nonce_len);
+        let tag: Option<Vec<u8>> =
+            (0..tag.len()).step_by(2).map(|i| u8::from_str_radix(&tag[i..i + 2], 16).ok()).collect();
+        tag.is_some_and(|tag| self.tag(nonce).verify_truncated_left(&tag).is_ok())
+    }
+
+    /// Session id from `X-Session-Id` or the session cookie, or a newly issued one.
+    /// Either must be an id this server issued, so clients cannot pick their
+    /// own (or another client's guessable) id; others, such as those from
+    /// before a restart, are replaced.
+    pub fn session_id(&self, headers: &HeaderMap) -> (String, SessionSource) {
+        let header = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok()).map(str::trim);
+        if let Some(id) = header.filter(|id| self.issued_here(id)) {
+            return (id.to_string(), SessionSource::Header);
+        }
+        if let Some(id) = session_cookie(headers).filter(|id| self.issued_here(id)) {
+            return (id.to_string(), SessionSource::Cookie);
+        }
+        (self.issue(), SessionSource::Issued)
+    }
+
+    /// Record the start of a request and return its sequence number in the session.
+    /// Callers skip requests without a session id: their newly issued session
+    /// only gets state once the client sends it back.
+    pub fn begin(&self, session_id: &str, method: &str, path: &str, confidence: f32) -> u64 {
+        let now = Instant::now();
+        let mut guard = self.sessions.lock().unwrap();
+        let sessions = &mut *guard;
+        sessions.tick += 1;
+        let tick = sessions.tick;
+        if let Some(state) = sessions.states.get_mut(session_id) {
+            let touched = std::mem::replace(&mut state.touched, tick);
+            sessions.recency.remove(&touched);
+        } else {
+            sessions.evict(now);
+        }
+        sessions.recency.insert(tick, session_id.to_string());
+        let state = sessions.states.entry(session_id.to_string()).or_insert_with(|| SessionState {
+            started_at: Utc::now(),
+            touched: tick,
+            last_seen: now,
+            last_seen_at: Utc::now(),
+            request_count: 0,
+            confidence_total: 0.0,
+            history: VecDeque::new(),
+            bot_endpoints: BTreeMap::new(),
+            behavior_score: None,
+            navigation: None,
+            loaded_app: false,
+            bot_field_uses: 0,
+            intelligence_level: IntelligenceLevel::default(),
+            queries: Vec::new(),
+        });
+        state.last_seen = now;
+        state.last_seen_at = Utc::now();
+        state.request_count += 1;
+        state.confidence_total += f64::from(confidence);
+        // The page itself is fetched before the session cookie is set, so its assets count too
+        state.loaded_app |= method == "GET" && (path == "/" || path.starts_with("/static/"));
+        if state.history.len() >= MAX_HISTORY {
+            state.history.pop_front();
+        }
+        state.history.push_back(RequestEntry {
+            sequence: state.request_count,
+            at: state.last_seen_at,
+            method: method.to_string(),
+            path: path.to_string(),
+            confidence,
+            fields: Vec::new(),
+            bot_api: false,
+        });
+        state.classify();
+        state.request_count
+    }
+
+    /// Record how a request was served: its GraphQL root fields and whether a
+    /// bot endpoint answered it
+    pub fn finish(&self, session_id: &str, sequence: u64, fields: Vec<String>, bot_api: bool) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(state) = sessions.states.get_mut(session_id) else {
+            return;
+        };
+        let Some(entry) = state.history.iter_mut().rev().find(|e| e.sequence == sequence) else {
+            return;
+        };
+        if bot_api {
+            *state.bot_endpoints.entry(entry.path.clone()).or_default() += 1;
+            let intent_post = entry.method == "POST" && entry.path == "/bot/intent";
+            let bot_fields = fields.iter().filter(|f| BOT_ONLY_FIELDS.contains(&f.as_str())).count();
+            state.bot_field_uses += bot_fields as u64 + u64::from(intent_post);
+        }
+        entry.fields = fields;
+        entry.bot_api = bot_api;
+        state.classify();
+    }
+
+    /// Take in the session's behavior aggregate after a report was stored
+    pub fn record_behavior(&self, aggregate: &BehaviorAggregate) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(state) = sessions.states.get_mut(&aggregate.session_id) else {
+            return;
+        };
+        state.behavior_score = aggregate.behavior_score;
+        state.navigation = aggregate.avg_navigation_pattern.or(state.navigation);
+        state.classify();
+    }
+
+    /// Score of the session's behavior reports, if it sent any
+    pub fn behavior_score(&self, session_id: &str) -> Option<f64> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.states.get(session_id)?.behavior_score
+    }
+
+    /// Record the shape of a GraphQL query the session ran
+    pub fn record_query(&self, session_id: &str, record: QueryRecord) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(state) = sessions.states.get_mut(session_id) else {
+            return;
+        };
+        if state.queries.len() >= MAX_QUERIES {
+            state.queries.remove(0);
+        }
+        state.queries.push(record);
+    }
+
+    /// Scripted pattern in the session's recent GraphQL queries, if it sent any
+    pub fn query_pattern(&self, session_id: &str) -> Option<QueryPattern> {
+        let sessions = self.sessions.lock().unwrap();
+        query_tracking::query_pattern(&sessions.states.get(session_id)?.queries)
+    }
+
+    /// Current state of a session
+    pub fn snapshot(&self, session_id: &str) -> Option<Session> {
+        let sessions = self.sessions.lock().unwrap();
+        let state = sessions.states.get(session_id)?;
+        let pattern = query_tracking::query_pattern(&state.queries);
+        Some(Session {
+            id: session_id.to_string(),
+            started_at: state.started_at.to_rfc3339(),
+            last_seen_at: state.last_seen_at.to_rfc3339(),
+            request_count: state.request_count as i64,
+            cumulative_confidence: state.cumulative_confidence(),
+            intelligence_level: state.intelligence_level,
+            bot_field_uses: state.bot_field_uses as i64,
+            history: state
+                .history
+                .iter()
+                .map(|e| SessionRequest {
+                    at: e.at.to_rfc3339(),
+                    method: e.method.clone(),
+                    path: e.path.clone(),
+                    confidence: e.confidence,
+                    fields: e.fields.clone(),
+                    bot_api: e.bot_api,
+                })
+                .collect(),
+            bot_endpoints: state
+                .bot_endpoints
+                .iter()
+                .map(|(endpoint, count)| EndpointUsage { endpoint: endpoint.clone(), count: *count as i64 })
+                .collect(),
+            queries: state
+                .queries
+                .iter()
+                .map(|q| GraphqlQueryStats {
+                    hash: format!("{:016x}", q.hash),
+                    depth: q.depth as i32,
+                    complexity: q.complexity as i32,
+                    fields: q.fields.clone(),
+                    introspection: q.introspection,
+                    rejected: q.rejected.clone(),
+                })
+                .collect(),
+            query_pattern_score: pattern.as_ref().map(|p| p.score),
+            query_pattern: pattern.map(|p| p.detail),
+        })
+    }
+}
@@ -1,89 +1,1090 @@
 #[cfg(test)]
 mod tests {
-    use crate::schema::{QueryRoot, MutationRoot, FlightOffer};
-    use crate::bot_schema::{BotQueryRoot, BotMutationRoot};
-    use crate::bot_detection::BotInfo;
+    use crate::schema::{QueryRoot, MutationRoot};
+    use crate::bot_schema::{BotIntent, BotMutation, BotQuery};
+    use crate::bot_detection::{self, BotInfo};
+    use crate::config::{self, Config, DetectionConfig, NegotiationConfig};
+    use crate::api_keys::{AGENT_SCOPES, ALL_SCOPES};
+    use crate::db;
+    use crate::{airports, behavior, intents, inventory};
+    use crate::payment::{MockPaymentProcessor, PaymentProcessor, SharedPaymentProcessor};
+    use crate::sessions::SessionStore;
     use async_graphql::{Schema, Request};
     use sqlx::SqlitePool;
+    use std::sync::Arc;
 
     type AppSchema = Schema<QueryRoot, MutationRoot, async_graphql::EmptySubscription>;
-    type BotSchema = Schema<BotQueryRoot, BotMutationRoot, async_graphql::EmptySubscription>;
+    type BotSchema = Schema<BotQuery, BotMutation, async_graphql::EmptySubscription>;
+
+    /// Detection data of requests that bring none: one session with every scope
+    fn test_caller() -> BotInfo {
+        let mut caller = bot_detection::score_request(&Default::default(), &DetectionConfig::default(), &Default::default());
+        caller.session_id = "test-session".to_string();
+        caller.agent_scopes = ALL_SCOPES.to_vec();
+        caller
+    }
 
     async fn setup_schema() -> (SqlitePool, AppSchema, BotSchema) {
-        let database_url = "sqlite::memory:";
-        let pool = SqlitePool::connect(database_url).await.unwrap();
-        sqlx::query(
-            r#"CREATE TABLE flights (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                origin TEXT NOT NULL,
-                destination TEXT NOT NULL,
-                departure_time TEXT NOT NULL,
-                arrival_time TEXT NOT NULL,
-                price REAL NOT NULL
-            );"#,
-        )
-        .execute(&pool)
-        .await
-        .unwrap();
+        let pool = db::connect("sqlite::memory:").await.unwrap();
+        db::migrate(&pool).await.unwrap();
         sqlx::query(
             r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price)
-               VALUES ('NYC','LAX','2025-06-01T08:00:00','2025-06-01T11:00:00',199.0);"#,
+               VALUES ('NYC','LAX','2025-06-01T08:00:00-04:00','2025-06-01T11:00:00-07:00',199.0);"#,
         )
         .execute(&pool)
         .await
         .unwrap();
 
+        // Payment token of a card that is always approved, used as bookFlight's `payment`
+        sqlx::query("INSERT INTO payment_methods (token, brand, last4, created_time) VALUES ('tok_test_visa', 'VISA', '1111', datetime('now'))")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let payments: SharedPaymentProcessor = Arc::new(MockPaymentProcessor::new(pool.clone()));
+        let schema = Schema::build(QueryRoot, MutationRoot, async_graphql::EmptySubscription)
+            .data(pool.clone())
+            .data(payments.clone())
+            .data(inventory::loader(pool.clone()))
+            .data(airports::loader(pool.clone()))
+            .data(test_caller())
+            .finish();
+        let bot_schema = Schema::build(BotQuery::default(), BotMutation::default(), async_graphql::EmptySubscription)
+            .data(pool.clone())
+            .data(payments)
+            .data(inventory::loader(pool.clone()))
+            .data(airports::loader(pool.clone()))
+            .data(NegotiationConfig::default())
+            .data(SessionStore::default())
+            .data(test_caller())
+            .finish();
+        (pool, schema, bot_schema)
+    }
+
+    #[tokio::test]
+    async fn test_search_flights() {
+        let (_pool, schema, _bot) = setup_schema().await;
+        let request = Request::new("{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: []) { id } }");
+        let response = schema.execute(request).await.data;
+        let list = response.into_json().unwrap()["searchFlights"].as_array().unwrap().clone();
+        assert!(!list.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_flights_date_filters() {
+        let (_pool, schema, _bot) = setup_schema().await;
+        let count = |dates: &str| {
+            let query = format!("{{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: {}) {{ id }} }}", dates);
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(Request::new(query)).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                response.data.into_json().unwrap()["searchFlights"].as_array().unwrap().len()
+            }
+        };
+        assert_eq!(count("[\"2025-06-01\"]").await, 1);
+        assert_eq!(count("[\"2025-06-02\"]").await, 0);
+        assert_eq!(count("[\"2025-05-30..2025-06-01\"]").await, 1);
+        assert_eq!(count("[\"2025-06-02±1\"]").await, 1);
+        assert_eq!(count("[\"2025-06-03+-1\"]").await, 0);
+
+        // Ranges are bounded before they are expanded into days
+        let response = schema
+            .execute(Request::new("{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: [\"0001-01-01..9999-12-31\"]) { id } }"))
+            .await;
+        assert!(!response.errors.is_empty());
+
+        let response = schema
+            .execute(Request::new("{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: [\"June 1st\"]) { id } }"))
+            .await;
+        assert!(!response.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bot_search_flights_by_date() {
+        let (_pool, _schema, bot_schema) = setup_schema().await;
+        let query = "{ searchFlightsByDate(origin: \"NYC\", destination: \"LAX\", dates: [\"2025-06-01±1\"]) { date lowestPrice flights { id } } }";
+        let response = bot_schema.execute(Request::new(query)).await.data;
+        let groups = response.into_json().unwrap()["searchFlightsByDate"].as_array().unwrap().clone();
+        let dates: Vec<&str> = groups.iter().map(|g| g["date"].as_str().unwrap()).collect();
+        assert_eq!(dates, vec!["2025-05-31", "2025-06-01", "2025-06-02"]);
+        assert!(groups[0]["lowestPrice"].is_null());
+        assert_eq!(groups[1]["flights"].as_array().unwrap().len(), 1);
+        assert_eq!(groups[1]["lowestPrice"].as_f64(), Some(199.0));
+    }
+
+    #[tokio::test]
+    async fn test_search_itineraries_builds_connections() {
+        let (pool, _schema, bot_schema) = setup_schema().await;
         sqlx::query(
-            r#"CREATE TABLE bookings (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                flight_id INTEGER NOT NULL,
-                passenger_details TEXT NOT NULL,
-                payment_details TEXT NOT NULL,
-                booking_time TEXT NOT NULL
-            );"#,
+            r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price) VALUES
+               ('LAX','SEA','2025-06-01T11:15:00-07:00','2025-06-01T14:00:00-07:00',120.0),
+               ('LAX','SEA','2025-06-01T13:30:00-07:00','2025-06-01T16:15:00-07:00',159.0),
+               ('LAX','SEA','2025-06-03T07:00:00-07:00','2025-06-03T09:45:00-07:00',149.0);"#,
         )
         .execute(&pool)
         .await
         .unwrap();
 
+        let query = "{ searchItineraries(origin: \"NYC\", destination: \"SEA\", dates: [\"2025-06-01\"]) { stops totalPrice totalDurationMinutes segments { id } layovers { airport durationMinutes } } }";
+        let response = bot_schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let list = response.data.into_json().unwrap()["searchItineraries"].as_array().unwrap().clone();
+
+        // 11:15 is under the minimum connection time and 06-03 exceeds the journey cap
+        assert_eq!(list.len(), 1);
+        let itinerary = &list[0];
+        assert_eq!(itinerary["stops"], 1);
+        assert_eq!(itinerary["totalPrice"].as_f64(), Some(358.0));
+        // 08:00 in New York to 16:15 in Seattle, three hours behind
+        assert_eq!(itinerary["totalDurationMinutes"], 675);
+        assert_eq!(itinerary["layovers"][0]["airport"], "LAX");
+        assert_eq!(itinerary["layovers"][0]["durationMinutes"], 150);
+
+        let direct = "{ searchItineraries(origin: \"NYC\", destination: \"SEA\", dates: [], maxStops: 0) { stops } }";
+        let response = bot_schema.execute(Request::new(direct)).await.data;
+        assert!(response.into_json().unwrap()["searchItineraries"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_trips_round_trip_booking() {
+        let (pool, schema, bot_schema) = setup_schema().await;
         sqlx::query(
-            r#"CREATE TABLE bot_intents (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                agent_type TEXT NOT NULL,
-                confidence REAL NOT NULL,
-                intent_type TEXT NOT NULL,
-                query_params TEXT,
-                reason TEXT,
-                additional_context TEXT,
-                recorded_time TEXT NOT NULL
-            );"#,
+            r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price) VALUES
+               ('LAX','NYC','2025-06-05T09:00:00-07:00','2025-06-05T17:00:00-04:00',210.0),
+               ('LAX','NYC','2025-06-06T09:00:00-07:00','2025-06-06T17:00:00-04:00',180.0);"#,
         )
         .execute(&pool)
         .await
         .unwrap();
 
+        let query = r#"{ searchTrips(slices: [
+            { origin: "NYC", destination: "LAX", date: "2025-06-01" },
+            { origin: "LAX", destination: "NYC", date: "2025-06-05..2025-06-06" }
+        ]) { tripId tripType totalPrice flights { id } } }"#;
+        let response = bot_schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let trips = response.data.into_json().unwrap()["searchTrips"].as_array().unwrap().clone();
+        assert_eq!(trips.len(), 2);
+        assert_eq!(trips[0]["tripType"], "ROUND_TRIP");
+        assert_eq!(trips[0]["totalPrice"].as_f64(), Some(379.0));
+        let trip_id = trips[0]["tripId"].as_str().unwrap().to_string();
+
+        let mutation = format!(
+            "mutation {{ bookFlight(passengers: [{{ firstName: \"Jane\", lastName: \"Doe\" }}], payment: \"tok_test_visa\", tripId: \"{}\") {{ bookingId totalPrice segments {{ origin }} }} }}",
+            trip_id
+        );
+        let response = schema.execute(Request::new(mutation)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let booking = response.data.into_json().unwrap()["bookFlight"].clone();
+        assert_eq!(booking["totalPrice"].as_f64(), Some(379.0));
+        assert_eq!(booking["segments"][1]["origin"], "LAX");
+    }
+
+    #[tokio::test]
+    async fn test_book_flight_enforces_inventory() {
+        let (pool, schema, bot_schema) = setup_schema().await;
+        sqlx::query("UPDATE seat_inventory SET capacity = 1 WHERE flight_id = 1 AND cabin = 'BUSINESS'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let search = "{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: []) { seatsRemaining(cabin: BUSINESS) } }";
+        let response = schema.execute(Request::new(search)).await.data;
+        assert_eq!(response.into_json().unwrap()["searchFlights"][0]["seatsRemaining"], 1);
+
+        let book = "mutation { bookFlight(passengers: [{ firstName: \"Jane\", lastName: \"Doe\" }], payment: \"tok_test_visa\", flightId: 1, cabin: BUSINESS) { bookingId cabin } }";
+        let response = bot_schema.execute(Request::new(book)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let response = schema.execute(Request::new(book)).await;
+        let error = &response.errors[0];
+        assert_eq!(error.extensions.as_ref().unwrap().get("code"), Some(&async_graphql::Value::from("SOLD_OUT")));
+
+        let missing = "mutation { bookFlight(passengers: [{ firstName: \"Jane\", lastName: \"Doe\" }], payment: \"tok_test_visa\", flightId: 99) { bookingId } }";
+        let response = schema.execute(Request::new(missing)).await;
+        assert_eq!(response.errors[0].extensions.as_ref().unwrap().get("code"), Some(&async_graphql::Value::from("FLIGHT_NOT_FOUND")));
+
+        let (bookings,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM bookings").fetch_one(&pool).await.unwrap();
+        assert_eq!(bookings, 1);
+    }
+
+    #[tokio::test]
+    async fn test_seat_selection_and_explanation() {
+        let (_pool, schema, bot_schema) = setup_schema().await;
+
+        let hold = "mutation { holdSeat(flightId: 1, seat: \"12a\") { seat holdToken } }";
+        let response = bot_schema.execute(Request::new(hold)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let hold = response.data.into_json().unwrap()["holdSeat"].clone();
+        assert_eq!(hold["seat"], "12A");
+        let token = hold["holdToken"].as_str().unwrap().to_string();
+
+        let map = "{ seatMap(flightId: 1) { aircraftCode rows { row exitRow seats { seat status } } } }";
+        let response = schema.execute(Request::new(map)).await.data.into_json().unwrap();
+        let rows = response["seatMap"]["rows"].as_array().unwrap();
+        let row12 = rows.iter().find(|r| r["row"] == 12).unwrap();
+        assert_eq!(row12["exitRow"], true);
+        assert_eq!(row12["seats"][0]["status"], "HELD");
+
+        // Someone else can't take a held seat
+        let steal = "mutation { bookFlight(passengers: [{ firstName: \"Joe\", lastName: \"Doe\" }], payment: \"tok_test_visa\", flightId: 1, seats: [{ flightId: 1, seat: \"12A\" }]) { bookingId } }";
+        let response = schema.execute(Request::new(steal)).await;
+        assert_eq!(response.errors[0].extensions.as_ref().unwrap().get("code"), Some(&async_graphql::Value::from("SEAT_UNAVAILABLE")));
+
+        let book = format!(
+            "mutation {{ bookFlight(passengers: [{{ firstName: \"Jane\", lastName: \"Doe\" }}], payment: \"tok_test_visa\", flightId: 1, seats: [{{ flightId: 1, seat: \"12A\", holdToken: \"{}\" }}]) {{ bookingId seats {{ seat }} }} }}",
+            token
+        );
+        let response = bot_schema.execute(Request::new(book)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let booking = response.data.into_json().unwrap()["bookFlight"].clone();
+        assert_eq!(booking["seats"][0]["seat"], "12A");
+
+        let explain = format!(
+            "{{ requestExplanation(flightId: 1, bookingId: {}) {{ seatDetails {{ seat cabin exitRow pitchInches hasWifi }} }} }}",
+            booking["bookingId"]
+        );
+        let response = bot_schema.execute(Request::new(explain)).await.data.into_json().unwrap();
+        let details = &response["requestExplanation"]["seatDetails"];
+        assert_eq!(details["seat"], "12A");
+        assert_eq!(details["cabin"], "ECONOMY");
+        assert_eq!(details["exitRow"], true);
+        assert_eq!(details["pitchInches"].as_f64(), Some(31.0));
+    }
+
+    #[tokio::test]
+    async fn test_book_flight_with_structured_passengers() {
+        let (pool, schema, bot_schema) = setup_schema().await;
+
+        let book = r#"mutation { bookFlight(payment: "tok_test_visa", flightId: 1, passengers: [
+            { firstName: "Jane", lastName: "Doe", contact: { email: "jane@example.com" }, loyaltyNumber: "AA123",
+              travelDocument: { documentType: PASSPORT, number: "X1234567", issuingCountry: "us", expiryDate: "2030-01-01" } },
+            { firstName: "Sam", lastName: "Doe", dateOfBirth: "2024-03-01", passengerType: INFANT }
+        ]) { bookingId } }"#;
+        let response = schema.execute(Request::new(book)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let booking_id = response.data.into_json().unwrap()["bookFlight"]["bookingId"].as_i64().unwrap();
+
+        // The infant travels on a lap, so only one seat is taken
+        let (sold,): (i64,) = sqlx::query_as("SELECT seats_sold FROM seat_inventory WHERE flight_id = 1 AND cabin = 'ECONOMY'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(sold, 1);
+
+        let query = format!(
+            "{{ getBooking(id: {}) {{ passengers {{ firstName passengerType contact {{ email }} travelDocument {{ numberLast4 issuingCountry }} }} }} }}",
+            booking_id
+        );
+        let response = schema.execute(Request::new(query)).await.data.into_json().unwrap();
+        let passengers = response["getBooking"]["passengers"].as_array().unwrap().clone();
+        assert_eq!(passengers.len(), 2);
+        assert_eq!(passengers[0]["contact"]["email"], "jane@example.com");
+        assert_eq!(passengers[0]["travelDocument"]["numberLast4"], "4567");
+        assert_eq!(passengers[0]["travelDocument"]["issuingCountry"], "US");
+        assert_eq!(passengers[1]["passengerType"], "INFANT");
+
+        let structured = format!("{{ getStructuredBooking(id: {}) }}", booking_id);
+        let response = bot_schema.execute(Request::new(structured)).await.data.into_json().unwrap();
+        assert_eq!(response["getStructuredBooking"]["booking"]["passengers"][1]["passenger_type"], "INFANT");
+
+        // A child's date of birth must match the passenger type
+        let invalid = r#"mutation { bookFlight(payment: "tok_test_visa", flightId: 1, passengers: [
+            { firstName: "Jane", lastName: "Doe" },
+            { firstName: "Kid", lastName: "Doe", dateOfBirth: "2010-01-01", passengerType: CHILD }
+        ]) { bookingId } }"#;
+        let response = bot_schema.execute(Request::new(invalid)).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("INVALID_PASSENGER")));
+        assert_eq!(extensions.get("field"), Some(&async_graphql::Value::from("passengerType")));
+    }
+
+    #[tokio::test]
+    async fn test_tokenized_payment_booking() {
+        let (pool, schema, bot_schema) = setup_schema().await;
+
+        let tokenize = r#"mutation { tokenizePayment(card: { number: "4242 4242 4242 4242", expiryMonth: 12, expiryYear: 2099, cvc: "123" }) { token brand last4 } }"#;
+        let response = schema.execute(Request::new(tokenize)).await.data.into_json().unwrap();
+        let card = response["tokenizePayment"].clone();
+        assert_eq!(card["brand"], "VISA");
+        assert_eq!(card["last4"], "4242");
+        let token = card["token"].as_str().unwrap().to_string();
+
+        // Tokens issued by one API are accepted by the other
+        let book = format!(
+            "mutation {{ bookFlight(passengers: [{{ firstName: \"Jane\", lastName: \"Doe\" }}, {{ firstName: \"John\", lastName: \"Doe\" }}], payment: \"{}\", flightId: 1) {{ bookingId totalPrice payment {{ brand last4 status amount }} }} }}",
+            token
+        );
+        let response = bot_schema.execute(Request::new(book)).await.data.into_json().unwrap();
+        let confirmation = response["bookFlight"].clone();
+        assert_eq!(confirmation["totalPrice"], 398.0);
+        assert_eq!(confirmation["payment"]["status"], "CAPTURED");
+        assert_eq!(confirmation["payment"]["amount"], 398.0);
+        let booking_id = confirmation["bookingId"].as_i64().unwrap();
+
+        let query = format!("{{ getBooking(id: {}) {{ payment {{ brand last4 status }} }} }}", booking_id);
+        let response = schema.execute(Request::new(query.clone())).await.data.into_json().unwrap();
+        assert_eq!(response["getBooking"]["payment"]["last4"], "4242");
+
+        // Other sessions cannot read the booking, even with the book scope
+        let stranger = BotInfo { session_id: "other-session".to_string(), ..test_caller() };
+        let response = schema.execute(Request::new(query).data(stranger)).await;
+        let code = response.errors[0].extensions.as_ref().unwrap().get("code");
+        assert_eq!(code, Some(&async_graphql::Value::from("BOOKING_NOT_FOUND")));
+
+        let (stored_token, last4): (String, String) =
+            sqlx::query_as("SELECT payment_token, card_last4 FROM bookings WHERE id = ?")
+                .bind(booking_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(stored_token, token);
+        assert_eq!(last4, "4242");
+
+        // Declined and invalid cards leave no booking behind
+        let tokenize = r#"mutation { tokenizePayment(card: { number: "4000000000000002", expiryMonth: 12, expiryYear: 2099 }) { token } }"#;
+        let response = schema.execute(Request::new(tokenize)).await.data.into_json().unwrap();
+        let declined = format!(
+            "mutation {{ bookFlight(passengers: [{{ firstName: \"Jane\", lastName: \"Doe\" }}], payment: \"{}\", flightId: 1) {{ bookingId }} }}",
+            response["tokenizePayment"]["token"].as_str().unwrap()
+        );
+        let response = schema.execute(Request::new(declined)).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("PAYMENT_DECLINED")));
+
+        // Raw card numbers have no expiry date and are not accepted as payment
+        for invalid in ["1234", "4242424242424242", "tok_unknown"] {
+            let book = format!(
+                "mutation {{ bookFlight(passengers: [{{ firstName: \"Jane\", lastName: \"Doe\" }}], payment: \"{}\", flightId: 1) {{ bookingId }} }}",
+                invalid
+            );
+            let response = schema.execute(Request::new(book)).await;
+            let extensions = response.errors[0].extensions.as_ref().unwrap();
+            assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("INVALID_PAYMENT_METHOD")));
+        }
+
+        // Tokens are kept in the database, so a new processor still knows them
+        let restarted = MockPaymentProcessor::new(pool.clone());
+        assert_eq!(restarted.payment_method(&token).await.unwrap().last4, "4242");
+
+        let (bookings,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM bookings").fetch_one(&pool).await.unwrap();
+        assert_eq!(bookings, 1);
+    }
+
+    #[tokio::test]
+    async fn test_migrations_track_schema_version() {
+        let pool = db::connect("sqlite::memory:").await.unwrap();
+        let fresh = db::schema_version(&pool).await.unwrap();
+        assert_eq!(fresh.current, None);
+        assert!(!fresh.is_current());
+
+        db::migrate(&pool).await.unwrap();
+        // Re-running is a no-op once every migration is applied
+        db::migrate(&pool).await.unwrap();
+        let migrated = db::schema_version(&pool).await.unwrap();
+        assert!(migrated.is_current());
+        assert_eq!(migrated.latest, db::MIGRATOR.iter().last().unwrap().version);
+
+        // Reference data comes with the migrations
+        let (aircraft,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM aircraft_configs").fetch_one(&pool).await.unwrap();
+        assert_eq!(aircraft, 2);
+
+        // Reading the version of a missing database does not create it
+        let missing = std::env::temp_dir().join(format!("bot-shop-{}.db", uuid::Uuid::new_v4().simple()));
+        assert!(db::connect_read_only(&format!("sqlite://{}", missing.display())).await.is_err());
+        assert!(!missing.exists());
+    }
+
+    #[tokio::test]
+    async fn test_submit_intent_persists_and_links() {
+        let (pool, schema, bot_schema) = setup_schema().await;
+        let book = "mutation { bookFlight(passengers: [{ firstName: \"Jane\", lastName: \"Doe\" }], payment: \"tok_test_visa\", flightId: 1) { bookingId } }";
+        let response = schema.execute(Request::new(book)).await.data.into_json().unwrap();
+        let booking_id = response["bookFlight"]["bookingId"].as_i64().unwrap();
+
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("user-agent", axum::http::HeaderValue::from_static("curl/8.0"));
+        headers.insert("x-user-agent-type", axum::http::HeaderValue::from_static("bot"));
+        let info = bot_detection::score_request(&headers, &DetectionConfig::default(), &Default::default());
+
+        let submit = format!(
+            "mutation {{ submitIntent(intent: {{ intentType: \"purchase\", reason: \"cheapest\", bookingId: {}, searchId: \"TRIP-1\" }}) }}",
+            booking_id
+        );
+        let response = bot_schema.execute(Request::new(submit).data(info.clone())).await.data.into_json().unwrap();
+        let intent_id = response["submitIntent"].as_i64().unwrap();
+
+        let stored = intents::list(&pool, None).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].id, intent_id);
+        assert_eq!(stored[0].agent_type, "bot");
+        assert_eq!(stored[0].confidence, info.confidence_score);
+        assert_eq!(stored[0].booking_id, Some(booking_id));
+        assert_eq!(stored[0].search_id.as_deref(), Some("TRIP-1"));
+
+        let unknown = "mutation { submitIntent(intent: { intentType: \"purchase\", bookingId: 999 }) }";
+        let response = bot_schema.execute(Request::new(unknown)).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("BOOKING_NOT_FOUND")));
+
+        // REST bodies use the GraphQL field names; the old snake_case ones still parse
+        let rest: BotIntent = serde_json::from_str(r#"{ "intentType": "search", "searchId": "TRIP-2" }"#).unwrap();
+        assert_eq!(rest.search_id.as_deref(), Some("TRIP-2"));
+        let legacy: BotIntent = serde_json::from_str(r#"{ "intent_type": "search", "booking_id": 3 }"#).unwrap();
+        assert_eq!((legacy.intent_type.as_str(), legacy.booking_id), ("search", Some(3)));
+
+        // Database failures are internal errors, not the client's fault
+        pool.close().await;
+        let err = intents::record(&pool, Some(&info), &rest).await.unwrap_err();
+        let code = err.extensions.as_ref().and_then(|e| e.get("code")).cloned();
+        assert_eq!(code, Some(async_graphql::Value::from("INTERNAL_ERROR")));
+    }
+
+    #[tokio::test]
+    async fn test_behavior_metrics_aggregate_and_rescore() {
+        use crate::bot_detection::Observations;
+
+        let (pool, _schema, bot_schema) = setup_schema().await;
+        let config = DetectionConfig::default();
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("user-agent", axum::http::HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64) Chrome/124.0 Safari/537.36"));
+        let mut info = bot_detection::score_request(&headers, &config, &Default::default());
+        let sessions = SessionStore::default();
+        info.session_id = sessions.session_id(&axum::http::HeaderMap::new()).0;
+        sessions.begin(&info.session_id, "GET", "/", info.confidence_score);
+        let baseline = info.confidence_score;
+
+        for entropy in [0.9, 0.7] {
+            let report = serde_json::json!({
+                "confidenceScore": 0.8,
+                "signals": { "mouseEntropy": entropy, "typingPattern": 0.5, "navigationPattern": 0.9, "formFilling": 0.9 },
+                "sampleCounts": { "mouseMovements": 10, "keyPresses": 4, "clicks": 2, "scrolls": 1, "formInteractions": 3 },
+                "viewport": { "width": 1280 }
+            });
+            behavior::record(&pool, &sessions, Some(&info), &report).await.unwrap();
+        }
+        let invalid = serde_json::json!({ "signals": { "mouseEntropy": 3.0 } });
+        assert!(behavior::record(&pool, &sessions, Some(&info), &invalid).await.is_err());
+
+        // Reports for another session, or for a session id the client made up, are refused
+        let other = serde_json::json!({ "sessionId": "someone-elses-session", "signals": { "mouseEntropy": 0.1 } });
+        assert!(behavior::record(&pool, &sessions, Some(&info), &other).await.is_err());
+        let chosen = BotInfo { session_id: "client-chosen-1".to_string(), ..info.clone() };
+        let report = serde_json::json!({ "signals": { "mouseEntropy": 0.1 } });
+        assert!(behavior::record(&pool, &sessions, Some(&chosen), &report).await.is_err());
+
+        let query = "{ behaviorAggregate { reports avgMouseEntropy mouseMovements behaviorScore } }";
+        let response = bot_schema.execute(Request::new(query).data(info.clone())).await.data.into_json().unwrap();
+        let aggregate = response["behaviorAggregate"].clone();
+        assert_eq!(aggregate["reports"], 2);
+        assert!((aggregate["avgMouseEntropy"].as_f64().unwrap() - 0.8).abs() < 1e-9);
+        assert_eq!(aggregate["mouseMovements"], 20);
+        let behavior_score = aggregate["behaviorScore"].as_f64().unwrap();
+        // The score is kept with the session as reports arrive, not queried per request
+        assert_eq!(sessions.behavior_score(&info.session_id), Some(behavior_score));
+
+        // Other sessions' behavior needs the read-behavior scope
+        let others = ["{ behaviorAggregate(sessionId: \"client-chosen-1\") { reports } }", "{ behaviorAggregates { sessionId } }"];
+        for query in others {
+            let response = bot_schema.execute(Request::new(query).data(info.clone())).await;
+            let code = response.errors[0].extensions.as_ref().unwrap().get("code").cloned();
+            assert_eq!(code, Some(async_graphql::Value::from("UNAUTHORIZED")), "{}", query);
+        }
+
+        // Later requests of the session are re-scored with the stored reports
+        let observations = Observations { behavior_score: Some(behavior_score), ..Default::default() };
+        let rescored = bot_detection::score_request(&headers, &config, &observations);
+        let signal = rescored.signals.iter().find(|s| s.name == "behavior").unwrap();
+        assert_eq!(signal.weight, config.weights.behavior);
+        assert!(rescored.confidence_score > baseline);
+    }
+
+    #[tokio::test]
+    async fn test_session_tracking() {
+        use crate::sessions::{self, SessionSource};
+        use axum::http::{HeaderMap, HeaderValue};
+
+        let store = SessionStore::default();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-session-id", HeaderValue::from_static("bad id;"));
+        let (issued, source) = store.session_id(&headers);
+        assert_eq!(source, SessionSource::Issued);
+        headers.clear();
+        let cookie = format!("theme=dark; bot_shop_session={}", issued);
+        headers.insert("cookie", HeaderValue::from_str(&cookie).unwrap());
+        assert_eq!(store.session_id(&headers), (issued.clone(), SessionSource::Cookie));
+        // A well-formed header id the server did not issue is ignored
+        headers.insert("x-session-id", HeaderValue::from_static("header-session-1"));
+        assert_eq!(store.session_id(&headers), (issued.clone(), SessionSource::Cookie));
+        let other = SessionStore::default().session_id(&HeaderMap::new()).0;
+        headers.insert("x-session-id", HeaderValue::from_str(&other).unwrap());
+        assert_eq!(store.session_id(&headers), (issued.clone(), SessionSource::Cookie));
+        headers.insert("x-session-id", HeaderValue::from_str(&issued).unwrap());
+        assert_eq!(store.session_id(&headers), (issued.clone(), SessionSource::Header));
+        // Cookies the server did not issue are replaced
+        headers.clear();
+        headers.insert("cookie", HeaderValue::from_static("bot_shop_session=cookie-session-1"));
+        assert_eq!(store.session_id(&headers).1, SessionSource::Issued);
+
+        // Only ids this store issued carry a valid tag
+        assert!(store.issued_here(&issued));
+        assert!(!store.issued_here("header-session-1"));
+        assert!(!SessionStore::default().issued_here(&issued));
+        let mut forged = issued.clone();
+        forged.replace_range(..1, if issued.starts_with('0') { "1" } else { "0" });
+        assert!(!store.issued_here(&forged));
+
+        let search = store.begin("header-session-1", "POST", "/graphql", 0.2);
+        store.finish("header-session-1", search, vec!["searchFlights".to_string()], false);
+        let intent = store.begin("header-session-1", "POST", "/bot/graphql", 0.8);
+        store.finish("header-session-1", intent, vec!["submitIntent".to_string()], true);
+
+        let (pool, _schema, bot_schema) = setup_schema().await;
+        let session = store.snapshot("header-session-1").unwrap();
+        let mut info = bot_detection::score_request(&headers, &DetectionConfig::default(), &Default::default());
+        info.session_id = session.id.clone();
+        let query = "{ currentSession { requestCount cumulativeConfidence history { fields botApi } botEndpoints { endpoint count } } }";
+        let response = bot_schema.execute(Request::new(query).data(session).data(info.clone())).await.data.into_json().unwrap();
+        let current = response["currentSession"].clone();
+        assert_eq!(current["requestCount"], 2);
+        assert!((current["cumulativeConfidence"].as_f64().unwrap() - 0.5).abs() < 1e-6);
+        assert_eq!(current["history"][0]["fields"][0], "searchFlights");
+        assert_eq!(current["botEndpoints"][0]["endpoint"], "/bot/graphql");
+        assert_eq!(current["botEndpoints"][0]["count"], 1);
+
+        // Intents are linked to the session they came from
+        let submit = "mutation { submitIntent(intent: { intentType: \"search\" }) }";
+        bot_schema.execute(Request::new(submit).data(info)).await;
+        let stored = intents::list(&pool, None).await.unwrap();
+        assert_eq!(stored[0].session_id.as_deref(), Some("header-session-1"));
+
+        // A full store drops its least recently used session
+        let store = SessionStore::default();
+        store.begin("first-session", "GET", "/", 0.1);
+        store.begin("second-session", "GET", "/", 0.1);
+        for i in 0..sessions::MAX_SESSIONS - 2 {
+            store.begin(&format!("filler-session-{}", i), "POST", "/graphql", 0.1);
+        }
+        store.begin("first-session", "POST", "/graphql", 0.1);
+        store.begin("one-too-many", "POST", "/graphql", 0.1);
+        assert!(store.snapshot("first-session").is_some());
+        assert!(store.snapshot("second-session").is_none());
+        assert!(store.snapshot("one-too-many").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_intelligence_classification() {
+        use crate::bot_detection::{classify_intelligence, IntelligenceLevel};
+
+        assert_eq!(classify_intelligence(0.1, 0.2, 0), IntelligenceLevel::L0);
+        assert_eq!(classify_intelligence(0.6, 0.5, 0), IntelligenceLevel::L1);
+        assert_eq!(classify_intelligence(0.9, 0.8, 3), IntelligenceLevel::L2);
+
+        // A scripted session climbs to L2 as it keeps using bot-only fields
+        let store = SessionStore::default();
+        let mut level = IntelligenceLevel::L0;
+        for field in ["searchFlights", "requestExplanation", "offerInsights", "negotiateOffer"] {
+            let sequence = store.begin("agent-session", "POST", "/bot/graphql", 0.85);
+            store.finish("agent-session", sequence, vec![field.to_string()], true);
+            let session = store.snapshot("agent-session").unwrap();
+            assert!(session.intelligence_level >= level);
+            level = session.intelligence_level;
+        }
+        assert_eq!(level, IntelligenceLevel::L2);
+        assert_eq!(store.snapshot("agent-session").unwrap().bot_field_uses, 3);
+
+        // Resolvers adapt to the level, and intents record it
+        let (pool, _schema, bot_schema) = setup_schema().await;
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("user-agent", axum::http::HeaderValue::from_static("python-requests/2.31"));
+        let mut info = bot_detection::score_request(&headers, &DetectionConfig::default(), &Default::default());
+        // Explanations need credentials; a signature grants the agent's scopes
+        info.verified = true;
+        info.agent_scopes = AGENT_SCOPES.to_vec();
+        let query = "{ requestExplanation(flightId: 1) { structuredExplanation } }";
+        let response = bot_schema.execute(Request::new(query).data(info.clone())).await.data.into_json().unwrap();
+        assert!(response["requestExplanation"]["structuredExplanation"]["price_components"].is_null());
+
+        info.intelligence_level = IntelligenceLevel::L2;
+        let response = bot_schema.execute(Request::new(query).data(info.clone())).await.data.into_json().unwrap();
+        let explanation = response["requestExplanation"]["structuredExplanation"].clone();
+        assert_eq!(explanation["detail_level"], "L2");
+        // The base fare plus the four taxes on a flight out of NYC
+        assert_eq!(explanation["price_components"].as_array().unwrap().len(), 5);
+
+        let submit = "mutation { submitIntent(intent: { intentType: \"compare\" }) }";
+        bot_schema.execute(Request::new(submit).data(info)).await;
+        let stored = intents::list(&pool, None).await.unwrap();
+        assert_eq!(stored[0].intelligence_level.as_deref(), Some("L2"));
+    }
+
+    #[tokio::test]
+    async fn test_graphql_query_tracking_and_limits() {
+        use crate::bot_detection::Observations;
+        use crate::query_tracking::QueryTracking;
+
+        let (pool, _schema, _bot) = setup_schema().await;
+        let sessions = SessionStore::default();
         let schema = Schema::build(QueryRoot, MutationRoot, async_graphql::EmptySubscription)
-            .data(pool.clone())
+            .data(pool)
+            .extension(QueryTracking::new(sessions.clone()))
+            .limit_depth(3)
+            .limit_complexity(50)
             .finish();
-        let bot_schema = Schema::build(BotQueryRoot, BotMutationRoot, async_graphql::EmptySubscription)
-            .data(pool.clone())
-            .finish();
-        (pool, schema, bot_schema)
+
+        let config = DetectionConfig::default();
+        let mut info = bot_detection::score_request(&axum::http::HeaderMap::new(), &config, &Default::default());
+        info.session_id = "graphql-session".to_string();
+        sessions.begin(&info.session_id, "POST", "/graphql", info.confidence_score);
+
+        let search = "{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: []) { id price } }";
+        schema.execute(Request::new(search).data(info.clone())).await;
+        let pattern = sessions.query_pattern(&info.session_id).unwrap();
+        assert_eq!(pattern.score, 0.0);
+
+        // Scripts replay the exact same query
+        for _ in 0..2 {
+            schema.execute(Request::new(search).data(info.clone())).await;
+        }
+        let pattern = sessions.query_pattern(&info.session_id).unwrap();
+        assert_eq!(pattern.score, 0.9, "{}", pattern.detail);
+        let session = sessions.snapshot(&info.session_id).unwrap();
+        assert_eq!(session.queries.len(), 3);
+        assert_eq!(session.queries[0].depth, 2);
+        assert_eq!(session.queries[0].fields, vec!["id", "price", "searchFlights"]);
+
+        // The pattern feeds the session's next detection score
+        let observations = Observations { query_pattern: Some(pattern), ..Default::default() };
+        let rescored = bot_detection::score_request(&axum::http::HeaderMap::new(), &config, &observations);
+        let signal = rescored.signals.iter().find(|s| s.name == "graphql").unwrap();
+        assert_eq!((signal.score, signal.weight), (0.9, config.weights.graphql));
+
+        // Depth limits are enforced
+        let deep = "{ getBooking(id: 1) { passengers { contact { email } } } }";
+        let response = schema.execute(Request::new(deep).data(info.clone())).await;
+        assert!(response.errors[0].message.contains("nested too deep"), "{:?}", response.errors);
+
+        // and the rejected query still lands in the session, with an estimated depth
+        let session = sessions.snapshot(&info.session_id).unwrap();
+        let rejected = session.queries.last().unwrap();
+        assert_eq!(rejected.depth, 4);
+        assert_eq!(rejected.rejected.as_deref(), Some("Query is nested too deep."));
+        assert_eq!(session.queries.iter().filter(|q| q.rejected.is_none()).count(), 3);
     }
 
     #[tokio::test]
-    async fn test_search_flights() {
-        let (_pool, schema, _bot) = setup_schema().await;
-        let request = Request::new("{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: []) { id } }");
-        let response = schema.execute(request).await.data;
-        let list = response.into_json().unwrap()["searchFlights"].as_array().unwrap().clone();
-        assert!(!list.is_empty());
+    async fn test_signed_agent_requests() {
+        use crate::agents::{self, NonceCache, SignatureError};
+        use axum::http::{HeaderMap, HeaderValue, Method};
+        use base64::Engine;
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let (pool, _schema, bot_schema) = setup_schema().await;
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = base64::engine::general_purpose::STANDARD.encode(key.verifying_key().as_bytes());
+        let agent = agents::register(&pool, "fare-watcher", &public_key).await.unwrap();
+        assert!(agents::register(&pool, "copycat", &public_key).await.is_err());
+
+        let body = br#"{"query":"{ detectionReport { verified } }"}"#;
+        let date = chrono::Utc::now().to_rfc2822();
+        let digest = agents::content_digest(body);
+        let params = format!(
+            "(\"@method\" \"@path\" \"date\" \"content-digest\");keyid=\"{}\";nonce=\"n-1\";alg=\"ed25519\"",
+            agent.agent_id
+        );
+        let base = format!(
+            "\"@method\": POST\n\"@path\": /bot/graphql\n\"date\": {}\n\"content-digest\": {}\n\"@signature-params\": {}",
+            date, digest, params
+        );
+        let signature = base64::engine::general_purpose::STANDARD.encode(key.sign(base.as_bytes()).to_bytes());
+        let mut headers = HeaderMap::new();
+        headers.insert("date", HeaderValue::from_str(&date).unwrap());
+        headers.insert("content-digest", HeaderValue::from_str(&digest).unwrap());
+        headers.insert("signature-input", HeaderValue::from_str(&format!("sig1={}", params)).unwrap());
+        headers.insert("signature", HeaderValue::from_str(&format!("sig1=:{}:", signature)).unwrap());
+
+        let nonces = NonceCache::default();
+        let tampered = agents::verify_request(&pool, &nonces, &Method::POST, "/bot/graphql", &headers, b"{}").await;
+        assert_eq!(tampered, Err(SignatureError::DigestMismatch));
+        let other_path = agents::verify_request(&pool, &nonces, &Method::POST, "/graphql", &headers, body).await;
+        assert_eq!(other_path, Err(SignatureError::Invalid));
+        let verified = agents::verify_request(&pool, &nonces, &Method::POST, "/bot/graphql", &headers, body).await;
+        let signed = verified.unwrap().unwrap();
+        assert_eq!(signed.agent_id, agent.agent_id);
+        assert_eq!(signed.scopes, AGENT_SCOPES);
+        // a captured request cannot be sent again while its date is accepted
+        let replayed = agents::verify_request(&pool, &nonces, &Method::POST, "/bot/graphql", &headers, body).await;
+        assert_eq!(replayed, Err(SignatureError::Replayed));
+        let unsigned = agents::verify_request(&pool, &nonces, &Method::POST, "/graphql", &HeaderMap::new(), body).await;
+        assert_eq!(unsigned, Ok(None));
+
+        // signatures without a nonce are refused
+        let mut no_nonce = headers.clone();
+        let input = format!("sig1={}", params.replace(";nonce=\"n-1\"", ""));
+        no_nonce.insert("signature-input", HeaderValue::from_str(&input).unwrap());
+        let refused = agents::verify_request(&pool, &nonces, &Method::POST, "/bot/graphql", &no_nonce, body).await;
+        assert!(matches!(refused, Err(SignatureError::Malformed(_))), "{:?}", refused);
+
+        // negotiateOffer needs credentials, which a signature provides
+        let mut info = bot_detection::score_request(&HeaderMap::new(), &DetectionConfig::default(), &Default::default());
+        let negotiate = "mutation { negotiateOffer(flightId: 1) { status } }";
+        let response = bot_schema.execute(Request::new(negotiate).data(info.clone())).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("UNAUTHORIZED")));
+
+        info.verified = true;
+        info.agent_id = Some(agent.agent_id);
+        info.agent_scopes = signed.scopes;
+        assert!(info.is_likely_bot());
+        let response = bot_schema.execute(Request::new(negotiate).data(info)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_scopes() {
+        use crate::agents;
+        use crate::api_keys::{self, ApiKeyError, ApiScope};
+        use axum::http::{HeaderMap, HeaderValue};
+        use base64::Engine;
+
+        let (pool, _schema, bot_schema) = setup_schema().await;
+        let public_key = |seed: u8| {
+            let key = ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+            base64::engine::general_purpose::STANDARD.encode(key.verifying_key().as_bytes())
+        };
+        let agent = agents::register(&pool, "fare-reader", &public_key(9)).await.unwrap();
+        let other = agents::register(&pool, "other", &public_key(10)).await.unwrap();
+
+        // Only signed requests can issue keys
+        let config = DetectionConfig::default();
+        let mut signed = bot_detection::score_request(&HeaderMap::new(), &config, &Default::default());
+        let issue = "mutation { issueApiKey(scopes: [SEARCH, EXPLAIN, READ_INTENTS], label: \"reader\") { keyId apiKey scopes } }";
+        let response = bot_schema.execute(Request::new(issue).data(signed.clone())).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("AGENT_NOT_VERIFIED")));
+
+        signed.verified = true;
+        signed.agent_id = Some(agent.agent_id.clone());
+        signed.agent_scopes = AGENT_SCOPES.to_vec();
+        let response = bot_schema.execute(Request::new(issue).data(signed.clone())).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let issued = response.data.into_json().unwrap()["issueApiKey"].clone();
+        let api_key = issued["apiKey"].as_str().unwrap();
+        assert!(api_key.starts_with("bsk_"));
+        assert_eq!(issued["scopes"], serde_json::json!(["SEARCH", "EXPLAIN", "READ_INTENTS"]));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_str(&format!("Bearer {}", api_key)).unwrap());
+        let presented = api_keys::presented_key(&headers).unwrap();
+        let grant = api_keys::validate(&pool, presented).await.unwrap().unwrap();
+        assert_eq!(grant.agent_id, agent.agent_id);
+        assert_eq!(api_keys::validate(&pool, "bsk_unknown").await.unwrap().unwrap_err(), ApiKeyError::Unknown);
+
+        // A key limits requests to its scopes
+        let mut keyed = bot_detection::score_request(&headers, &config, &Default::default());
+        keyed.agent_id = Some(grant.agent_id.clone());
+        keyed.api_key = Some(grant);
+        assert!(keyed.has_scope(ApiScope::Explain) && !keyed.has_scope(ApiScope::Book));
+        let explain = "{ requestExplanation(flightId: 1) { flightId } }";
+        let response = bot_schema.execute(Request::new(explain).data(keyed.clone())).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let negotiate = "mutation { negotiateOffer(flightId: 1) { status } }";
+        let response = bot_schema.execute(Request::new(negotiate).data(keyed.clone())).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("INSUFFICIENT_SCOPE")));
+        assert_eq!(extensions.get("scope"), Some(&async_graphql::Value::from("negotiate")));
+
+        // Signed agents have their grants, which leave out read-behavior until an operator adds it
+        assert!(signed.has_scope(ApiScope::Negotiate) && !signed.has_scope(ApiScope::ReadBehavior));
+        let read_behavior = "mutation { issueApiKey(scopes: [READ_BEHAVIOR]) { keyId } }";
+        let response = bot_schema.execute(Request::new(read_behavior).data(signed.clone())).await;
+        assert!(response.errors[0].message.contains("not granted"), "{:?}", response.errors);
+        sqlx::query("UPDATE agents SET scopes = 'search,explain,read-behavior' WHERE id = ?")
+            .bind(&agent.agent_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let response = bot_schema.execute(Request::new(read_behavior).data(signed)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        // and keys lose scopes their agent no longer has
+        let grant = api_keys::validate(&pool, api_key).await.unwrap().unwrap();
+        assert_eq!(grant.scopes, vec![ApiScope::Search, ApiScope::Explain]);
+
+        // Anonymous callers keep the public scopes only
+        let anonymous = bot_detection::score_request(&HeaderMap::new(), &config, &Default::default());
+        let search = "{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: []) { id } }";
+        assert!(bot_schema.execute(Request::new(search).data(anonymous.clone())).await.errors.is_empty());
+        let response = bot_schema.execute(Request::new(explain).data(anonymous.clone())).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("UNAUTHORIZED")));
+        // and requests without detection data are refused outright
+        let bare = Schema::build(QueryRoot, MutationRoot, async_graphql::EmptySubscription).data(pool.clone()).finish();
+        let response = bare.execute(Request::new(search)).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("UNAUTHORIZED")));
+
+        // Intents are listed per agent
+        let submit = "mutation { submitIntent(intent: { intentType: \"search\" }) }";
+        bot_schema.execute(Request::new(submit).data(keyed)).await;
+        bot_schema.execute(Request::new(submit).data(anonymous)).await;
+        let own = intents::list(&pool, Some(&agent.agent_id)).await.unwrap();
+        assert_eq!(own.len(), 1);
+        assert!(intents::list(&pool, Some(&other.agent_id)).await.unwrap().is_empty());
+
+        // Revoked keys stop working
+        let key_id = issued["keyId"].as_str().unwrap();
+        assert!(api_keys::revoke(&pool, &agent.agent_id, key_id).await.unwrap());
+        assert_eq!(api_keys::validate(&pool, api_key).await.unwrap().unwrap_err(), ApiKeyError::Revoked);
+        assert_eq!(api_keys::list(&pool, &agent.agent_id).await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_rate_limiting() {
+        use crate::rate_limit::{self, RateLimiter, TrafficClass};
+        use std::time::{Duration, Instant};
+
+        let config = Config::from_toml("[rate_limit.bot]\nburst = 3\nper_minute = 60").unwrap();
+        config.validate().unwrap();
+        let sessions = SessionStore::default();
+        let limiter = RateLimiter::new(config.rate_limit.clone(), sessions.clone());
+        let mut info = bot_detection::score_request(&Default::default(), &DetectionConfig::default(), &Default::default());
+        let (session_id, _) = sessions.session_id(&Default::default());
+        info.session_id = session_id.clone();
+        info.client_ip = "10.0.0.1".to_string();
+        let keys = rate_limit::bucket_keys(&info, &sessions);
+        assert_eq!(keys, vec!["ip:10.0.0.1".to_string(), format!("session:{}", session_id)]);
+        let start = Instant::now();
+
+        for remaining in [2, 1, 0] {
+            let decision = limiter.check(TrafficClass::Bot, &keys, start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+        let denied = limiter.check(TrafficClass::Bot, &keys, start);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after_secs, Some(1));
+        let headers = denied.headers();
+        assert_eq!(headers["retry-after"], "1");
+        assert_eq!(headers["ratelimit-limit"], "3");
+        assert_eq!(headers["ratelimit-policy"], "60;w=60;burst=3;policy=\"bot\"");
+
+        // A client-invented session gets no bucket of its own, and a fresh
+        // one from the same address still draws from the address's bucket
+        info.session_id = "session-b".to_string();
+        assert_eq!(rate_limit::bucket_keys(&info, &sessions), vec!["ip:10.0.0.1".to_string()]);
+        info.session_id = sessions.session_id(&Default::default()).0;
+        assert!(!limiter.check(TrafficClass::Bot, &rate_limit::bucket_keys(&info, &sessions), start).allowed);
+        // An issued session keeps its bucket when it moves to another address
+        info.session_id = session_id.clone();
+        info.client_ip = "10.0.0.3".to_string();
+        assert!(!limiter.check(TrafficClass::Bot, &rate_limit::bucket_keys(&info, &sessions), start).allowed);
+        info.client_ip = "10.0.0.1".to_string();
+        // and passing for a human does not refill them
+        assert!(!limiter.check(TrafficClass::Human, &keys, start).allowed);
+        // Buckets refill at the policy rate
+        assert!(limiter.check(TrafficClass::Bot, &keys, start + Duration::from_secs(1)).allowed);
+        // Humans have their own, larger policy
+        let human = limiter.check(TrafficClass::Human, &["ip:10.0.0.2".to_string()], start);
+        assert_eq!((human.allowed, human.policy.burst), (true, 60));
+
+        // Identified agents also draw from a bucket of their own
+        info.agent_id = Some("agent-a".to_string());
+        assert_eq!(rate_limit::bucket_keys(&info, &sessions)[2], "agent:agent-a");
+
+        let invalid = Config::from_toml("[rate_limit.agent]\nburst = 0\nper_minute = 10").unwrap();
+        assert!(invalid.validate().is_err());
     }
 
     #[tokio::test]
     async fn test_bot_info() {
-        let info = BotInfo { confidence_score: 0.6, agent_type: "bot".to_string(), request_start: std::time::Instant::now() };
+        let info = BotInfo {
+            confidence_score: 0.6,
+            agent_type: "bot".to_string(),
+            session_id: "test".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            intelligence_level: Default::default(),
+            verified: false,
+            agent_id: None,
+            agent_scopes: Vec::new(),
+            api_key: None,
+            bot_threshold: DetectionConfig::default().bot_threshold,
+            signals: Vec::new(),
+        };
         assert!(info.is_likely_bot());
+
+        // Declaring a bot type is advisory and does not outweigh a low score
+        let declared = BotInfo { confidence_score: 0.3, ..info };
+        assert!(!declared.is_likely_bot());
+    }
+
+    #[test]
+    fn test_server_side_bot_scoring() {
+        use crate::bot_detection::Observations;
+        use axum::http::{HeaderMap, HeaderValue};
+        use std::time::Duration;
+
+        fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+            let mut map = HeaderMap::new();
+            for (name, value) in pairs {
+                map.insert(*name, HeaderValue::from_static(value));
+            }
+            map
+        }
+        fn observed(since_last: Duration) -> Observations {
+            Observations { since_last: Some(since_last), ..Default::default() }
+        }
+        let config = DetectionConfig::default();
+
+        let browser = headers(&[
+            ("host", "localhost:8000"),
+            ("user-agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Safari/537.36"),
+            ("accept", "*/*"),
+            ("accept-language", "en-US"),
+            ("accept-encoding", "gzip"),
+            ("sec-fetch-mode", "cors"),
+            ("cookie", "theme=dark"),
+            // A bot-like hint alone does not make a browser a bot
+            ("x-bot-confidence", "0.9"),
+        ]);
+        let info = bot_detection::score_request(&browser, &config, &observed(Duration::from_secs(5)));
+        assert!(!info.is_likely_bot(), "browser scored {}", info.confidence_score);
+        assert_eq!(info.signals.len(), 8);
+        let order = |info: &BotInfo| info.signals.iter().find(|s| s.name == "header_order").unwrap().score;
+        assert_eq!(order(&info), 0.0);
+
+        // The same headers in an HTTP library's order score as unusual
+        let reordered = headers(&[
+            ("host", "localhost:8000"),
+            ("accept-encoding", "gzip"),
+            ("user-agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Safari/537.36"),
+            ("accept-language", "en-US"),
+            ("accept", "*/*"),
+            ("sec-fetch-mode", "cors"),
+            ("cookie", "theme=dark"),
+        ]);
+        let reordered_info = bot_detection::score_request(&reordered, &config, &observed(Duration::from_secs(5)));
+        assert_eq!(order(&reordered_info), 1.0);
+        assert!(reordered_info.confidence_score > info.confidence_score);
+
+        // Claiming to be human does not hide a scripted client
+        let script = headers(&[
+            ("user-agent", "python-requests/2.31"),
+            ("accept", "*/*"),
+            ("x-bot-confidence", "0.0"),
+            ("x-user-agent-type", "human"),
+        ]);
+        let info = bot_detection::score_request(&script, &config, &observed(Duration::from_millis(50)));
+        assert!(info.is_likely_bot(), "script scored {}", info.confidence_score);
+        let agent = info.signals.iter().find(|s| s.name == "user_agent").unwrap();
+        assert_eq!(agent.score, 1.0);
+        let timing = info.signals.iter().find(|s| s.name == "timing").unwrap();
+        assert_eq!(timing.score, 1.0);
+
+        // Forwarded addresses are only believed from trusted proxies
+        let forwarded = headers(&[("x-forwarded-for", "203.0.113.7, 10.0.0.2")]);
+        let proxy: std::net::IpAddr = "10.0.0.1".parse().unwrap();
+        let peer = Some(std::net::SocketAddr::new(proxy, 4000));
+        assert_eq!(bot_detection::client_ip(&forwarded, peer, &[]), "10.0.0.1");
+        assert_eq!(bot_detection::client_ip(&forwarded, peer, &[proxy]), "10.0.0.2");
+        let chain = [proxy, "10.0.0.2".parse().unwrap()];
+        assert_eq!(bot_detection::client_ip(&forwarded, peer, &chain), "203.0.113.7");
+    }
+
+    #[tokio::test]
+    async fn test_bot_detection_report() {
+        let (_pool, _schema, bot_schema) = setup_schema().await;
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("user-agent", axum::http::HeaderValue::from_static("curl/8.0"));
+        let info = bot_detection::score_request(&headers, &DetectionConfig::default(), &Default::default());
+
+        let request = Request::new("{ detectionReport { isBot signals { name score detail } } }").data(info);
+        let response = bot_schema.execute(request).await.data.into_json().unwrap();
+        let report = response["detectionReport"].clone();
+        assert_eq!(report["isBot"], true);
+        let agent = report["signals"].as_array().unwrap().iter().find(|s| s["name"] == "user_agent").unwrap().clone();
+        assert_eq!(agent["detail"], "automation agent 'curl'");
+    }
+
+    #[tokio::test]
+    async fn test_bot_api_routing_and_merged_schema() {
+        use crate::bot_detection::{should_use_bot_api, ApiVariant};
+
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("user-agent", axum::http::HeaderValue::from_static("python-requests/2.31"));
+        let info = bot_detection::score_request(&headers, &DetectionConfig::default(), &Default::default());
+        assert!(should_use_bot_api(&info, None));
+
+        // Bots can opt out of the bot schema
+        headers.insert("x-api-variant", axum::http::HeaderValue::from_static("Human"));
+        assert_eq!(ApiVariant::requested(&headers), Some(ApiVariant::Human));
+        assert!(!should_use_bot_api(&info, ApiVariant::requested(&headers)));
+
+        // The bot schema serves the shared fields alongside the bot-only ones
+        let (_pool, schema, bot_schema) = setup_schema().await;
+        let query = "{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: []) { id } requestExplanation(flightId: 1) { flightId } }";
+        let response = bot_schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let response = schema.execute(Request::new(query)).await;
+        assert!(!response.errors.is_empty());
+
+        // Bots keep booking with a Float flightId, which the human schema never took
+        let book = "mutation($f: Float!) { bookFlight(passengers: [{ firstName: \"Jane\", lastName: \"Doe\" }], payment: \"tok_test_visa\", flightId: $f) { bookingId } }";
+        let variables = |id: f64| async_graphql::Variables::from_json(serde_json::json!({ "f": id }));
+        let response = bot_schema.execute(Request::new(book).variables(variables(1.0))).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let response = bot_schema.execute(Request::new(book).variables(variables(1.5))).await;
+        assert!(!response.errors.is_empty());
+        let response = schema.execute(Request::new(book).variables(variables(1.0))).await;
+        assert!(!response.errors.is_empty());
+    }
+
+    #[test]
+    fn test_config_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [server]
+            listen = "0.0.0.0:9000"
+
+            [cors]
+            allowed_origins = ["https://shop.example.com"]
+
+            [detection]
+            bot_threshold = 0.8
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.listen.port(), 9000);
+        assert_eq!(config.detection.bot_threshold, 0.8);
+        // Unset values keep their defaults
+        assert_eq!(config.detection.default_confidence, DetectionConfig::default().default_confidence);
+        assert_eq!(config.database.url, db::DEFAULT_DATABASE_URL);
+        config.validate().unwrap();
+
+        let info = BotInfo {
+            confidence_score: 0.7,
+            agent_type: "unknown".to_string(),
+            session_id: "test".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            intelligence_level: Default::default(),
+            verified: false,
+            agent_id: None,
+            agent_scopes: Vec::new(),
+            api_key: None,
+            bot_threshold: config.detection.bot_threshold,
+            signals: Vec::new(),
+        };
+        assert!(!info.is_likely_bot());
+
+        assert!(Config::from_toml("[detection]\nbot_treshold = 0.8").is_err());
+        let out_of_range = Config::from_toml("[detection]\nbot_threshold = 1.5").unwrap();
+        assert!(out_of_range.validate().is_err());
+        let bad_origin = Config::from_toml("[cors]\nallowed_origins = [\"shop.example.com\"]").unwrap();
+        assert!(bad_origin.validate().is_err());
+
+        let args = ["--config", "prod.toml"].map(String::from);
+        assert_eq!(config::config_path_from_args(args), Some("prod.toml".into()));
+        assert_eq!(config::config_path_from_args(["--config=dev.toml".to_string()]), Some("dev.toml".into()));
     }
 
     #[tokio::test]
@@ -96,6 +1097,252 @@ mod tests {
         assert!(price > 0.0);
     }
 
+    #[tokio::test]
+    async fn test_addon_pricing() {
+        let (pool, schema, _bot) = setup_schema().await;
+        sqlx::query(
+            r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price, aircraft_code)
+               VALUES ('SFO','SEA','2025-06-02T08:00:00-07:00','2025-06-02T10:00:00-07:00',99.0,'E175');"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let response = schema.execute(Request::new("{ addonCatalog { code unit maxPerPassenger } }")).await;
+        let catalog = response.data.into_json().unwrap()["addonCatalog"].as_array().unwrap().clone();
+        assert_eq!(catalog.len(), 6);
+
+        let query = r#"mutation { buildOffer(flightId: 1, passengers: 2, addonSelections: [
+            { code: "CHECKED_BAG", quantity: 3 },
+            { code: "extra_legroom_seat", quantity: 2 },
+            { code: "TRAVEL_INSURANCE", quantity: 2 }
+        ]) { addons totalPrice lineItems { kind code quantity unitPrice amount } } }"#;
+        let response = schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let offer = response.data.into_json().unwrap()["buildOffer"].clone();
+        let items = offer["lineItems"].as_array().unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0]["kind"], "FARE");
+        assert_eq!(items[0]["amount"].as_f64(), Some(398.0));
+        // Route price for NYC-LAX bags and seats, default price for insurance
+        assert_eq!(items[1]["unitPrice"].as_f64(), Some(45.0));
+        assert_eq!(items[2]["amount"].as_f64(), Some(98.0));
+        assert_eq!(items[3]["amount"].as_f64(), Some(48.0));
+        assert_eq!(offer["totalPrice"].as_f64(), Some(679.0));
+        assert_eq!(offer["addons"].as_array().unwrap().len(), 3);
+
+        // Business fares are 2.5 times the listed economy fare, with a checked bag included
+        let query = r#"mutation { buildOffer(flightId: 1, cabin: BUSINESS, addonSelections: [{ code: "CHECKED_BAG" }]) { totalPrice } }"#;
+        let response = schema.execute(Request::new(query)).await;
+        assert_eq!(response.data.into_json().unwrap()["buildOffer"]["totalPrice"].as_f64(), Some(497.5));
+
+        // Clients of the deprecated `[String]` argument get one of each code
+        let query = r#"mutation { buildOffer(flightId: 1, addons: ["CHECKED_BAG"]) { addons totalPrice } }"#;
+        let response = schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let offer = response.data.into_json().unwrap()["buildOffer"].clone();
+        assert_eq!(offer["addons"], serde_json::json!(["CHECKED_BAG"]));
+        assert_eq!(offer["totalPrice"].as_f64(), Some(244.0));
+
+        let query = "mutation { buildOffer(flightId: 1, passengers: 10) { totalPrice } }";
+        let response = schema.execute(Request::new(query)).await;
+        assert!(response.errors[0].message.contains("between 1 and 9"), "{:?}", response.errors);
+
+        let error_code = |query: &'static str| {
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(Request::new(query)).await;
+                response.errors[0].extensions.as_ref().and_then(|e| e.get("code")).cloned()
+            }
+        };
+        let rejected = [
+            (r#"mutation { buildOffer(flightId: 1, addonSelections: [{ code: "LOUNGE" }]) { totalPrice } }"#, "UNKNOWN_ADDON"),
+            (
+                r#"mutation { buildOffer(flightId: 1, cabin: BUSINESS, addonSelections: [{ code: "MEAL" }]) { totalPrice } }"#,
+                "ADDON_NOT_ELIGIBLE",
+            ),
+            (r#"mutation { buildOffer(flightId: 2, addonSelections: [{ code: "WIFI" }]) { totalPrice } }"#, "ADDON_NOT_ELIGIBLE"),
+            (
+                r#"mutation { buildOffer(flightId: 1, addonSelections: [{ code: "CHECKED_BAG", quantity: 4 }]) { totalPrice } }"#,
+                "INVALID_ADDON_QUANTITY",
+            ),
+        ];
+        for (query, code) in rejected {
+            assert_eq!(error_code(query).await, Some(async_graphql::Value::from(code)), "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_book_persisted_offer() {
+        let (pool, schema, _bot) = setup_schema().await;
+        let build = r#"mutation { buildOffer(flightId: 1, addonSelections: [{ code: "CHECKED_BAG" }, { code: "TRAVEL_INSURANCE" }]) { offerId expiresAt totalPrice } }"#;
+        let offer_id = |response: async_graphql::Response| {
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+            response.data.into_json().unwrap()["buildOffer"]["offerId"].as_str().unwrap().to_string()
+        };
+        let book = |offer_id: &str| {
+            format!(
+                "mutation {{ bookFlight(passengers: [{{ firstName: \"Jane\", lastName: \"Doe\" }}], payment: \"tok_test_visa\", offerId: \"{}\") {{ bookingId offerId totalPrice lineItems {{ code amount }} }} }}",
+                offer_id
+            )
+        };
+        let error_code = |response: async_graphql::Response| {
+            response.errors[0].extensions.as_ref().and_then(|e| e.get("code")).cloned()
+        };
+
+        // The quoted add-ons are booked and charged at the quoted total
+        let offer = offer_id(schema.execute(Request::new(build)).await);
+        let response = schema.execute(Request::new(book(&offer))).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let booking = response.data.into_json().unwrap()["bookFlight"].clone();
+        assert_eq!(booking["offerId"], offer.as_str());
+        assert_eq!(booking["totalPrice"].as_f64(), Some(268.0));
+        assert_eq!(booking["lineItems"].as_array().unwrap().len(), 3);
+        let detail = format!("{{ getBooking(id: {}) {{ lineItems {{ code }} }} }}", booking["bookingId"]);
+        let response = schema.execute(Request::new(detail)).await.data.into_json().unwrap();
+        assert_eq!(response["getBooking"]["lineItems"][1]["code"], "CHECKED_BAG");
+
+        let response = schema.execute(Request::new(book(&offer))).await;
+        assert_eq!(error_code(response), Some(async_graphql::Value::from("OFFER_ALREADY_BOOKED")));
+
+        let offer = offer_id(schema.execute(Request::new(build)).await);
+        sqlx::query("UPDATE flights SET price = 219.0 WHERE id = 1").execute(&pool).await.unwrap();
+        let response = schema.execute(Request::new(book(&offer))).await;
+        assert_eq!(error_code(response), Some(async_graphql::Value::from("PRICE_CHANGED")));
+
+        let offer = offer_id(schema.execute(Request::new(build)).await);
+        sqlx::query("UPDATE offers SET expires_at = datetime('now', '-1 minutes') WHERE id = ?")
+            .bind(&offer)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let response = schema.execute(Request::new(book(&offer))).await;
+        assert_eq!(error_code(response), Some(async_graphql::Value::from("OFFER_EXPIRED")));
+
+        let (bookings,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM bookings").fetch_one(&pool).await.unwrap();
+        assert_eq!(bookings, 1);
+
+        // Unbooked offers are purged a day after expiring; booked ones are kept
+        sqlx::query("UPDATE offers SET expires_at = datetime('now', '-2 days') WHERE id = ? OR booking_id IS NOT NULL")
+            .bind(&offer)
+            .execute(&pool)
+            .await
+            .unwrap();
+        offer_id(schema.execute(Request::new(build)).await);
+        let response = schema.execute(Request::new(book(&offer))).await;
+        assert_eq!(error_code(response), Some(async_graphql::Value::from("OFFER_NOT_FOUND")));
+        let (booked,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM offers WHERE booking_id IS NOT NULL").fetch_one(&pool).await.unwrap();
+        assert_eq!(booked, 1);
+    }
+
+    #[tokio::test]
+    async fn test_negotiation_sessions() {
+        let (pool, schema, bot_schema) = setup_schema().await;
+        let config = NegotiationConfig::default();
+        let rule = |load_factor| config.rules.iter().find(|r| r.matches("NYC", "LAX", load_factor)).map(|r| r.name.as_str());
+        assert_eq!(rule(0.9), Some("high-demand"));
+        assert_eq!(rule(0.7), Some("standard"));
+        let bad_rule = Config::from_toml("[[negotiation.rules]]\nname = \"x\"\nmax_discount_percent = 10\nopening_discount_percent = 20").unwrap();
+        assert!(bad_rule.validate().is_err());
+
+        let run = |query: String| {
+            let bot_schema = bot_schema.clone();
+            async move {
+                let response = bot_schema.execute(Request::new(query)).await;
+                let code = response.errors.first().and_then(|e| e.extensions.as_ref()?.get("code").cloned());
+                (response.data.into_json().unwrap(), code)
+            }
+        };
+
+        // An empty flight falls under the low-demand rule: 5% off to open, 20% at most
+        let (data, _) = run("mutation { negotiateOffer(flightId: 1, passengers: 2) { negotiationId status rule askingPrice roundsLeft } }".to_string()).await;
+        let opened = &data["negotiateOffer"];
+        assert_eq!(opened["rule"], "low-demand");
+        assert_eq!(opened["askingPrice"].as_f64(), Some(189.05));
+        assert_eq!(opened["roundsLeft"], 5);
+        let id = opened["negotiationId"].as_str().unwrap().to_string();
+
+        let counter = |price: f64| {
+            format!(
+                "mutation {{ counterOffer(negotiationId: \"{}\", proposedPrice: {}) {{ status askingPrice agreedPrice totalPrice offerId rounds {{ accepted }} }} }}",
+                id, price
+            )
+        };
+        let (data, _) = run(counter(150.0)).await;
+        let countered = &data["counterOffer"];
+        assert_eq!(countered["status"], "OPEN");
+        let asking = countered["askingPrice"].as_f64().unwrap();
+        assert!(asking < 189.05 && asking > 159.2, "{}", asking);
+
+        let (data, _) = run(counter(170.0)).await;
+        let agreed = &data["counterOffer"];
+        assert_eq!(agreed["status"], "AGREED");
+        assert_eq!(agreed["agreedPrice"].as_f64(), Some(170.0));
+        assert_eq!(agreed["totalPrice"].as_f64(), Some(340.0));
+        assert_eq!(agreed["rounds"].as_array().unwrap().len(), 2);
+        let offer_id = agreed["offerId"].as_str().unwrap().to_string();
+
+        let (_, code) = run(counter(160.0)).await;
+        assert_eq!(code, Some(async_graphql::Value::from("NEGOTIATION_CLOSED")));
+
+        // The negotiated offer books at the agreed fare
+        let book = format!(
+            "mutation {{ bookFlight(passengers: [{{ firstName: \"Jane\", lastName: \"Doe\" }}, {{ firstName: \"John\", lastName: \"Doe\" }}], payment: \"tok_test_visa\", offerId: \"{}\") {{ totalPrice }} }}",
+            offer_id
+        );
+        let response = schema.execute(Request::new(book)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(response.data.into_json().unwrap()["bookFlight"]["totalPrice"].as_f64(), Some(340.0));
+
+        // Negotiations expire, and those opened by an agent are private to it
+        let mut info = bot_detection::score_request(&axum::http::HeaderMap::new(), &DetectionConfig::default(), &Default::default());
+        info.verified = true;
+        info.agent_id = Some("agent-a".to_string());
+        info.agent_scopes = AGENT_SCOPES.to_vec();
+        let open = "mutation { negotiateOffer(flightId: 1) { negotiationId } }";
+        let response = bot_schema.execute(Request::new(open).data(info.clone())).await;
+        let id = response.data.into_json().unwrap()["negotiateOffer"]["negotiationId"].as_str().unwrap().to_string();
+        let query = format!("{{ negotiation(negotiationId: \"{}\") {{ status }} }}", id);
+        let response = bot_schema.execute(Request::new(query.clone()).data(info.clone())).await;
+        assert_eq!(response.data.into_json().unwrap()["negotiation"]["status"], "OPEN");
+        info.agent_id = Some("agent-b".to_string());
+        let response = bot_schema.execute(Request::new(query).data(info.clone())).await;
+        let code = response.errors[0].extensions.as_ref().unwrap().get("code");
+        assert_eq!(code, Some(&async_graphql::Value::from("NEGOTIATION_NOT_FOUND")));
+
+        // Those opened without an agent belong to the session, and are agreed only once
+        let (data, _) = run("mutation { negotiateOffer(flightId: 1) { negotiationId } }".to_string()).await;
+        let id = data["negotiateOffer"]["negotiationId"].as_str().unwrap().to_string();
+        let accept = format!("mutation {{ acceptNegotiation(negotiationId: \"{}\") {{ status }} }}", id);
+        info.agent_id = None;
+        info.session_id = "other-session".to_string();
+        let response = bot_schema.execute(Request::new(accept.clone()).data(info)).await;
+        let code = response.errors[0].extensions.as_ref().unwrap().get("code");
+        assert_eq!(code, Some(&async_graphql::Value::from("NEGOTIATION_NOT_FOUND")));
+        let (data, _) = run(accept.clone()).await;
+        assert_eq!(data["acceptNegotiation"]["status"], "AGREED");
+        let (_, code) = run(accept).await;
+        assert_eq!(code, Some(async_graphql::Value::from("NEGOTIATION_CLOSED")));
+        let (offers,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM offers WHERE negotiation_id = ?")
+            .bind(&id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(offers, 1);
+
+        let (data, _) = run("mutation { negotiateOffer(flightId: 1) { negotiationId } }".to_string()).await;
+        let id = data["negotiateOffer"]["negotiationId"].as_str().unwrap().to_string();
+        sqlx::query("UPDATE negotiations SET expires_at = datetime('now', '-1 minutes') WHERE id = ?")
+            .bind(&id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let accept = format!("mutation {{ acceptNegotiation(negotiationId: \"{}\") {{ status }} }}", id);
+        let (_, code) = run(accept).await;
+        assert_eq!(code, Some(async_graphql::Value::from("NEGOTIATION_EXPIRED")));
+    }
+
     #[tokio::test]
     async fn test_bot_search_flights() {
         let (_pool, _schema, bot_schema) = setup_schema().await;
@@ -107,12 +1354,220 @@ mod tests {
 
     #[tokio::test]
     async fn test_request_explanation() {
-        let (_pool, _schema, bot_schema) = setup_schema().await;
-        let query = "{ requestExplanation(flightId: 1) { flightId baseFare } }";
+        let (pool, _schema, bot_schema) = setup_schema().await;
+        sqlx::query(
+            r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price)
+               VALUES ('NYC','LAX','2025-06-01T18:00:00-04:00','2025-06-01T21:00:00-07:00',249.0);"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let query = "{ requestExplanation(flightId: 2) { flightId fareFamily baseFare taxesFees comparativeValue cancellationPolicy taxComponents { code amount rule } structuredExplanation sources { field rules } } }";
         let request = Request::new(query);
         let response = bot_schema.execute(request).await.data;
         let explanation = response.into_json().unwrap()["requestExplanation"].clone();
         let base_fare = explanation["baseFare"].as_f64().unwrap();
-        assert!(base_fare > 0.0);
+        let taxes = explanation["taxesFees"].as_f64().unwrap();
+        assert!((base_fare + taxes - 249.0).abs() < 1e-9, "{} + {}", base_fare, taxes);
+
+        // Taxes come from the tax rules of the route: 7.5% of the base fare plus fixed fees
+        let components = explanation["taxComponents"].as_array().unwrap();
+        assert_eq!(components.len(), 4);
+        let transport = components.iter().find(|t| t["rule"] == "tax:US-TRANSPORTATION").unwrap();
+        assert_eq!(transport["amount"].as_f64(), Some(((249.0 - 15.1) / 1.075 * 0.075 * 100.0_f64).round() / 100.0));
+        assert_eq!(explanation["fareFamily"], "Economy Standard");
+        assert_eq!(explanation["cancellationPolicy"], "70% refund up to 24 hours before departure; no refund after that");
+        assert_eq!(explanation["comparativeValue"].as_f64(), Some(0.8));
+        assert_eq!(explanation["structuredExplanation"]["change_fee"].as_f64(), Some(75.0));
+
+        let sources = explanation["sources"].as_array().unwrap();
+        let rules_of = |field: &str| sources.iter().find(|s| s["field"] == field).map(|s| s["rules"].clone());
+        assert_eq!(rules_of("cancellationPolicy"), Some(serde_json::json!(["refund:ECONOMY_STANDARD"])));
+        assert_eq!(
+            rules_of("comparativeValue"),
+            Some(serde_json::json!(["route_fares:NYC-LAX", "fare_family:ECONOMY_STANDARD"]))
+        );
+        assert_eq!(rules_of("structuredExplanation.baggage_allowance"), Some(serde_json::json!(["baggage:ECONOMY"])));
+        assert_eq!(rules_of("taxesFees").unwrap().as_array().unwrap().len(), 4);
+
+        // A business seat is priced at the listed fare times the cabin's multiplier
+        let query = "{ requestExplanation(flightId: 2, seat: \"1A\") { fareFamily baseFare taxesFees comparativeValue sources { field rules } } }";
+        let response = bot_schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let explanation = response.data.into_json().unwrap()["requestExplanation"].clone();
+        assert_eq!(explanation["fareFamily"], "Business Flex");
+        let base_fare = explanation["baseFare"].as_f64().unwrap();
+        let taxes = explanation["taxesFees"].as_f64().unwrap();
+        assert!((base_fare + taxes - 622.5).abs() < 1e-9, "{} + {}", base_fare, taxes);
+        // Every fare on the route gets the same multiplier, so the comparison holds
+        assert_eq!(explanation["comparativeValue"].as_f64(), Some(0.8));
+        let sources = explanation["sources"].as_array().unwrap();
+        let base_fare_rules = sources.iter().find(|s| s["field"] == "baseFare").unwrap()["rules"].clone();
+        assert_eq!(base_fare_rules[0], "fare_family:BUSINESS_FLEX");
+
+        // A fare below the fixed taxes cannot be split into base fare and taxes
+        sqlx::query("UPDATE flights SET price = 10.0 WHERE id = 2").execute(&pool).await.unwrap();
+        let response = bot_schema.execute(Request::new("{ requestExplanation(flightId: 2) { baseFare } }")).await;
+        assert_eq!(response.errors[0].message, "Fare 10.00 is below the 15.10 of fixed taxes on its route");
+    }
+
+    #[tokio::test]
+    async fn test_offer_insights_from_price_history() {
+        let (pool, schema, bot_schema) = setup_schema().await;
+        sqlx::query(
+            r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price) VALUES
+               ('NYC','LAX','2025-06-02T10:30:00-04:00','2025-06-02T13:30:00-07:00',229.0),
+               ('NYC','LAX','2025-06-20T08:00:00-04:00','2025-06-20T11:00:00-07:00',99.0),
+               ('NYC','SFO','2025-06-01T09:00:00-04:00','2025-06-01T12:30:00-07:00',149.0);"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query("UPDATE flights SET price = 179.0 WHERE id = 1").execute(&pool).await.unwrap();
+
+        // A search only snapshots flights whose price was not recorded in the last hour
+        sqlx::query("UPDATE price_history SET recorded_time = datetime('now', '-2 days') WHERE flight_id = 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let response = schema
+            .execute(Request::new("{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: [\"2025-06-01..2025-06-02\"]) { id } }"))
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        // Searched prices are written in the background
+        for _ in 0..100 {
+            let (searched,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM price_history WHERE source = 'search'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            if searched > 0 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        let sources: Vec<(i64, String)> = sqlx::query_as("SELECT flight_id, source FROM price_history WHERE flight_id IN (1, 2) ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let expected = [(1, "listed"), (2, "listed"), (1, "change"), (2, "search")];
+        assert_eq!(sources, expected.map(|(id, source)| (id, source.to_string())));
+
+        let query = "{ offerInsights(flightId: 1) { priceComparison { averagePrice percentile trend trendPercent sampleSize priceHistory { price source } } \
+                     alternatives { flightId price priceDifference timeDifferenceMinutes } structuredData } }";
+        let response = bot_schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let insights = response.data.into_json().unwrap()["offerInsights"].clone();
+        let comparison = &insights["priceComparison"];
+        // Route prices in the window, the last of each flight and day: 179 for
+        // flight 1, 229 two days ago and 229 today for flight 2
+        assert_eq!(comparison["sampleSize"], 3);
+        assert_eq!(comparison["averagePrice"].as_f64(), Some(212.33));
+        assert_eq!(comparison["percentile"].as_f64(), Some(17.0));
+        assert_eq!(comparison["trend"], "FALLING");
+        assert_eq!(comparison["trendPercent"].as_f64(), Some(-10.1));
+        assert_eq!(comparison["priceHistory"], serde_json::json!([{ "price": 199.0, "source": "listed" }, { "price": 179.0, "source": "change" }]));
+
+        // Only the same route within the date window, with real deltas
+        let alternatives = insights["alternatives"].as_array().unwrap();
+        assert_eq!(alternatives.len(), 1);
+        assert_eq!(alternatives[0]["flightId"], 2);
+        assert_eq!(alternatives[0]["priceDifference"].as_f64(), Some(50.0));
+        assert_eq!(alternatives[0]["timeDifferenceMinutes"], 1590);
+        assert_eq!(insights["structuredData"]["alternative_flights"][0]["flight_id"], 2);
+
+        let response = bot_schema.execute(Request::new("{ offerInsights(flightId: 99) { flightId } }")).await;
+        assert_eq!(response.errors[0].message, "Flight 99 not found");
+    }
+
+    #[tokio::test]
+    async fn test_flight_schedule_across_time_zones() {
+        let (pool, schema, bot_schema) = setup_schema().await;
+        let query = "{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: [\"2025-06-01\"]) { departureTime durationMinutes distanceMiles originAirport { timeZone } destinationAirport { name timeZone } } }";
+        let response = schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let flight = response.data.into_json().unwrap()["searchFlights"][0].clone();
+        assert_eq!(flight["departureTime"], "2025-06-01T08:00:00-04:00");
+        // 08:00 EDT to 11:00 PDT
+        assert_eq!(flight["durationMinutes"], 360);
+        assert_eq!(flight["distanceMiles"], 2470);
+        assert_eq!(flight["originAirport"]["timeZone"], "America/New_York");
+        assert_eq!(flight["destinationAirport"]["name"], "Los Angeles International");
+
+        // Times must carry an offset; those stored before that was enforced
+        // are read as local times at their airports when migrating
+        let unzoned = r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price)
+                         VALUES ('SEA','NYC','2025-12-01T23:30:00','2025-12-02T07:45:00',189.0);"#;
+        let err = sqlx::query("UPDATE flights SET departure_time = '2025-06-01T08:00:00' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Flight times need a UTC offset"), "{}", err);
+        sqlx::query("DROP TRIGGER flights_time_offset_insert").execute(&pool).await.unwrap();
+        sqlx::query(unzoned).execute(&pool).await.unwrap();
+        // Times at an airport missing from the reference table are read as UTC
+        sqlx::query(
+            r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price)
+               VALUES ('SEA','XXX','2025-12-01T10:00:00','2025-12-01T12:00:00',99.0);"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        // The schema is already current, and migrating still localizes them
+        db::migrate(&pool).await.unwrap();
+        let times: Vec<(String, String)> = sqlx::query_as("SELECT departure_time, arrival_time FROM flights WHERE id IN (2, 3) ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let expected = [
+            ("2025-12-01T23:30:00-08:00", "2025-12-02T07:45:00-05:00"),
+            ("2025-12-01T10:00:00-08:00", "2025-12-01T12:00:00+00:00"),
+        ];
+        assert_eq!(times, expected.map(|(departure, arrival)| (departure.to_string(), arrival.to_string())));
+        assert_eq!(airports::localize_flight_times(&pool).await.unwrap(), 0);
+        sqlx::query("DELETE FROM flights WHERE id = 3").execute(&pool).await.unwrap();
+
+        // A time that cannot be read fails the migration and leaves every row as it was
+        sqlx::query(
+            r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price) VALUES
+               ('SEA','NYC','2025-12-03T10:00:00','2025-12-03T18:00:00',99.0),
+               ('NYC','LAX','2025-03-09T02:30:00','2025-03-09T05:00:00',99.0);"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let err = airports::localize_flight_times(&pool).await.unwrap_err();
+        assert!(err.to_string().contains("2025-03-09T02:30:00 does not exist in America/New_York"), "{}", err);
+        let (unzoned,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM flights WHERE length(departure_time) = 19")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(unzoned, 2);
+        sqlx::query("DELETE FROM flights WHERE length(departure_time) = 19").execute(&pool).await.unwrap();
+
+        let query = "{ searchFlights(origin: \"SEA\", destination: \"NYC\", dates: [\"2025-12-01\"]) { durationMinutes } }";
+        let response = bot_schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(response.data.into_json().unwrap()["searchFlights"][0]["durationMinutes"], 315);
+
+        // Flights sort by instant: 01:30 EDT departs before 01:15 EST when clocks go back
+        sqlx::query(
+            r#"INSERT INTO flights (origin, destination, departure_time, arrival_time, price) VALUES
+               ('NYC','LAX','2025-11-02T01:15:00-05:00','2025-11-02T04:15:00-08:00',199.0),
+               ('NYC','LAX','2025-11-02T01:30:00-04:00','2025-11-02T03:30:00-08:00',199.0),
+               ('NYC','XYZ','2025-11-02T09:00:00-05:00','2025-11-02T11:00:00-05:00',99.0);"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let query = "{ searchFlights(origin: \"NYC\", destination: \"LAX\", dates: [\"2025-11-02\"]) { id } }";
+        let response = schema.execute(Request::new(query)).await;
+        assert_eq!(response.data.into_json().unwrap()["searchFlights"], serde_json::json!([{ "id": 7 }, { "id": 6 }]));
+
+        // Airports missing from the reference table leave their fields null
+        let query = "{ searchFlights(origin: \"NYC\", destination: \"XYZ\", dates: []) { distanceMiles originAirport { code } destinationAirport { code } } }";
+        let response = schema.execute(Request::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let flight = response.data.into_json().unwrap()["searchFlights"][0].clone();
+        assert_eq!(flight, serde_json::json!({ "distanceMiles": null, "originAirport": { "code": "NYC" }, "destinationAirport": null }));
     }
 }
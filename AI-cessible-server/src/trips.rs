@@ -0,0 +1,119 @@
+use chrono::Duration;
+use sqlx::SqlitePool;
+
+use crate::itinerary::{parse_time, MIN_CONNECTION_MINUTES};
+use crate::schema::{FlightOffer, TripOption, TripSliceInput, TripType};
+use crate::search;
+
+/// Longest multi-city journey accepted by searchTrips
+pub const MAX_SLICES: usize = 6;
+/// Cap on the number of priced combinations returned
+pub const MAX_TRIP_OPTIONS: usize = 50;
+/// Partial combinations kept between slices, cheapest first
+const MAX_PARTIAL_COMBINATIONS: usize = 500;
+
+const TRIP_ID_PREFIX: &str = "TRIP-";
+
+/// Encode the flights of a trip, in slice order, as a single offer id
+pub fn trip_id(flights: &[FlightOffer]) -> String {
+    let ids: Vec<String> = flights.iter().map(|f| f.id.to_string()).collect();
+    format!("{}{}", TRIP_ID_PREFIX, ids.join("-"))
+}
+
+/// Decode a trip id back into its flight ids
+pub fn parse_trip_id(trip_id: &str) -> async_graphql::Result<Vec<i64>> {
+    let invalid = || async_graphql::Error::new(format!("Invalid trip id '{}'", trip_id));
+    let ids = trip_id.strip_prefix(TRIP_ID_PREFIX).ok_or_else(invalid)?;
+    let ids = ids
+        .split('-')
+        .map(|id| id.parse::<i64>().map_err(|_| invalid()))
+        .collect::<async_graphql::Result<Vec<i64>>>()?;
+    if ids.is_empty() || ids.len() > MAX_SLICES {
+        return Err(invalid());
+    }
+    Ok(ids)
+}
+
+/// Classify a trip from the shape of its slices
+pub fn trip_type(slices: &[(&str, &str)]) -> TripType {
+    match slices {
+        [_] => TripType::OneWay,
+        [(o1, d1), (o2, d2)] if o1 == d2 && d1 == o2 => TripType::RoundTrip,
+        [(o1, d1), (o2, d2)] if o1 == d2 || d1 == o2 => TripType::OpenJaw,
+        _ => TripType::MultiCity,
+    }
+}
+
+/// Check that each flight departs after the previous one has landed
+pub fn validate_sequence(flights: &[FlightOffer]) -> async_graphql::Result<()> {
+    for pair in flights.windows(2) {
+        let arrives = parse_time(&pair[0].arrival_time)?;
+        let departs = parse_time(&pair[1].departure_time)?;
+        if departs - arrives < Duration::minutes(MIN_CONNECTION_MINUTES) {
+            return Err(async_graphql::Error::new(format!(
+                "Flight {} departs before flight {} can be connected",
+                pair[1].id, pair[0].id
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Price every valid combination of flights across the requested slices
+pub async fn find_trips(
+    pool: &SqlitePool,
+    slices: &[TripSliceInput],
+) -> async_graphql::Result<Vec<TripOption>> {
+    if slices.is_empty() || slices.len() > MAX_SLICES {
+        return Err(async_graphql::Error::new(format!(
+            "A trip needs between 1 and {} slices",
+            MAX_SLICES
+        )));
+    }
+
+    let mut candidates = Vec::with_capacity(slices.len());
+    for slice in slices {
+        let dates = vec![slice.date.clone()];
+        candidates.push(search::find_flights(pool, &slice.origin, &slice.destination, &dates).await?);
+    }
+
+    let shape: Vec<(&str, &str)> = slices
+        .iter()
+        .map(|s| (s.origin.as_str(), s.destination.as_str()))
+        .collect();
+    let kind = trip_type(&shape);
+
+    let mut combinations: Vec<Vec<FlightOffer>> = vec![Vec::new()];
+    for options in &candidates {
+        let mut next = Vec::new();
+        for partial in &combinations {
+            for flight in options {
+                let mut combination = partial.clone();
+                combination.push(flight.clone());
+                if validate_sequence(&combination[combination.len().saturating_sub(2)..]).is_ok() {
+                    next.push(combination);
+                }
+            }
+        }
+        next.sort_by(|a, b| total(a).total_cmp(&total(b)));
+        next.truncate(MAX_PARTIAL_COMBINATIONS);
+        combinations = next;
+    }
+
+    let mut trips: Vec<TripOption> = combinations
+        .into_iter()
+        .map(|flights| TripOption {
+            trip_id: trip_id(&flights),
+            trip_type: kind,
+            total_price: total(&flights),
+            flights,
+        })
+        .collect();
+    trips.sort_by(|a, b| a.total_price.total_cmp(&b.total_price));
+    trips.truncate(MAX_TRIP_OPTIONS);
+    Ok(trips)
+}
+
+fn total(flights: &[FlightOffer]) -> f64 {
+    flights.iter().map(|f| f.price).sum()
+}
@@ -45,8 +45,8 @@ const authLink = setContext((_, { headers }) => {
 const client = new ApolloClient({
   link: authLink.concat(
     ApolloLink.split(
-      // Test function to determine which link to use
-      () => botDetector.isLikelyBot(),
+      // Test function to determine which link to use; an operation may pin it through its context
+      (operation) => operation.getContext().botApi ?? botDetector.isLikelyBot(),
       // True: use bot link
       botHttpLink,
       // False: use regular link
@@ -69,18 +69,39 @@ const SEARCH_FLIGHTS = gql`
   }
 `;
 
+const TOKENIZE_PAYMENT = gql`
+  mutation tokenizePayment($card: CardInput!) {
+    tokenizePayment(card: $card) {
+      token
+    }
+  }
+`;
+
+const BOOKING_FIELDS = `
+  bookingId
+  flight {
+    id
+    origin
+    destination
+    departureTime
+    arrivalTime
+    price
+  }
+`;
+
 const BOOK_FLIGHT = gql`
-  mutation bookFlight($passengerDetails: String!, $payment: String!, $flightId: Float!) {
-    bookFlight(passengerDetails: $passengerDetails, payment: $payment, flightId: $flightId) {
-      bookingId
-      flight {
-        id
-        origin
-        destination
-        departureTime
-        arrivalTime
-        price
-      }
+  mutation bookFlight($passengers: [PassengerInput!]!, $payment: String!, $flightId: Int!) {
+    bookFlight(passengers: $passengers, payment: $payment, flightId: $flightId) {
+      ${BOOKING_FIELDS}
+    }
+  }
+`;
+
+// The bot API's bookFlight still takes a Float flightId
+const BOT_BOOK_FLIGHT = gql`
+  mutation bookFlight($passengers: [PassengerInput!]!, $payment: String!, $flightId: Float!) {
+    bookFlight(passengers: $passengers, payment: $payment, flightId: $flightId) {
+      ${BOOKING_FIELDS}
     }
   }
 `;
@@ -170,11 +191,31 @@ function FlightResults({ flights, onSelectFlight }) {
 
 function BookingPage({ flight, onBookingComplete }) {
   const [passenger, setPassenger] = useState('');
-  const [payment, setPayment] = useState('');
-  const [bookFlight, { data, loading, error }] = useMutation(BOOK_FLIGHT, {
-    variables: { passengerDetails: passenger, payment, flightId: flight.id },
+  const [email, setEmail] = useState('');
+  const [cardNumber, setCardNumber] = useState('');
+  const [expiry, setExpiry] = useState('');
+  // Split the full name into the structured passenger the API expects
+  const [firstName, ...lastNames] = passenger.trim().split(/\s+/);
+  const passengers = [{
+    firstName: firstName || '',
+    lastName: lastNames.join(' '),
+    ...(email ? { contact: { email } } : {}),
+  }];
+  const [tokenizePayment, { loading: tokenizing, error: cardError }] = useMutation(TOKENIZE_PAYMENT);
+  const botApi = botDetector.isLikelyBot();
+  const [bookFlight, { data, loading, error }] = useMutation(botApi ? BOT_BOOK_FLIGHT : BOOK_FLIGHT, {
     onCompleted: (data) => onBookingComplete(data.bookFlight),
   });
+  // Card details are exchanged for a token first; bookFlight only accepts tokens
+  const book = async () => {
+    const [month, year] = expiry.split('/').map((part) => parseInt(part, 10));
+    const card = { number: cardNumber, expiryMonth: month || 0, expiryYear: year < 100 ? 2000 + year : year || 0 };
+    const result = await tokenizePayment({ variables: { card } }).catch(() => null);
+    const payment = result?.data?.tokenizePayment?.token;
+    if (payment) {
+      bookFlight({ variables: { passengers, payment, flightId: flight.id }, context: { botApi } });
+    }
+  };
 
   if (data) {
     return (
@@ -212,22 +253,35 @@ function BookingPage({ flight, onBookingComplete }) {
           value={passenger}
           onChange={(e) => setPassenger(e.target.value)}
         />
+        <input
+          id="email"
+          placeholder="Email (optional)"
+          value={email}
+          onChange={(e) => setEmail(e.target.value)}
+        />
       </div>
       <div>
         <label htmlFor="payment">Payment Details</label>
         <input
           id="payment"
           placeholder="Card Number"
-          value={payment}
-          onChange={(e) => setPayment(e.target.value)}
+          value={cardNumber}
+          onChange={(e) => setCardNumber(e.target.value)}
+        />
+        <input
+          id="expiry"
+          placeholder="Expiry (MM/YY)"
+          value={expiry}
+          onChange={(e) => setExpiry(e.target.value)}
         />
       </div>
       <button 
         className="btn btn-full btn-md-auto"
-        onClick={() => bookFlight()}>
+        onClick={book}>
         Book Flight
       </button>
-      {loading && <p>Processing booking...</p>}
+      {(tokenizing || loading) && <p>Processing booking...</p>}
+      {cardError && <p className="text-red">Error: {cardError.message}</p>}
       {error && <p className="text-red">Error: {error.message}</p>}
     </div>
   );
@@ -773,7 +773,7 @@ class BotDetector {
           'Content-Type': 'application/json'
         },
         body: JSON.stringify(reportData),
-        // Don't block UI for this
+        // The session cookie the server issued decides which session the report belongs to
         credentials: 'same-origin'
       });
       
@@ -3,6 +3,8 @@ const { chromium } = require('playwright');
 
 const BASE_URL = process.env.AI_CESSIBLE_URL || 'http://localhost:8000';
 const PORT = process.env.PORT || 3100;
+// Scoped key for bot-only fields such as requestExplanation (`explain` scope)
+const API_KEY = process.env.BOT_SHOP_API_KEY;
 
 async function createBrowser() {
   const browser = await chromium.launch({ headless: true });
@@ -18,33 +20,37 @@ async function main() {
   app.use(express.json());
 
   async function runGraphQL(query, variables) {
-    return page.evaluate(async ({ q, v }) => {
+    return page.evaluate(async ({ q, v, key }) => {
+      const headers = { 'Content-Type': 'application/json' };
+      if (key) {
+        headers['Authorization'] = `Bearer ${key}`;
+      }
       const resp = await fetch('/bot/graphql', {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
+        headers,
         body: JSON.stringify({ query: q, variables: v })
       });
       return await resp.json();
-    }, { q: query, v: variables });
+    }, { q: query, v: variables, key: API_KEY });
   }
 
   app.post('/search', async (req, res) => {
     const { origin, destination, dates } = req.body;
-    const query = `query($o:String!,$d:String!,$dates:[String!]!){searchFlights(origin:$o,destination:$d,dates:$dates){id origin destination departureTime arrivalTime price}}`;
+    const query = `query($o:String!,$d:String!,$dates:[String!]!){searchFlights(origin:$o,destination:$d,dates:$dates){id origin destination departureTime arrivalTime durationMinutes distanceMiles price}}`;
     const data = await runGraphQL(query, { o: origin, d: destination, dates });
     res.json(data);
   });
 
   app.post('/book', async (req, res) => {
-    const { passengerDetails, payment, flightId } = req.body;
-    const mutation = `mutation($p:String!,$pay:String!,$f:Float!){bookFlight(passengerDetails:$p,payment:$pay,flightId:$f){bookingId flight{ id origin destination departureTime arrivalTime price }}}`;
-    const data = await runGraphQL(mutation, { p: passengerDetails, pay: payment, f: flightId });
+    const { passengers, payment, flightId } = req.body;
+    const mutation = `mutation($p:[PassengerInput!]!,$pay:String!,$f:Float!){bookFlight(passengers:$p,payment:$pay,flightId:$f){bookingId flight{ id origin destination departureTime arrivalTime price }}}`;
+    const data = await runGraphQL(mutation, { p: passengers, pay: payment, f: flightId });
     res.json(data);
   });
 
   app.post('/requestExplanation', async (req, res) => {
     const { flightId } = req.body;
-    const query = `query($id:Int!){requestExplanation(flightId:$id){flightId baseFare taxesFees comparativeValue cancellationPolicy seatDetails{pitchInches widthInches reclineDegrees hasPower hasWifi} structuredExplanation}}`;
+    const query = `query($id:Int!){requestExplanation(flightId:$id){flightId fareFamily baseFare taxesFees taxComponents{code name amount rule} comparativeValue cancellationPolicy seatDetails{pitchInches widthInches reclineDegrees hasPower hasWifi} structuredExplanation sources{field rules detail}}}`;
     const data = await runGraphQL(query, { id: flightId });
     res.json(data);
   });
@@ -118,7 +118,8 @@ async function runBot() {
           
           // Fill payment
           if (await page.$('input[placeholder="Card Number"]')) {
-            await page.fill('input[placeholder="Card Number"]', '1234567812345678');
+            await page.fill('input[placeholder="Card Number"]', '4242424242424242');
+            await page.fill('input[placeholder="Expiry (MM/YY)"]', '12/30');
             await sleep(BOT_DELAY);
             
             // Book flight
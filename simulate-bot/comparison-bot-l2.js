@@ -141,10 +141,10 @@ async function runComparisonBot() {
 
   // Submit intent for analytics
   const intent = {
-    intent_type: 'comparison_search',
-    query_params: { routes: ROUTES, dates: TRAVEL_DATES },
+    intentType: 'comparison_search',
+    queryParams: { routes: ROUTES, dates: TRAVEL_DATES },
     reason: 'price_comparison',
-    additional_context: null
+    additionalContext: null
   };
 
   await page.evaluate(async (payload) => {
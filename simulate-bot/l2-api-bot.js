@@ -1,4 +1,5 @@
 const { chromium } = require('playwright');
+const { registerAgent, signedFetch, issueApiKey } = require('./signing');
 
 const BASE_URL = 'http://localhost:8000';
 
@@ -28,13 +29,23 @@ async function runL2Bot() {
 
   if (apiEndpoint) {
     const query = `query {\n  searchFlights(origin: \"NYC\", destination: \"LAX\", dates: [\"2025-06-01\"]) {\n    id\n    origin\n    destination\n    price\n  }\n}`;
-    const resp = await fetch(BASE_URL + apiEndpoint, {
+    // Sign API calls so the server can verify this agent's identity
+    const agent = await registerAgent(BASE_URL, 'L2BotDemo');
+    console.log('Registered as verified agent:', agent.agentId);
+    const resp = await signedFetch(agent, BASE_URL + apiEndpoint, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify({ query })
     });
     const data = await resp.json();
     console.log('API response:', JSON.stringify(data, null, 2));
+
+    // A read-only key is enough to review this agent's own intents
+    const key = await issueApiKey(agent, BASE_URL, ['READ_INTENTS'], 'intent reader');
+    const intents = await fetch(BASE_URL + '/bot/intent', {
+      headers: { 'Authorization': `Bearer ${key.apiKey}` }
+    });
+    console.log('Own intents:', JSON.stringify(await intents.json(), null, 2));
   } else {
     console.log('No bot API hint found.');
   }
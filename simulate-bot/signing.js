@@ -0,0 +1,87 @@
+/**
+ * Helpers for verified agents: register an Ed25519 key with the server and
+ * sign requests with HTTP message signatures over method, path, date and body digest.
+ * Every signature carries a fresh nonce, since the server rejects replays.
+ */
+const crypto = require('crypto');
+
+/**
+ * Generate a key pair and register its public key
+ * @param {string} baseUrl - Server base URL
+ * @param {string} name - Agent name
+ * @return {Promise<{agentId: string, privateKey: crypto.KeyObject}>}
+ */
+async function registerAgent(baseUrl, name) {
+  const { publicKey, privateKey } = crypto.generateKeyPairSync('ed25519');
+  const rawKey = Buffer.from(publicKey.export({ format: 'jwk' }).x, 'base64url').toString('base64');
+  const resp = await fetch(baseUrl + '/bot/agents', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ name, publicKey: rawKey })
+  });
+  const data = await resp.json();
+  if (!resp.ok) {
+    throw new Error(`Agent registration failed: ${data.error}`);
+  }
+  return { agentId: data.agentId, privateKey };
+}
+
+/**
+ * fetch() with a signature the server verifies against the registered key
+ * @param {{agentId: string, privateKey: crypto.KeyObject}} agent - Registered agent
+ * @param {string} url - Absolute request URL
+ * @param {object} options - fetch options; `body` must be a string
+ * @return {Promise<Response>}
+ */
+function signedFetch(agent, url, options = {}) {
+  const method = (options.method || 'GET').toUpperCase();
+  const body = options.body || '';
+  const date = new Date().toUTCString();
+  const digest = `sha-256=:${crypto.createHash('sha256').update(body).digest('base64')}:`;
+  const nonce = crypto.randomUUID();
+  const params = `("@method" "@path" "date" "content-digest");keyid="${agent.agentId}";nonce="${nonce}";alg="ed25519"`;
+  const base = [
+    `"@method": ${method}`,
+    `"@path": ${new URL(url).pathname}`,
+    `"date": ${date}`,
+    `"content-digest": ${digest}`,
+    `"@signature-params": ${params}`
+  ].join('\n');
+  const signature = crypto.sign(null, Buffer.from(base), agent.privateKey).toString('base64');
+
+  return fetch(url, {
+    ...options,
+    method,
+    headers: {
+      ...options.headers,
+      'Date': date,
+      'Content-Digest': digest,
+      'Signature-Input': `sig1=${params}`,
+      'Signature': `sig1=:${signature}:`
+    }
+  });
+}
+
+/**
+ * Issue a scoped API key for the agent; the key is only returned once
+ * @param {{agentId: string, privateKey: crypto.KeyObject}} agent - Registered agent
+ * @param {string} baseUrl - Server base URL
+ * @param {string[]} scopes - ApiScope values, e.g. ['SEARCH', 'READ_INTENTS']
+ * @param {string} [label] - Note stored with the key
+ * @return {Promise<{keyId: string, apiKey: string, scopes: string[]}>}
+ */
+async function issueApiKey(agent, baseUrl, scopes, label) {
+  const query = 'mutation($scopes: [ApiScope!]!, $label: String) { issueApiKey(scopes: $scopes, label: $label) { keyId apiKey scopes } }';
+  const resp = await signedFetch(agent, baseUrl + '/bot/graphql', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ query, variables: { scopes, label } })
+  });
+  const { data, errors } = await resp.json();
+  if (errors) {
+    throw new Error(`API key issuance failed: ${errors[0].message}`);
+  }
+  return data.issueApiKey;
+}
+
+module.exports = { registerAgent, signedFetch, issueApiKey };